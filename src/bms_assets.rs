@@ -0,0 +1,190 @@
+extern crate music;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use time;
+
+use image::{ImageBuffer, RgbaImage};
+use ffmpeg::{self, format, frame, media};
+
+use bms_loader::{self, Bms, Image, LoadReport, MissingAsset, Sample, SoundX};
+use sample_info;
+
+// where the sounds of a chart go to be played
+pub trait SoundBackend {
+    fn bind(&self, wav_id: SoundX, path: &Path) -> Result<(), String>;
+}
+
+// what turns a #BMP file into pictures
+pub trait ImageBackend {
+    // each frame with when it shows from the start of the file, a still image is one frame at 0
+    fn decode(&self, path: &Path) -> Result<Vec<(f64, RgbaImage)>, String>;
+}
+
+// the mixer piston-music opens, only while music::start is running
+pub struct MixerSounds;
+
+impl SoundBackend for MixerSounds {
+    fn bind(&self, wav_id: SoundX, path: &Path) -> Result<(), String> {
+        let path = path.to_str().ok_or("the path is not UTF-8".to_string())?;
+        music::bind_sound_file(wav_id, path);
+        Ok(())
+    }
+}
+
+// the files are still looked for, but nothing can play them
+pub struct NoSounds;
+
+impl SoundBackend for NoSounds {
+    fn bind(&self, _wav_id: SoundX, _path: &Path) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// stills and videos alike
+pub struct FfmpegImages;
+
+impl ImageBackend for FfmpegImages {
+    fn decode(&self, path: &Path) -> Result<Vec<(f64, RgbaImage)>, String> {
+        ffmpeg::init().map_err(|e| e.to_string())?;
+        let mut context = ffmpeg::format::input(&path.to_path_buf()).map_err(|e| e.to_string())?;
+        let (mut decoder, image_index) = {
+            let input = context.streams().best(media::Type::Video).ok_or("no picture in the file".to_string())?;
+            (input.codec().decoder().video().map_err(|e| e.to_string())?, input.index())
+        };
+
+        let mut frames = vec![];
+        let mut decoded = frame::Video::empty();
+        let mut converted = frame::Video::empty();
+        for (stream, packet) in context.packets() {
+            if stream.index() == image_index {
+                if let Ok(true) = decoder.decode(&packet, &mut decoded) {
+                    let mut ctx = ffmpeg::software::scaling::Context::get(
+                        decoded.format(),
+                        decoded.width(),
+                        decoded.height(),
+                        format::Pixel::RGBA,
+                        decoded.width(),
+                        decoded.height(),
+                        ffmpeg::software::scaling::flag::BICUBIC).map_err(|e| e.to_string())?;
+
+                    let time = decoded.timestamp().unwrap_or(0) as f64 * f64::from(stream.time_base());
+                    ctx.run(&decoded, &mut converted).map_err(|e| e.to_string())?;
+                    let image = ImageBuffer::from_raw(converted.width(), converted.height(), converted.data(0).to_vec())
+                        .ok_or("frame size doesn't match its pixels".to_string())?;
+                    frames.push((time, image));
+                }
+            }
+        }
+        Ok(frames)
+    }
+}
+
+// a #WAV may be an OGG of the same name
+fn sound_path(chart_path: &Path, file: &str) -> PathBuf {
+    let path = chart_path.with_file_name(file);
+    if path.with_extension("wav").exists() {
+        path.with_extension("wav")
+    } else {
+        path.with_extension("ogg")
+    }
+}
+
+// finds the files the chart names next to it and hands them to the backends.
+// what can't be found or read goes in the report, the chart plays on without it
+pub fn load(bms: &mut Bms, chart_path: &Path, sounds: &SoundBackend, images: &ImageBackend, report: &mut LoadReport) {
+    let start = time::precise_time_s();
+    for &(wav_id, ref file) in &bms.wav_files {
+        let header = format!("WAV{}", bms_loader::id_name(wav_id.id));
+        let path = sound_path(chart_path, file);
+        if !path.exists() {
+            report.missing_sounds.push(MissingAsset { header: header, path: path, error: "not found as .wav or .ogg".to_string() });
+            continue;
+        }
+        if let Err(error) = sounds.bind(wav_id, &path) {
+            report.missing_sounds.push(MissingAsset { header: header, path: path, error: error });
+            continue;
+        }
+        let length = sample_info::sample_length(&path);
+        bms.samples.insert(wav_id, Sample { path: path, length: length });
+    }
+
+    let mut frames: HashMap<u32, Vec<Image>> = HashMap::new();
+    for &(bmp_id, ref file) in &bms.bmp_files {
+        let header = format!("BMP{}", bms_loader::id_name(bmp_id));
+        let path = chart_path.with_file_name(file);
+        if !path.exists() {
+            report.missing_images.push(MissingAsset { header: header, path: path, error: "not found".to_string() });
+            continue;
+        }
+        match images.decode(&path) {
+            Ok(decoded) => {
+                let mut shown = vec![];
+                for (timing, image) in decoded {
+                    shown.push(Image { timing: timing, texture_id: bms.images.len() as i32 });
+                    bms.images.push(image);
+                }
+                frames.insert(bmp_id, shown);
+            }
+            Err(error) => report.missing_images.push(MissingAsset { header: header, path: path, error: error }),
+        }
+    }
+    for change in &bms.bga_changes {
+        for image in frames.get(&change.bmp_id).map_or(&[][..], |shown| &shown[..]) {
+            bms.bga.push(Image { timing: change.timing + image.timing, texture_id: image.texture_id });
+        }
+    }
+    report.asset_time = time::precise_time_s() - start;
+}
+
+#[test]
+fn load_test() {
+    use std::cell::RefCell;
+    use std::env;
+    use std::fs::{self, File};
+    use bms_parser::{BmsParser, BmsStringParser};
+
+    struct Recorded(RefCell<Vec<(u32, PathBuf)>>);
+    impl SoundBackend for Recorded {
+        fn bind(&self, wav_id: SoundX, path: &Path) -> Result<(), String> {
+            if path.ends_with("broken.wav") {
+                return Err("unsupported format".to_string());
+            }
+            self.0.borrow_mut().push((wav_id.id, path.to_path_buf()));
+            Ok(())
+        }
+    }
+    // two frames half a second apart, each the size of its frame number
+    struct TwoFrames;
+    impl ImageBackend for TwoFrames {
+        fn decode(&self, _path: &Path) -> Result<Vec<(f64, RgbaImage)>, String> {
+            Ok(vec![(0.0, ImageBuffer::from_raw(1, 1, vec![0; 4]).unwrap()), (0.5, ImageBuffer::from_raw(2, 2, vec![0; 16]).unwrap())])
+        }
+    }
+
+    let dir = env::temp_dir().join("rust_bms_assets_test");
+    fs::create_dir_all(&dir).unwrap();
+    File::create(dir.join("kick.ogg")).unwrap();
+    File::create(dir.join("broken.wav")).unwrap();
+    File::create(dir.join("movie.mpg")).unwrap();
+    let chart_path = dir.join("chart.bme");
+
+    let script = "#BPM 120\n#WAV01 kick.wav\n#WAV02 broken.wav\n#WAV03 none.wav\n#BMP01 movie.mpg\n#BMP02 none.bmp\n#00111:010203\n#00104:0102\n";
+    let (mut bms, mut report) = bms_loader::chart(&BmsStringParser { script: script.to_string() }.parse());
+    // the timeline is there before any file is looked at
+    assert_eq!(3, bms.sounds.len());
+    assert_eq!(vec![(0.0, 1), (1.0, 2)], bms.bga_changes.iter().map(|change| (change.timing, change.bmp_id)).collect::<Vec<_>>());
+    assert!(bms.samples.is_empty() && bms.bga.is_empty());
+
+    let sounds = Recorded(RefCell::new(vec![]));
+    load(&mut bms, &chart_path, &sounds, &TwoFrames, &mut report);
+    assert_eq!(vec![(1, dir.join("kick.ogg"))], *sounds.0.borrow());
+    assert_eq!(vec![SoundX { id: 1 }], bms.samples.keys().cloned().collect::<Vec<_>>());
+    assert_eq!(2, bms.images.len());
+    assert_eq!(vec![(0.0, 0), (0.5, 1)], bms.bga.iter().map(|image| (image.timing, image.texture_id)).collect::<Vec<_>>());
+    assert_eq!(vec![
+        format!("#WAV02 {}: unsupported format", dir.join("broken.wav").to_str().unwrap()),
+        format!("#WAV03 {}: not found as .wav or .ogg", dir.join("none.ogg").to_str().unwrap()),
+        format!("#BMP02 {}: not found", dir.join("none.bmp").to_str().unwrap()),
+    ], report.problems());
+}
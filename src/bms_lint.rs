@@ -0,0 +1,459 @@
+use std::collections::{HashMap, HashSet};
+use std::cmp::Ordering;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use bms_loader::id_name;
+use bms_parser::{BmsScript, ScriptLine};
+
+// one authoring mistake, where it was written
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lint {
+    pub line: usize,
+    pub measure: Option<u32>,
+    pub message: String,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.measure {
+            Some(measure) => write!(f, "line {} (measure {:03}): {}", self.line, measure, self.message),
+            None => write!(f, "line {}: {}", self.line, self.message),
+        }
+    }
+}
+
+const ELSE: u32 = 0; // #RANDOM values start at 1, this is any value no other branch takes
+const CONTROL_HEADERS: &'static [&'static str] = &["RANDOM", "SETRANDOM", "ENDRANDOM", "IF", "ELSEIF", "ELSE", "ENDIF"];
+
+// the #RANDOM blocks and values a line is under. lines under different values of one block never meet
+type Branch = Vec<(usize, u32)>;
+
+fn compatible(a: &Branch, b: &Branch) -> bool {
+    a.iter().all(|&(block, value)| b.iter().all(|&(other_block, other_value)| block != other_block || value == other_value))
+}
+
+struct OpenIf {
+    line: usize,
+    random: Option<(usize, u32)>, // block id and its range
+    taken: HashSet<u32>,
+    value: Option<u32>, // None while in a branch that can't be taken
+}
+
+// position in the chart, a fraction of its measure so positions compare exactly
+#[derive(Debug, Copy, Clone)]
+struct Position {
+    measure: u32,
+    index: u64,
+    len: u64,
+}
+
+impl Position {
+    fn cmp(&self, other: &Position) -> Ordering {
+        self.measure.cmp(&other.measure).then((self.index * other.len).cmp(&(other.index * self.len)))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Object {
+    line: usize,
+    position: Position,
+    id: u32,
+    branch: Branch,
+}
+
+// everything of a lane, 1P and 2P lanes are the digit after 1 or 2
+#[derive(Default)]
+struct Lane {
+    notes: Vec<Object>,
+    long_notes: Vec<Object>,
+}
+
+fn lane_of(channel: &str) -> Option<(String, bool)> {
+    let (side, digit) = (&channel[..1], &channel[1..]);
+    if digit == "0" || !digit.chars().all(|c| c.is_digit(10)) {
+        return None;
+    }
+    match side {
+        "1" | "2" => Some((channel.to_string(), false)),
+        "5" => Some((format!("1{}", digit), true)),
+        "6" => Some((format!("2{}", digit), true)),
+        _ => None,
+    }
+}
+
+fn refers_to_wav(channel: &str) -> bool {
+    channel == "01" || lane_of(channel).is_some() || (channel.starts_with('3') || channel.starts_with('4')) && &channel[1..] != "0"
+}
+
+fn refers_to_bmp(channel: &str) -> bool {
+    channel == "04" || channel == "06" || channel == "07"
+}
+
+fn objects(data: &str) -> Vec<String> {
+    let chars: Vec<char> = data.trim().chars().collect();
+    chars.chunks(2).map(|pair| pair.iter().collect()).collect()
+}
+
+// a file next to the chart, the way the loader looks for it. a WAV may be an OGG of the same name
+fn find_file(dir: &Path, name: &str, sound: bool) -> Result<(), Option<String>> {
+    let path = dir.join(name);
+    let mut candidates = vec![path.clone()];
+    if sound {
+        candidates.push(path.with_extension("wav"));
+        candidates.push(path.with_extension("ogg"));
+    }
+    if candidates.iter().any(|candidate| candidate.exists()) {
+        return Ok(());
+    }
+    // a chart made on Windows often gets the case of the name wrong
+    let wanted: Vec<String> = candidates.iter().filter_map(|candidate| candidate.file_name().and_then(|name| name.to_str()).map(|name| name.to_lowercase())).collect();
+    let parent = path.parent().unwrap_or(dir);
+    let found = fs::read_dir(parent).ok().and_then(|entries| {
+        entries.filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.file_name().to_str().map(|name| name.to_string()))
+            .find(|name| wanted.contains(&name.to_lowercase()))
+    });
+    Err(found)
+}
+
+// checks a chart for what usually goes wrong when writing one by hand.
+// dir is where the chart is, files aren't checked without it
+pub fn lint(script: &BmsScript, dir: Option<&Path>) -> Vec<Lint> {
+    let mut lints = vec![];
+    let mut push = |line: usize, measure: Option<u32>, message: String| lints.push(Lint { line: line, measure: measure, message: message });
+
+    let mut blocks = 0;
+    let mut randoms: Vec<Option<(usize, u32)>> = vec![None]; // the #RANDOM in effect at each #IF depth
+    let mut ifs: Vec<OpenIf> = vec![];
+
+    let mut headers: HashMap<String, Vec<(usize, Branch)>> = HashMap::new();
+    let mut wavs: HashMap<u32, (usize, String)> = HashMap::new();
+    let mut bmps: HashMap<u32, (usize, String)> = HashMap::new();
+    let mut wav_refs: Vec<Object> = vec![];
+    let mut bmp_refs: Vec<Object> = vec![];
+    let mut lanes: HashMap<String, Lane> = HashMap::new();
+    let mut lnobj = None;
+
+    for &(line, ref script_line) in script.lines() {
+        if let ScriptLine::Header(ref key, ref value) = *script_line {
+            let key = key.to_uppercase();
+            let number: Option<u32> = value.trim().parse().ok();
+            match key.as_str() {
+                "RANDOM" | "SETRANDOM" => {
+                    blocks += 1;
+                    *randoms.last_mut().unwrap() = number.map(|n| (blocks, n));
+                    continue;
+                }
+                "ENDRANDOM" => {
+                    *randoms.last_mut().unwrap() = None;
+                    continue;
+                }
+                "IF" => {
+                    let random = *randoms.last().unwrap();
+                    let value = match (random, number) {
+                        (None, _) => {
+                            push(line, None, "#IF outside #RANDOM is never taken".to_string());
+                            None
+                        }
+                        (Some((_, n)), Some(value)) if value >= 1 && value <= n => Some(value),
+                        (Some((_, n)), _) => {
+                            push(line, None, format!("#IF {} is never taken, #RANDOM {} gives 1 to {}", value.trim(), n, n));
+                            None
+                        }
+                    };
+                    ifs.push(OpenIf { line: line, random: random, taken: value.into_iter().collect(), value: value });
+                    randoms.push(None);
+                    continue;
+                }
+                "ELSEIF" | "ELSE" => {
+                    let open = match ifs.last_mut() {
+                        Some(open) => open,
+                        None => {
+                            push(line, None, format!("#{} without #IF", key));
+                            continue;
+                        }
+                    };
+                    open.value = match (open.random, key.as_str(), number) {
+                        (None, _, _) => None,
+                        (Some((_, n)), "ELSE", _) => {
+                            let left: Vec<u32> = (1..n + 1).filter(|value| !open.taken.contains(value)).collect();
+                            match left.len() {
+                                0 => {
+                                    push(line, None, format!("#ELSE is never taken, every value of #RANDOM {} has a branch", n));
+                                    None
+                                }
+                                1 => Some(left[0]),
+                                _ => Some(ELSE),
+                            }
+                        }
+                        (Some((_, _)), _, Some(value)) if open.taken.contains(&value) => {
+                            push(line, None, format!("#ELSEIF {} is never taken, an earlier branch takes it", value));
+                            None
+                        }
+                        (Some((_, n)), _, Some(value)) if value >= 1 && value <= n => Some(value),
+                        (Some((_, n)), _, _) => {
+                            push(line, None, format!("#ELSEIF {} is never taken, #RANDOM {} gives 1 to {}", value.trim(), n, n));
+                            None
+                        }
+                    };
+                    if let Some(value) = open.value {
+                        open.taken.insert(value);
+                    }
+                    *randoms.last_mut().unwrap() = None;
+                    continue;
+                }
+                "ENDIF" => {
+                    if ifs.pop().is_some() {
+                        randoms.pop();
+                    } else {
+                        push(line, None, "#ENDIF without #IF".to_string());
+                    }
+                    continue;
+                }
+                _ => (),
+            }
+        }
+
+        // what can't be reached has nothing worth checking
+        if ifs.iter().any(|open| open.value.is_none()) {
+            continue;
+        }
+        let branch: Branch = ifs.iter().filter_map(|open| match (open.random, open.value) {
+            (Some((block, _)), Some(value)) => Some((block, value)),
+            _ => None,
+        }).collect();
+
+        match *script_line {
+            ScriptLine::Header(ref key, ref value) => {
+                let key = key.to_uppercase();
+                if CONTROL_HEADERS.contains(&key.as_str()) {
+                    continue;
+                }
+                let earlier = headers.entry(key.clone()).or_insert(vec![]);
+                if let Some(&(earlier_line, _)) = earlier.iter().rev().find(|&&(_, ref earlier_branch)| compatible(earlier_branch, &branch)) {
+                    push(line, None, format!("#{} is defined again, the one on line {} is overridden", key, earlier_line));
+                }
+                earlier.push((line, branch.clone()));
+
+                let id = if key.len() == 5 { u32::from_str_radix(&key[3..], 36).ok() } else { None };
+                match (&key[..key.len().min(3)], id) {
+                    ("WAV", Some(id)) => { wavs.insert(id, (line, value.trim().to_string())); }
+                    ("BMP", Some(id)) => { bmps.insert(id, (line, value.trim().to_string())); }
+                    _ => (),
+                }
+                if key == "LNOBJ" {
+                    lnobj = u32::from_str_radix(value.trim(), 36).ok();
+                }
+            }
+            ScriptLine::Channel(ref key, ref data) => {
+                let measure: u32 = key[..3].parse().unwrap();
+                let channel = &key[3..];
+                if channel == "02" {
+                    match data.trim().parse::<f64>() {
+                        Ok(length) if length > 0.0 => (),
+                        _ => push(line, Some(measure), format!("measure length {} has to be more than 0", data.trim())),
+                    }
+                    continue;
+                }
+
+                let objects = objects(data);
+                let len = objects.len() as u64;
+                for (index, object) in objects.iter().enumerate() {
+                    let id = match u32::from_str_radix(object, 36) {
+                        Ok(0) | Err(_) => continue,
+                        Ok(id) => id,
+                    };
+                    let object = Object { line: line, position: Position { measure: measure, index: index as u64, len: len }, id: id, branch: branch.clone() };
+                    if refers_to_bmp(channel) {
+                        bmp_refs.push(object.clone());
+                    } else if refers_to_wav(channel) {
+                        wav_refs.push(object.clone());
+                    }
+                    if let Some((lane, long)) = lane_of(channel) {
+                        let lane = lanes.entry(lane).or_insert_with(Lane::default);
+                        if long {
+                            lane.long_notes.push(object);
+                        } else {
+                            lane.notes.push(object);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    for open in &ifs {
+        push(open.line, None, "#IF is never closed with #ENDIF".to_string());
+    }
+
+    let referenced = |refs: &Vec<Object>| refs.iter().map(|object| object.id).collect::<HashSet<u32>>();
+    let mut wav_used = referenced(&wav_refs);
+    wav_used.extend(lnobj);
+    let bmp_used = referenced(&bmp_refs);
+    for (refs, defined, name) in vec![(&wav_refs, &wavs, "WAV"), (&bmp_refs, &bmps, "BMP")] {
+        let mut reported = HashSet::new();
+        for object in refs.iter().filter(|object| !defined.contains_key(&object.id)) {
+            if reported.insert(object.id) {
+                push(object.line, Some(object.position.measure), format!("#{}{} is used but not defined", name, id_name(object.id)));
+            }
+        }
+    }
+    for (defined, used, name) in vec![(&wavs, &wav_used, "WAV"), (&bmps, &bmp_used, "BMP")] {
+        for (id, &(line, ref file)) in defined.iter() {
+            if !used.contains(id) {
+                push(line, None, format!("#{}{} is never used", name, id_name(*id)));
+            }
+            if let Some(dir) = dir {
+                match find_file(dir, file, name == "WAV") {
+                    Ok(()) => (),
+                    Err(Some(found)) => push(line, None, format!("#{}{} file {} is missing, there is {} with different case", name, id_name(*id), file, found)),
+                    Err(None) => push(line, None, format!("#{}{} file {} is missing", name, id_name(*id), file)),
+                }
+            }
+        }
+    }
+
+    let mut lane_names: Vec<&String> = lanes.keys().collect();
+    lane_names.sort();
+    for name in lane_names {
+        let lane = &lanes[name];
+        let mut all: Vec<&Object> = lane.notes.iter().chain(lane.long_notes.iter()).collect();
+        all.sort_by(|a, b| a.position.cmp(&b.position).then(a.line.cmp(&b.line)));
+        for (i, object) in all.iter().enumerate() {
+            let earlier = all[..i].iter().rev()
+                .take_while(|earlier| earlier.position.cmp(&object.position) == Ordering::Equal)
+                .find(|earlier| compatible(&earlier.branch, &object.branch));
+            if let Some(earlier) = earlier {
+                push(object.line, Some(object.position.measure), format!("notes overlap in lane {}, the other is on line {}", name, earlier.line));
+            }
+        }
+
+        let mut long_notes: Vec<&Object> = lane.long_notes.iter().collect();
+        long_notes.sort_by(|a, b| a.position.cmp(&b.position));
+        for pair in long_notes.chunks(2) {
+            if pair.len() < 2 {
+                push(pair[0].line, Some(pair[0].position.measure), format!("long note in lane {} has no end", name));
+                continue;
+            }
+            let (start, end) = (pair[0], pair[1]);
+            for note in &lane.notes {
+                if note.position.cmp(&start.position) == Ordering::Greater && note.position.cmp(&end.position) == Ordering::Less
+                    && compatible(&note.branch, &start.branch) {
+                    push(note.line, Some(note.position.measure), format!("note inside the long note from line {} to line {}", start.line, end.line));
+                }
+            }
+        }
+
+        // an LNOBJ note ends the long note the note before it starts
+        if let Some(lnobj) = lnobj {
+            let mut notes: Vec<&Object> = lane.notes.iter().collect();
+            notes.sort_by(|a, b| a.position.cmp(&b.position));
+            for (i, note) in notes.iter().enumerate().filter(|&(_, note)| note.id == lnobj) {
+                let start = notes[..i].iter().rev()
+                    .filter(|earlier| earlier.position.cmp(&note.position) == Ordering::Less)
+                    .find(|earlier| compatible(&earlier.branch, &note.branch));
+                if start.map_or(true, |start| start.id == lnobj) {
+                    push(note.line, Some(note.position.measure), format!("LNOBJ end in lane {} has no note to start from", name));
+                }
+            }
+        }
+    }
+
+    lints.sort_by(|a, b| a.line.cmp(&b.line));
+    lints
+}
+
+#[cfg(test)]
+fn lint_messages(script: &str, dir: Option<&Path>) -> Vec<String> {
+    use bms_parser::{BmsParser, BmsStringParser};
+    lint(&BmsStringParser { script: script.to_string() }.parse(), dir).iter().map(|lint| lint.to_string()).collect()
+}
+
+#[test]
+fn lint_test() {
+    let script = "\
+#TITLE test
+#TITLE again
+#WAV01 a.wav
+#WAV02 b.wav
+#BMP01 a.bmp
+#00102:0
+#00111:0103
+#00111:01
+#00151:000000AA
+#00152:BB
+#00112:00010000
+#00152:00CC
+#00104:02
+";
+    assert_eq!(vec![
+        "line 2: #TITLE is defined again, the one on line 1 is overridden",
+        "line 4: #WAV02 is never used",
+        "line 5: #BMP01 is never used",
+        "line 6 (measure 001): measure length 0 has to be more than 0",
+        "line 7 (measure 001): #WAV03 is used but not defined",
+        "line 8 (measure 001): notes overlap in lane 11, the other is on line 7",
+        "line 9 (measure 001): #WAVAA is used but not defined",
+        "line 9 (measure 001): long note in lane 11 has no end",
+        "line 10 (measure 001): #WAVBB is used but not defined",
+        "line 11 (measure 001): note inside the long note from line 10 to line 12",
+        "line 12 (measure 001): #WAVCC is used but not defined",
+        "line 13 (measure 001): #BMP02 is used but not defined",
+    ], lint_messages(script, None));
+}
+
+#[test]
+fn lint_random_test() {
+    let script = "\
+#WAV01 a.wav
+#WAV02 b.wav
+#LNOBJ 02
+#00011:02
+#RANDOM 2
+#IF 1
+#00111:01
+#ELSEIF 1
+#00111:01
+#ELSE
+#00111:01
+#ENDIF
+#IF 3
+#ENDIF
+#IF 2
+#00111:0002
+#ENDIF
+#IF 1
+#00111:0100
+#ELSEIF 2
+#ELSE
+#00111:01
+#ENDIF
+#IF 1
+";
+    // the #ELSE on line 10 can only be 2, so line 11 starts the long note line 16 ends
+    assert_eq!(vec![
+        "line 4 (measure 000): LNOBJ end in lane 11 has no note to start from",
+        "line 8: #ELSEIF 1 is never taken, an earlier branch takes it",
+        "line 13: #IF 3 is never taken, #RANDOM 2 gives 1 to 2",
+        "line 19 (measure 001): notes overlap in lane 11, the other is on line 7",
+        "line 21: #ELSE is never taken, every value of #RANDOM 2 has a branch",
+        "line 24: #IF is never closed with #ENDIF",
+    ], lint_messages(script, None));
+}
+
+#[test]
+fn lint_files_test() {
+    use std::env;
+    use std::fs::File;
+    let dir = env::temp_dir().join("rust_bms_lint_test");
+    fs::create_dir_all(&dir).unwrap();
+    File::create(dir.join("Kick.wav")).unwrap();
+    File::create(dir.join("snare.ogg")).unwrap();
+
+    let script = "#WAV01 kick.wav\n#WAV02 snare.wav\n#WAV03 hat.wav\n#00111:010203\n";
+    assert_eq!(vec![
+        "line 1: #WAV01 file kick.wav is missing, there is Kick.wav with different case",
+        "line 3: #WAV03 file hat.wav is missing",
+    ], lint_messages(script, Some(&dir)));
+}
@@ -1,16 +1,13 @@
-extern crate music;
-
 use std::{fmt, clone};
 use rand::{self, Rng};
-use bms_parser::{BmsParser, BmsFileParser, BmsScript};
+use bms_parser::{BmsFileParser, BmsScript};
 use std::collections::{HashMap, HashSet};
+use std::io;
 use std::path::{Path, PathBuf};
 use time;
+use bms_assets::{self, SoundBackend, ImageBackend, MixerSounds, NoSounds, FfmpegImages};
 
-use opengl_graphics::{GlGraphics, OpenGL, Texture, TextureSettings};
-use image::ImageBuffer;
-
-use ffmpeg::{self, format, codec, frame, media, filter, rescale, Rescale};
+use image::RgbaImage;
 
 pub struct KeyMetadata {
     id: u32,
@@ -37,6 +34,14 @@ pub enum Key {
     P1_KEY6 = 6,
     P1_KEY7 = 7,
     P1_SCRATCH = 0,
+    P2_KEY1 = 9,
+    P2_KEY2 = 10,
+    P2_KEY3 = 11,
+    P2_KEY4 = 12,
+    P2_KEY5 = 13,
+    P2_KEY6 = 14,
+    P2_KEY7 = 15,
+    P2_SCRATCH = 8,
     P1_FREE_SCRATCH = 254,
     BACK_CHORUS = 255,
 }
@@ -50,7 +55,94 @@ impl Key {
              Key::P1_KEY5,
              Key::P1_KEY6,
              Key::P1_KEY7,
-             Key::P1_SCRATCH].into_iter().collect()
+             Key::P1_SCRATCH,
+             Key::P2_KEY1,
+             Key::P2_KEY2,
+             Key::P2_KEY3,
+             Key::P2_KEY4,
+             Key::P2_KEY5,
+             Key::P2_KEY6,
+             Key::P2_KEY7,
+             Key::P2_SCRATCH].into_iter().collect()
+    }
+
+    // inverse of the Debug output
+    pub fn from_name(name: &str) -> Option<Key> {
+        match name {
+            "P1_KEY1" => Some(Key::P1_KEY1),
+            "P1_KEY2" => Some(Key::P1_KEY2),
+            "P1_KEY3" => Some(Key::P1_KEY3),
+            "P1_KEY4" => Some(Key::P1_KEY4),
+            "P1_KEY5" => Some(Key::P1_KEY5),
+            "P1_KEY6" => Some(Key::P1_KEY6),
+            "P1_KEY7" => Some(Key::P1_KEY7),
+            "P1_SCRATCH" => Some(Key::P1_SCRATCH),
+            "P2_KEY1" => Some(Key::P2_KEY1),
+            "P2_KEY2" => Some(Key::P2_KEY2),
+            "P2_KEY3" => Some(Key::P2_KEY3),
+            "P2_KEY4" => Some(Key::P2_KEY4),
+            "P2_KEY5" => Some(Key::P2_KEY5),
+            "P2_KEY6" => Some(Key::P2_KEY6),
+            "P2_KEY7" => Some(Key::P2_KEY7),
+            "P2_SCRATCH" => Some(Key::P2_SCRATCH),
+            "P1_FREE_SCRATCH" => Some(Key::P1_FREE_SCRATCH),
+            "BACK_CHORUS" => Some(Key::BACK_CHORUS),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
+pub enum PlayMode {
+    BEAT_5K,
+    BEAT_7K,
+    POPN_9K,
+    BEAT_14K,
+}
+
+impl PlayMode {
+    pub fn all() -> Vec<PlayMode> {
+        vec![PlayMode::BEAT_5K, PlayMode::BEAT_7K, PlayMode::POPN_9K, PlayMode::BEAT_14K]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            PlayMode::BEAT_5K => "5K",
+            PlayMode::BEAT_7K => "7K",
+            PlayMode::POPN_9K => "9K",
+            PlayMode::BEAT_14K => "14K",
+        }
+    }
+
+    // guessed from the lanes in use since the loader doesn't know about #PLAYER or .pms yet
+    pub fn of(sounds: &[Sound]) -> PlayMode {
+        let used: HashSet<Key> = sounds.iter().map(|sound| sound.key).collect();
+        let p2_keys = vec![Key::P2_KEY1, Key::P2_KEY2, Key::P2_KEY3, Key::P2_KEY4, Key::P2_KEY5, Key::P2_KEY6, Key::P2_KEY7, Key::P2_SCRATCH];
+        let p2_beat_only = vec![Key::P2_KEY1, Key::P2_KEY6, Key::P2_KEY7, Key::P2_SCRATCH];
+        if p2_keys.iter().any(|key| used.contains(key)) {
+            if !used.contains(&Key::P1_SCRATCH) && !p2_beat_only.iter().any(|key| used.contains(key)) {
+                PlayMode::POPN_9K
+            } else {
+                PlayMode::BEAT_14K
+            }
+        } else if used.contains(&Key::P1_KEY6) || used.contains(&Key::P1_KEY7) {
+            PlayMode::BEAT_7K
+        } else {
+            PlayMode::BEAT_5K
+        }
+    }
+
+    // lanes from left to right; 9K charts put buttons 6-9 on the 2P channels 22-25
+    pub fn lanes(&self) -> Vec<Key> {
+        match *self {
+            PlayMode::BEAT_5K => vec![Key::P1_SCRATCH, Key::P1_KEY1, Key::P1_KEY2, Key::P1_KEY3, Key::P1_KEY4, Key::P1_KEY5],
+            PlayMode::BEAT_7K => vec![Key::P1_SCRATCH, Key::P1_KEY1, Key::P1_KEY2, Key::P1_KEY3, Key::P1_KEY4, Key::P1_KEY5, Key::P1_KEY6, Key::P1_KEY7],
+            PlayMode::POPN_9K => vec![Key::P1_KEY1, Key::P1_KEY2, Key::P1_KEY3, Key::P1_KEY4, Key::P1_KEY5, Key::P2_KEY2, Key::P2_KEY3, Key::P2_KEY4, Key::P2_KEY5],
+            PlayMode::BEAT_14K => vec![
+                Key::P1_SCRATCH, Key::P1_KEY1, Key::P1_KEY2, Key::P1_KEY3, Key::P1_KEY4, Key::P1_KEY5, Key::P1_KEY6, Key::P1_KEY7,
+                Key::P2_KEY1, Key::P2_KEY2, Key::P2_KEY3, Key::P2_KEY4, Key::P2_KEY5, Key::P2_KEY6, Key::P2_KEY7, Key::P2_SCRATCH,
+            ],
+        }
     }
 }
 
@@ -64,39 +156,197 @@ fn channel_of_key(key: &Key) -> &'static str {
         Key::P1_KEY6 => "18",
         Key::P1_KEY7 => "19",
         Key::P1_SCRATCH => "16",
+        Key::P2_KEY1 => "21",
+        Key::P2_KEY2 => "22",
+        Key::P2_KEY3 => "23",
+        Key::P2_KEY4 => "24",
+        Key::P2_KEY5 => "25",
+        Key::P2_KEY6 => "28",
+        Key::P2_KEY7 => "29",
+        Key::P2_SCRATCH => "26",
         Key::BACK_CHORUS => "01",
         _ => "none",
     }
 }
 
+// LNTYPE 1 long notes live in channel 5x (6x for 2P) for the key channel 1x (2x)
+fn ln_channel_of_key(key: &Key) -> String {
+    let channel = channel_of_key(key);
+    match &channel[..1] {
+        "1" => format!("5{}", &channel[1..]),
+        "2" => format!("6{}", &channel[1..]),
+        _ => "none".to_string(),
+    }
+}
+
+// landmine channels are D1-D9 and E1-E9, next to the key channels 1x and 2x
+fn mine_channel_of_key(key: &Key) -> String {
+    let channel = channel_of_key(key);
+    match &channel[..1] {
+        "1" => format!("D{}", &channel[1..]),
+        "2" => format!("E{}", &channel[1..]),
+        _ => "none".to_string(),
+    }
+}
+
+#[derive(Clone)]
 pub struct Sound {
     pub key: Key,
     pub timing: f64,
     pub wav_id: SoundX,
+    pub ln_end: Option<f64>, // end timing if this is the head of a long note
 }
 
-#[derive(PartialEq, PartialOrd)]
+#[derive(Clone, PartialEq, PartialOrd)]
 pub struct BpmChange {
     pub timing: f64,
     pub bpm: f64,
 }
 
+// a note that takes damage from the gauge when pressed, not played yet
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Mine {
+    pub key: Key,
+    pub timing: f64,
+    pub damage: u32,
+}
+
+// #STOP, scrolling halts for duration seconds from timing
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Stop {
+    pub timing: f64,
+    pub duration: f64,
+}
+
+// bpm 0 while stopped, so positions stand still through a #STOP
+pub fn scroll_bpms(bpms: &Vec<BpmChange>, stops: &Vec<Stop>) -> Vec<BpmChange> {
+    let mut scroll = bpms.clone();
+    for stop in stops {
+        let bpm = bpms.iter().filter(|change| change.timing <= stop.timing).last().map_or(130.0, |change| change.bpm);
+        scroll.push(BpmChange { timing: stop.timing, bpm: 0.0 });
+        scroll.push(BpmChange { timing: stop.timing + stop.duration, bpm: bpm });
+    }
+    scroll.sort_by(|a, b| a.timing.partial_cmp(&b.timing).unwrap());
+    scroll
+}
+
+// a #WAV file, kept so a sample can be restarted part way through after a seek
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub path: PathBuf,
+    pub length: Option<f64>,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct Image {
     pub timing: f64,
     pub texture_id: i32,
 }
 
+// a #BMP shown from timing, the asset stage turns it into the frames of the file
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BgaChange {
+    pub timing: f64,
+    pub bmp_id: u32,
+}
+
 pub struct Bms {
     pub sounds: Vec<Sound>,
+    pub mines: Vec<Mine>,
     pub bars: Vec<f64>,  // time for bar line to pass the judge line relative to start time in sec.
     pub bpms: Vec<BpmChange>,
+    pub stops: Vec<Stop>,
+    pub bga_changes: Vec<BgaChange>,
+    pub total: Option<f64>, // #TOTAL, how much the gauge grows over the whole chart
+    pub wav_files: Vec<(SoundX, String)>, // as written in the headers, for the asset stage to find
+    pub bmp_files: Vec<(u32, String)>,
+    // filled by the asset stage
     pub bga: Vec<Image>,
-    pub textures: Vec<Texture>,
+    pub images: Vec<RgbaImage>, // decoded BGA frames, made into textures by whatever draws them
+    pub samples: HashMap<SoundX, Sample>, // the sounds that were found
+}
+
+impl Bms {
+    pub fn play_mode(&self) -> PlayMode {
+        PlayMode::of(&self.sounds)
+    }
+
+    // when the last note or long note ends
+    pub fn end(&self) -> f64 {
+        self.sounds.iter().fold(0.0, |end, sound| f64::max(end, sound.ln_end.unwrap_or(sound.timing)))
+    }
+}
+
+// a #WAV or #BMP file that won't be heard or seen
+#[derive(Debug, Clone, PartialEq)]
+pub struct MissingAsset {
+    pub header: String, // like WAV01
+    pub path: PathBuf,
+    pub error: String,
+}
+
+// an object of a channel the loader left out of the chart
+#[derive(Debug, Clone, PartialEq)]
+pub struct SkippedObject {
+    pub measure: String,
+    pub channel: String,
+    pub object: String,
+    pub reason: String,
+}
+
+// what didn't make it from the file into the chart, and how long loading took
+#[derive(Debug, Clone, Default)]
+pub struct LoadReport {
+    pub missing_sounds: Vec<MissingAsset>,
+    pub missing_images: Vec<MissingAsset>,
+    pub bad_headers: Vec<String>,
+    pub skipped: Vec<SkippedObject>,
+    pub unsupported_channels: Vec<String>, // with objects in them, sorted
+    pub parse_time: f64, // seconds reading the file
+    pub asset_time: f64, // seconds finding and decoding sounds and images
+    pub timeline_time: f64, // seconds placing the objects in time
+}
+
+impl LoadReport {
+    pub fn is_clean(&self) -> bool {
+        self.missing_sounds.is_empty() && self.missing_images.is_empty() && self.bad_headers.is_empty()
+            && self.skipped.is_empty() && self.unsupported_channels.is_empty()
+    }
+
+    // a line for each problem
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        for asset in self.missing_sounds.iter().chain(self.missing_images.iter()) {
+            problems.push(format!("#{} {}: {}", asset.header, asset.path.to_str().unwrap_or(""), asset.error));
+        }
+        problems.extend(self.bad_headers.iter().cloned());
+        for object in &self.skipped {
+            problems.push(format!("measure {} channel {}: {} skipped, {}", object.measure, object.channel, object.object, object.reason));
+        }
+        for channel in &self.unsupported_channels {
+            problems.push(format!("channel {} is not played", channel));
+        }
+        problems
+    }
+
+    // short enough for the loading screen
+    pub fn summary(&self) -> String {
+        let counts = vec![
+            (self.missing_sounds.len(), "sounds missing"),
+            (self.missing_images.len(), "images missing"),
+            (self.bad_headers.len(), "bad headers"),
+            (self.skipped.len(), "objects skipped"),
+            (self.unsupported_channels.len(), "channels not played"),
+        ];
+        let mut parts: Vec<String> = counts.into_iter().filter(|&(count, _)| count > 0).map(|(count, what)| format!("{} {}", count, what)).collect();
+        parts.push(format!("loaded in {:.2}s", self.parse_time + self.asset_time + self.timeline_time));
+        parts.join(", ")
+    }
 }
 
 pub trait BmsLoader {
-    fn load(&self) -> Bms;
+    // fails only when the chart itself can't be read, anything less goes in the report
+    fn load(&self) -> io::Result<(Bms, LoadReport)>;
 }
 
 #[derive(Debug)]
@@ -115,17 +365,43 @@ impl BmsEvent {
 enum BmsEventType {
     Bar,
     BpmChange(f64),
+    Stop(f64), // in 1/192 of a 4/4 measure
     Key(Key, SoundX),
-    Bga(Vec<Image>),
+    LongNote(Key, SoundX),
+    Mine(Key, u32),
+    Bga(u32),
+}
+
+// the two ids of #WAVxx and the like, as they are written
+pub fn id_name(id: u32) -> String {
+    let digits: Vec<char> = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ".chars().collect();
+    format!("{}{}", digits[(id / 36 % 36) as usize], digits[(id % 36) as usize])
 }
 
 pub struct BmsFileLoader {
-    path: String
+    path: String,
+    assets: Option<(Box<SoundBackend>, Box<ImageBackend>)>, // None stops after the chart stage
 }
 
 impl BmsFileLoader {
+    // sounds bound to the mixer, which has to be open, and BGA decoded with ffmpeg
     pub fn new(path: &str) -> BmsFileLoader {
-        BmsFileLoader { path: path.to_string() }
+        BmsFileLoader::with_backends(path, Box::new(MixerSounds), Box::new(FfmpegImages))
+    }
+
+    // for loads that never play anything, so no mixer has to be open
+    pub fn without_sound(path: &str) -> BmsFileLoader {
+        BmsFileLoader::with_backends(path, Box::new(NoSounds), Box::new(FfmpegImages))
+    }
+
+    // notes and timing only, no file besides the script is touched.
+    // quick enough to run over charts that are just being looked at
+    pub fn chart_only(path: &str) -> BmsFileLoader {
+        BmsFileLoader { path: path.to_string(), assets: None }
+    }
+
+    pub fn with_backends(path: &str, sounds: Box<SoundBackend>, images: Box<ImageBackend>) -> BmsFileLoader {
+        BmsFileLoader { path: path.to_string(), assets: Some((sounds, images)) }
     }
 
     fn list_segment_ids(script: &BmsScript) -> Vec<&str> {
@@ -139,8 +415,21 @@ impl BmsFileLoader {
         ret
     }
 
-    fn decode(code: &str) -> i32 {
-        i32::from_str_radix(code, 16).unwrap()
+    fn decode(code: &str) -> Option<i32> {
+        i32::from_str_radix(code, 16).ok()
+    }
+
+    // the id of headers like WAV01 or BMP0A, after the name
+    fn header_id(key: &str, name: &str) -> Option<u32> {
+        if key.len() == name.len() + 2 {
+            u32::from_str_radix(&key[name.len()..], 36).ok()
+        } else {
+            None
+        }
+    }
+
+    fn skip(report: &mut LoadReport, segment_id: &str, channel: &str, object: &str, reason: String) {
+        report.skipped.push(SkippedObject { measure: segment_id.to_string(), channel: channel.to_string(), object: object.to_string(), reason: reason });
     }
 
     fn beat_duration(bpm: f64) -> f64 {
@@ -160,211 +449,339 @@ impl BmsFileLoader {
         command_v
     }
 
-    fn load_images(path: &str, textures: &mut Vec<Texture>) -> Vec<Image> {
-        ffmpeg::init().unwrap();
-        let mut images = Vec::new();
-        let mut id = textures.len() as i32;
-        match ffmpeg::format::input(&path.to_string()) {
-            Ok(mut context) => {
-                let (mut decoder, image_index) = {
-                    let input = context.streams().best(media::Type::Video).unwrap();
-                    (input.codec().decoder().video().unwrap(), input.index())
-                };
+    // long note channels list start and end objects alternately per lane
+    fn pair_long_notes(long_notes: Vec<Sound>) -> Vec<Sound> {
+        let mut heads: HashMap<Key, Sound> = HashMap::new();
+        let mut paired = vec![];
+        for sound in long_notes {
+            match heads.remove(&sound.key) {
+                Some(mut head) => {
+                    head.ln_end = Some(sound.timing);
+                    paired.push(head);
+                }
+                None => {
+                    heads.insert(sound.key, sound);
+                }
+            }
+        }
+        // a head without an end is played as a normal note
+        paired.extend(heads.into_iter().map(|(_, head)| head));
+        paired
+    }
 
-                let mut decoded = frame::Video::empty();
-                let mut converted = frame::Video::empty();
-                let mut i = 0;
-                for (stream, mut packet) in context.packets() {
-                    if stream.index() == image_index {
-                        if let Ok(true) = decoder.decode(&packet, &mut decoded) {
-                            let mut ctx = ffmpeg::software::scaling::Context::get(
-                                decoded.format(),
-                                decoded.width(),
-                                decoded.height(),
-                                format::Pixel::RGBA,
-                                decoded.width(),
-                                decoded.height(),
-                                ffmpeg::software::scaling::flag::BICUBIC).unwrap();
-
-                            let time = decoded.timestamp().unwrap_or(0) as f64 * f64::from(stream.time_base());
-//                            println!("{}", time);
-                            images.push(Image { timing: time, texture_id: id });
-
-                            ctx.run(&mut decoded, &mut converted);
-                            let texture = Texture::from_image(&ImageBuffer::from_raw(converted.width(), converted.height(), converted.data(0).to_vec()).unwrap(), &TextureSettings::new());
-                            textures.push(texture);
-                            id += 1;
-                        }
+    // #LNOBJ turns the previous note of the lane into a long note ending at the LNOBJ note
+    fn apply_lnobj(sounds: Vec<Sound>, lnobj: SoundX) -> Vec<Sound> {
+        let mut result: Vec<Sound> = vec![];
+        let mut last_by_key: HashMap<Key, usize> = HashMap::new();
+        for sound in sounds {
+            if sound.wav_id == lnobj && sound.key != Key::BACK_CHORUS {
+                if let Some(&index) = last_by_key.get(&sound.key) {
+                    if result[index].ln_end.is_none() {
+                        result[index].ln_end = Some(sound.timing);
+                        continue;
                     }
                 }
             }
-            Err(error) => {
-                println!("error while loading bga: {}", error)
-            }
+            last_by_key.insert(sound.key, result.len());
+            result.push(sound);
         }
-        images
+        result
     }
 }
 
 impl BmsLoader for BmsFileLoader {
-    fn load(&self) -> Bms {
-        println!("Start BmsFileLoader.load() at {}", time::precise_time_s());
+    fn load(&self) -> io::Result<(Bms, LoadReport)> {
+        let start = time::precise_time_s();
         let script_parser = BmsFileParser { path: self.path.to_string() };
-        let script = script_parser.parse();
+        let script = script_parser.read()?;
+        let parse_time = time::precise_time_s() - start;
 
-        let segment_ids = BmsFileLoader::list_segment_ids(&script);
-
-        // 1. make bpms
-        let mut bpms: Vec<BpmChange> = vec![];
-        let mut bars: Vec<f64> = vec![];
-        let mut sounds: Vec<Sound> = vec![];
-        let mut bga: Vec<Image> = vec![];
-        let mut textures: Vec<Texture> = vec![];
-
-        // get initial bpm
-        let initial_bpm: f64 = script.headers().get("BPM").unwrap().parse().unwrap_or(130.);
+        let (mut bms, mut report) = chart(&script);
+        report.parse_time = parse_time;
+        if let Some((ref sounds, ref images)) = self.assets {
+            bms_assets::load(&mut bms, Path::new(&self.path), &**sounds, &**images, &mut report);
+        }
+        Ok((bms, report))
+    }
+}
 
-        bpms.push(BpmChange { timing: 0., bpm: initial_bpm });
+// the timeline of the chart with the files it uses left as names. reads nothing but the script
+pub fn chart(script: &BmsScript) -> (Bms, LoadReport) {
+    let mut report = LoadReport::default();
+    let start = time::precise_time_s();
+
+    let segment_ids = BmsFileLoader::list_segment_ids(&script);
+
+    // 1. make bpms
+    let mut bpms: Vec<BpmChange> = vec![];
+    let mut bars: Vec<f64> = vec![];
+    let mut stops: Vec<Stop> = vec![];
+    let mut sounds: Vec<Sound> = vec![];
+    let mut long_notes: Vec<Sound> = vec![];
+    let mut mines: Vec<Mine> = vec![];
+    let mut bga_changes: Vec<BgaChange> = vec![];
+
+    // get initial bpm
+    let initial_bpm: f64 = match script.headers().get("BPM").map(|bpm| bpm.trim().parse()) {
+        Some(Ok(bpm)) if bpm > 0. => bpm,
+        Some(_) => {
+            report.bad_headers.push(format!("#BPM {} is not a bpm, 130 is used", script.headers()["BPM"]));
+            130.
+        }
+        None => {
+            report.bad_headers.push("#BPM is missing, 130 is used".to_string());
+            130.
+        }
+    };
+
+    bpms.push(BpmChange { timing: 0., bpm: initial_bpm });
+
+    // 2P channels carry DP charts and buttons 6-9 of 9K charts
+    let mut keys = vec![Key::BACK_CHORUS];
+    keys.extend(Key::visible_keys());
+
+    let mut wav_files: Vec<(SoundX, String)> = vec![];
+    let mut bmp_files: Vec<(u32, String)> = vec![];
+    let mut stop_map: HashMap<u32, f64> = HashMap::new();
+    // sorted so the report and the texture ids come out the same every load
+    let mut headers: Vec<(&String, &String)> = script.headers().iter().collect();
+    headers.sort();
+    for (key, value) in headers {
+        if key.starts_with("WAV") {
+            let wav_id = match BmsFileLoader::header_id(key, "WAV") {
+                Some(wav_id) => wav_id,
+                None => {
+                    report.bad_headers.push(format!("#{} has no 2 character id", key));
+                    continue;
+                }
+            };
+            wav_files.push((SoundX {id: wav_id}, value.to_string()));
+        }
 
-        // TODO: DP
-        let keys = vec![
-            Key::BACK_CHORUS,
-            Key::P1_KEY1,
-            Key::P1_KEY2,
-            Key::P1_KEY3,
-            Key::P1_KEY4,
-            Key::P1_KEY5,
-            Key::P1_KEY6,
-            Key::P1_KEY7,
-            Key::P1_SCRATCH,
-        ];
+        if key.starts_with("BMP") {
+            let bmp_id = match BmsFileLoader::header_id(key, "BMP") {
+                Some(bmp_id) => bmp_id,
+                None => {
+                    report.bad_headers.push(format!("#{} has no 2 character id", key));
+                    continue;
+                }
+            };
+            bmp_files.push((bmp_id, value.to_string()));
+        }
 
-        let path_path = Path::new(&self.path);
-        let mut wav_ids: HashSet<u32> = HashSet::new();
-        let mut image_map: HashMap<u32, Vec<Image>> = HashMap::new();
-        for (key, value) in script.headers() {
-            if key.starts_with("WAV") {
-                let wav_id = u32::from_str_radix(&key[3..5], 36).unwrap();
-//                println!("{} {}", &value, path_path.with_file_name(&value).with_extension("ogg").as_path().to_str().unwrap());
-                let wav_path = path_path.with_file_name(&value);
-                let wav_path = if wav_path.with_extension("wav").as_path().exists() {
-                    wav_path.with_extension("wav")
-                } else {
-                    wav_path.with_extension("ogg")
-                };
-                music::bind_sound_file(SoundX {id: wav_id}, wav_path.as_path().to_str().unwrap());
-                wav_ids.insert(wav_id);
+        if key.starts_with("STOP") {
+            match (BmsFileLoader::header_id(key, "STOP"), value.trim().parse()) {
+                (Some(stop_id), Ok(length)) => {
+                    stop_map.insert(stop_id, length);
+                }
+                _ => report.bad_headers.push(format!("#{} {} is not a stop id and length", key, value)),
             }
-
-            if key.starts_with("BMP") {
-                let bmp_id = u32::from_str_radix(&key[3..5], 36).unwrap();
-                image_map.insert(bmp_id, BmsFileLoader::load_images(path_path.with_file_name(&value).to_str().unwrap(), &mut textures));
+        }
+    }
+    // notes of a missing file stay in the chart and play nothing, so every id counts
+    let wav_ids: HashSet<u32> = wav_files.iter().map(|&(wav_id, _)| wav_id.id).collect();
+    let bmp_ids: HashSet<u32> = bmp_files.iter().map(|&(bmp_id, _)| bmp_id).collect();
+
+    // parse from beginning
+    let mut current_bpm = initial_bpm;
+    let mut segment_head: f64 = 0.;
+    for segment_id in &segment_ids {
+        let mut events: Vec<BmsEvent> = vec![];
+        events.push(BmsEvent::new(0., BmsEventType::Bar));
+
+        let size_key = format!("{}{}", segment_id, "02");
+
+        let segment_size: f64 = match script.channels().get(&size_key).and_then(|s| s.last()) {
+            Some(s) => match s.trim().parse() {
+                Ok(size) if size > 0. => size,
+                _ => {
+                    BmsFileLoader::skip(&mut report, segment_id, "02", s.trim(), "not a measure length, 1 is used".to_string());
+                    1.
+                }
+            },
+            None => 1.,
+        };
+        let beats: f64 = 4. * segment_size;
+
+        let empty = vec![];
+        // parse bpm change
+        // TODO: handle channel 08 soft landing
+        let softlanding_channel = format!("{}03", segment_id);
+        for softlanding_channel_commands in script.channels().get(&softlanding_channel).unwrap_or(&empty) {
+            let commands = BmsFileLoader::decompose_command(softlanding_channel_commands);
+            let notes = commands.len();
+            for (idx, command) in commands.iter().enumerate() {
+                let segment_position = (idx as f64) / (notes as f64);
+                match BmsFileLoader::decode(command) {
+                    Some(new_bpm) if new_bpm > 0 => events.push(BmsEvent::new(segment_position, BmsEventType::BpmChange(new_bpm as f64))),
+                    Some(_) => (),
+                    None => BmsFileLoader::skip(&mut report, segment_id, "03", command, "not a hex bpm".to_string()),
+                }
             }
         }
 
-        // parse from beginning
-        let mut current_bpm = initial_bpm;
-        let mut segment_head: f64 = 0.;
-        for segment_id in &segment_ids {
-            let mut events: Vec<BmsEvent> = vec![];
-            events.push(BmsEvent::new(0., BmsEventType::Bar));
-
-            let size_key = format!("{}{}", segment_id, "02");
-
-            let segment_size: f64 = match script.channels().get(&size_key) {
-                Some(s) => s.last().unwrap().trim().parse().unwrap(),
-                None => 1.,
-            };
-            let beats: f64 = 4. * segment_size;
-
-            let empty = vec![];
-            // parse bpm change
-            // TODO: handle channel 08 soft landing
-            let softlanding_channel = format!("{}03", segment_id);
-            for softlanding_channel_commands in script.channels().get(&softlanding_channel).unwrap_or(&empty) {
-                let commands = BmsFileLoader::decompose_command(softlanding_channel_commands);
+        // parse keys
+        for key in &keys {
+            let channel_key = format!("{}{}", segment_id, channel_of_key(key));
+            for channel_commands in script.channels().get(&channel_key).unwrap_or(&empty) {
+                let commands = BmsFileLoader::decompose_command(channel_commands);
                 let notes = commands.len();
+
                 for (idx, command) in commands.iter().enumerate() {
                     let segment_position = (idx as f64) / (notes as f64);
-                    let new_bpm = BmsFileLoader::decode(command);
-                    if new_bpm > 0 {
-                        events.push(BmsEvent::new(segment_position, BmsEventType::BpmChange(new_bpm as f64)))
+                    match u32::from_str_radix(command, 36) {
+                        Ok(0) => (),
+                        Ok(wav_id) if wav_ids.contains(&wav_id) => {
+                            events.push(BmsEvent::new(segment_position, BmsEventType::Key(*key, SoundX {id: wav_id})));
+                        }
+                        Ok(_) => BmsFileLoader::skip(&mut report, segment_id, &channel_of_key(key), command, format!("no #WAV{}", command)),
+                        Err(_) => BmsFileLoader::skip(&mut report, segment_id, &channel_of_key(key), command, "not a base 36 id".to_string()),
                     }
-                }
-            }
-
-            // parse keys
-            for key in &keys {
-                let channel_key = format!("{}{}", segment_id, channel_of_key(key));
-                for channel_commands in script.channels().get(&channel_key).unwrap_or(&empty) {
-                    let commands = BmsFileLoader::decompose_command(channel_commands);
-                    let notes = commands.len();
+                };
+            };
 
-                    for (idx, command) in commands.iter().enumerate() {
-                        let wav_id = u32::from_str_radix(command, 36).unwrap();
-                        let segment_position = (idx as f64) / (notes as f64);
+            let ln_channel_key = format!("{}{}", segment_id, ln_channel_of_key(key));
+            for channel_commands in script.channels().get(&ln_channel_key).unwrap_or(&empty) {
+                let commands = BmsFileLoader::decompose_command(channel_commands);
+                let notes = commands.len();
 
-                        if wav_id != 0 && wav_ids.contains(&wav_id) {
-                            events.push(BmsEvent::new(segment_position, BmsEventType::Key(*key, SoundX {id: wav_id})));
-                        };
-                    };
+                for (idx, command) in commands.iter().enumerate() {
+                    let segment_position = (idx as f64) / (notes as f64);
+                    match u32::from_str_radix(command, 36) {
+                        Ok(0) => (),
+                        Ok(wav_id) if wav_ids.contains(&wav_id) => {
+                            events.push(BmsEvent::new(segment_position, BmsEventType::LongNote(*key, SoundX {id: wav_id})));
+                        }
+                        Ok(_) => BmsFileLoader::skip(&mut report, segment_id, &ln_channel_of_key(key), command, format!("no #WAV{}", command)),
+                        Err(_) => BmsFileLoader::skip(&mut report, segment_id, &ln_channel_of_key(key), command, "not a base 36 id".to_string()),
+                    }
                 };
             };
 
-            let bga_channel = format!("{}04", segment_id);
-            for bga_channel_commands in script.channels().get(&bga_channel).unwrap_or(&empty) {
-                let commands = BmsFileLoader::decompose_command(bga_channel_commands);
+            let mine_channel_key = format!("{}{}", segment_id, mine_channel_of_key(key));
+            for channel_commands in script.channels().get(&mine_channel_key).unwrap_or(&empty) {
+                let commands = BmsFileLoader::decompose_command(channel_commands);
                 let notes = commands.len();
 
                 for (idx, command) in commands.iter().enumerate() {
-                    let bmp_id = u32::from_str_radix(command, 36).unwrap();
                     let segment_position = (idx as f64) / (notes as f64);
-
-                    if image_map.contains_key(&bmp_id) {
-                        events.push(BmsEvent::new(segment_position, BmsEventType::Bga(image_map[&bmp_id].clone())));
-                    };
+                    match u32::from_str_radix(command, 36) {
+                        Ok(0) => (),
+                        Ok(damage) => events.push(BmsEvent::new(segment_position, BmsEventType::Mine(*key, damage))),
+                        Err(_) => BmsFileLoader::skip(&mut report, segment_id, &mine_channel_of_key(key), command, "not a base 36 damage".to_string()),
+                    }
                 };
-            }
-
+            };
+        };
 
-            events.sort_by(|a, b| a.segment_position.partial_cmp(&b.segment_position).unwrap());
-            let mut previous_position: f64 = 0.;
-            let mut previous_timing: f64 = segment_head;
-            let mut current_segment_bpm: f64 = current_bpm;
-            for event in events {
-                let position_delta = event.segment_position - previous_position;
-                let timing_delta = position_delta * beats * BmsFileLoader::beat_duration(current_segment_bpm);
-                let timing = previous_timing + timing_delta;
+        let bga_channel = format!("{}04", segment_id);
+        for bga_channel_commands in script.channels().get(&bga_channel).unwrap_or(&empty) {
+            let commands = BmsFileLoader::decompose_command(bga_channel_commands);
+            let notes = commands.len();
+
+            for (idx, command) in commands.iter().enumerate() {
+                let segment_position = (idx as f64) / (notes as f64);
+                match u32::from_str_radix(command, 36) {
+                    Ok(0) => (),
+                    Ok(bmp_id) if bmp_ids.contains(&bmp_id) => events.push(BmsEvent::new(segment_position, BmsEventType::Bga(bmp_id))),
+                    Ok(_) => BmsFileLoader::skip(&mut report, segment_id, "04", command, format!("no #BMP{}", command)),
+                    Err(_) => BmsFileLoader::skip(&mut report, segment_id, "04", command, "not a base 36 id".to_string()),
+                }
+            };
+        }
 
-//                println!("{} {:?}", timing, event);
 
-                match event.event {
-                    BmsEventType::Bar => bars.push(timing),
-                    BmsEventType::Key(key, soundx) => sounds.push(Sound { key: key, timing: timing, wav_id: soundx} ),
-                    BmsEventType::BpmChange(newBpm) => {
-                        current_segment_bpm = newBpm;
-                        bpms.push(BpmChange { timing: timing, bpm: newBpm} );
+        // pushed last so the stable sort keeps them after everything else at the same position
+        let stop_channel = format!("{}09", segment_id);
+        for stop_channel_commands in script.channels().get(&stop_channel).unwrap_or(&empty) {
+            let commands = BmsFileLoader::decompose_command(stop_channel_commands);
+            let notes = commands.len();
+            for (idx, command) in commands.iter().enumerate() {
+                let segment_position = (idx as f64) / (notes as f64);
+                match u32::from_str_radix(command, 36) {
+                    Ok(0) => (),
+                    Ok(id) => match stop_map.get(&id) {
+                        Some(length) => events.push(BmsEvent::new(segment_position, BmsEventType::Stop(*length))),
+                        None => BmsFileLoader::skip(&mut report, segment_id, "09", command, format!("no #STOP{}", command)),
                     },
-                    BmsEventType::Bga(images) => {
-                        for image in images {
-                            bga.push(Image {timing: timing + image.timing, texture_id: image.texture_id});
-                        }
-                    }
-                };
-
-                previous_position = event.segment_position;
-                previous_timing = timing;
+                    Err(_) => BmsFileLoader::skip(&mut report, segment_id, "09", command, "not a base 36 id".to_string()),
+                }
             }
+        }
 
-            current_bpm = current_segment_bpm;
-            segment_head = previous_timing + (1. - previous_position) * beats * BmsFileLoader::beat_duration(current_bpm);
-        };
+        events.sort_by(|a, b| a.segment_position.partial_cmp(&b.segment_position).unwrap());
+        let mut previous_position: f64 = 0.;
+        let mut previous_timing: f64 = segment_head;
+        let mut current_segment_bpm: f64 = current_bpm;
+        for event in events {
+            let position_delta = event.segment_position - previous_position;
+            let timing_delta = position_delta * beats * BmsFileLoader::beat_duration(current_segment_bpm);
+            let mut timing = previous_timing + timing_delta;
+
+            match event.event {
+                BmsEventType::Bar => bars.push(timing),
+                BmsEventType::Key(key, soundx) => sounds.push(Sound { key: key, timing: timing, wav_id: soundx, ln_end: None } ),
+                BmsEventType::LongNote(key, soundx) => long_notes.push(Sound { key: key, timing: timing, wav_id: soundx, ln_end: None } ),
+                BmsEventType::Mine(key, damage) => mines.push(Mine { key: key, timing: timing, damage: damage }),
+                BmsEventType::BpmChange(newBpm) => {
+                    current_segment_bpm = newBpm;
+                    bpms.push(BpmChange { timing: timing, bpm: newBpm} );
+                },
+                BmsEventType::Stop(length) => {
+                    let duration = length / 48. * BmsFileLoader::beat_duration(current_segment_bpm);
+                    stops.push(Stop { timing: timing, duration: duration });
+                    timing += duration;
+                },
+                BmsEventType::Bga(bmp_id) => bga_changes.push(BgaChange { timing: timing, bmp_id: bmp_id }),
+            };
 
-        println!("notes: {}", sounds.len());
-        println!("Finish BmsFileLoader.load() at {}", time::precise_time_s());
+            previous_position = event.segment_position;
+            previous_timing = timing;
+        }
 
-        Bms { bpms: bpms, bars: bars, sounds: sounds, bga: bga, textures: textures }
+        current_bpm = current_segment_bpm;
+        segment_head = previous_timing + (1. - previous_position) * beats * BmsFileLoader::beat_duration(current_bpm);
+    };
+
+    if let Some(lnobj) = script.headers().get("LNOBJ") {
+        if let Ok(id) = u32::from_str_radix(lnobj.trim(), 36) {
+            sounds = BmsFileLoader::apply_lnobj(sounds, SoundX { id: id });
+        }
     }
+    sounds.extend(BmsFileLoader::pair_long_notes(long_notes));
+    sounds.sort_by(|a, b| a.timing.partial_cmp(&b.timing).unwrap());
+
+    // what the channels above didn't read
+    let mut read_channels: HashSet<String> = ["01", "02", "03", "04", "09"].iter().map(|channel| channel.to_string()).collect();
+    for key in &keys {
+        read_channels.insert(channel_of_key(key).to_string());
+        read_channels.insert(ln_channel_of_key(key));
+        read_channels.insert(mine_channel_of_key(key));
+    }
+    for (key, values) in script.channels() {
+        let channel = &key[3..];
+        let has_objects = values.iter().any(|value| BmsFileLoader::decompose_command(value.trim()).iter().any(|command| *command != "00"));
+        if has_objects && !read_channels.contains(channel) && !report.unsupported_channels.iter().any(|unsupported| unsupported == channel) {
+            report.unsupported_channels.push(channel.to_string());
+        }
+    }
+    report.unsupported_channels.sort();
+
+    let total = match script.headers().get("TOTAL").map(|total| total.trim().parse()) {
+        Some(Ok(total)) => Some(total),
+        Some(Err(_)) => {
+            report.bad_headers.push(format!("#TOTAL {} is not a number, the default is used", script.headers()["TOTAL"]));
+            None
+        }
+        None => None,
+    };
+    report.timeline_time = time::precise_time_s() - start;
+
+    let bms = Bms {
+        bpms: bpms, stops: stops, bars: bars, sounds: sounds, mines: mines, bga_changes: bga_changes, total: total,
+        wav_files: wav_files, bmp_files: bmp_files, bga: vec![], images: vec![], samples: HashMap::new(),
+    };
+    (bms, report)
 }
 
 pub struct FixtureLoader { i: i32 }
@@ -376,7 +793,7 @@ impl FixtureLoader {
 }
 
 impl BmsLoader for FixtureLoader {
-    fn load(&self) -> Bms {
+    fn load(&self) -> io::Result<(Bms, LoadReport)> {
         let keys = vec![
             Key::P1_KEY1,
             Key::P1_KEY2,
@@ -393,24 +810,107 @@ impl BmsLoader for FixtureLoader {
         let mut v = vec![];
         for i in 0..10000 {
             v.push(
-                Sound { key: keys[i % keys.len()], timing: rng.gen_range(1f64, 1000f64), wav_id: SoundX { id: 1 } },
+                Sound { key: keys[i % keys.len()], timing: rng.gen_range(1f64, 1000f64), wav_id: SoundX { id: 1 }, ln_end: None },
             )
         }
         v.sort_by(|a, b| a.timing.partial_cmp(&b.timing).unwrap());
 
         use std::f64;
-        Bms {
+        let bms = Bms {
             sounds: v,
+            mines: Vec::new(),
             bars: (0..1000i64).map(|x| x as f64).collect(),
             bpms: (0..100000i64).map(|x| BpmChange { timing: x as f64 / 100.0, bpm: 201.0 + 200.0 * ((x as f64 / 100.0 % (f64::consts::PI * 2.0)).sin()) }).collect(),
+            stops: Vec::new(),
+            bga_changes: Vec::new(),
+            total: None,
+            wav_files: Vec::new(),
+            bmp_files: Vec::new(),
             bga: Vec::new(),
-            textures: Vec::new(),
-        }
+            images: Vec::new(),
+            samples: HashMap::new(),
+        };
+        Ok((bms, LoadReport::default()))
     }
 }
 
 #[test]
 fn loader_test() {
-    let loader = BmsFileLoader { path: "example/conflict/_01_conflict.bme".to_string() };
-    let bms = loader.load();
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    let dir = env::temp_dir().join("rust_bms_loader_test");
+    fs::create_dir_all(&dir).unwrap();
+    let chart = dir.join("chart.bme");
+    File::create(&chart).unwrap().write_all(b"\
+#BPM 120
+#WAV01 kick.wav
+#WAV02 missing.wav
+#BMP01 missing.bmp
+#STOP01 48
+#00111:0102
+#00112:0003
+#00102:abc
+#00103:0G
+#00109:02
+#00108:01
+#00107:00
+").unwrap();
+
+    let (bms, report) = BmsFileLoader::chart_only(chart.to_str().unwrap()).load().unwrap();
+    // the note of the missing sound is still there to hit
+    assert_eq!(vec![(0.0, 1), (1.0, 2)], bms.sounds.iter().map(|sound| (sound.timing, sound.wav_id.id)).collect::<Vec<_>>());
+    // the files are left as names, missing or not
+    assert_eq!(vec![(SoundX { id: 1 }, "kick.wav".to_string()), (SoundX { id: 2 }, "missing.wav".to_string())], bms.wav_files);
+    assert!(bms.samples.is_empty() && bms.images.is_empty());
+    assert_eq!(vec![
+        "measure 001 channel 02: abc skipped, not a measure length, 1 is used".to_string(),
+        "measure 001 channel 03: 0G skipped, not a hex bpm".to_string(),
+        "measure 001 channel 12: 03 skipped, no #WAV03".to_string(),
+        "measure 001 channel 09: 02 skipped, no #STOP02".to_string(),
+        "channel 08 is not played".to_string(),
+    ], report.problems());
+    assert!(!report.is_clean());
+    assert_eq!(0.0, report.asset_time);
+    assert!(report.summary().starts_with("4 objects skipped, 1 channels not played, loaded in "));
+
+    // the asset stage finds what is next to the chart, stubs stand in for the mixer and ffmpeg
+    struct NoImages;
+    impl ImageBackend for NoImages {
+        fn decode(&self, _path: &Path) -> Result<Vec<(f64, RgbaImage)>, String> {
+            Err("not decoded in this test".to_string())
+        }
+    }
+    File::create(dir.join("kick.wav")).unwrap();
+    let loader = BmsFileLoader::with_backends(chart.to_str().unwrap(), Box::new(NoSounds), Box::new(NoImages));
+    let (bms, report) = loader.load().unwrap();
+    assert_eq!(vec![SoundX { id: 1 }], bms.samples.keys().cloned().collect::<Vec<_>>());
+    assert_eq!(vec![
+        format!("#WAV02 {}: not found as .wav or .ogg", dir.join("missing.ogg").to_str().unwrap()),
+        format!("#BMP01 {}: not found", dir.join("missing.bmp").to_str().unwrap()),
+    ], report.problems()[..2].to_vec());
+    assert!(report.summary().starts_with("1 sounds missing, 1 images missing, 4 objects skipped, 1 channels not played, loaded in "));
+
+    assert!(BmsFileLoader::chart_only(dir.join("none.bme").to_str().unwrap()).load().is_err());
+}
+
+#[test]
+fn stop_test() {
+    use bms_parser::{BmsParser, BmsStringParser};
+    let script = "#BPM 120\n#WAV01 kick.wav\n#STOP01 96\n#00109:0001\n#00111:01000101\n#00211:01\n";
+    let (bms, report) = chart(&BmsStringParser { script: script.to_string() }.parse());
+    // 96/48 beats at 120 bpm, from halfway through measure 1
+    assert_eq!(vec![Stop { timing: 1.0, duration: 1.0 }], bms.stops);
+    // the note on the stop is played as it starts, everything after comes a second later
+    assert_eq!(vec![0.0, 1.0, 2.5, 3.0], bms.sounds.iter().map(|sound| sound.timing).collect::<Vec<_>>());
+    assert_eq!(vec![0.0, 3.0], bms.bars);
+    assert!(report.is_clean());
+}
+
+#[test]
+fn scroll_bpms_test() {
+    let bpms = vec![BpmChange { timing: 0.0, bpm: 100.0 }, BpmChange { timing: 10.0, bpm: 200.0 }];
+    let scroll = scroll_bpms(&bpms, &vec![Stop { timing: 5.0, duration: 2.0 }, Stop { timing: 12.0, duration: 1.0 }]);
+    assert_eq!(vec![(0.0, 100.0), (5.0, 0.0), (7.0, 100.0), (10.0, 200.0), (12.0, 0.0), (13.0, 200.0)],
+               scroll.iter().map(|change| (change.timing, change.bpm)).collect::<Vec<_>>());
 }
@@ -1,12 +1,20 @@
 use std::fs::File;
+use std::io;
 use std::io::prelude::*;
 use regex::Regex;
 use std::collections::HashMap;
-use time;
+
+// a header or channel line as written, before #IF or anything else is applied
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptLine {
+    Header(String, String),
+    Channel(String, String), // measure and channel like 00111, then the objects
+}
 
 pub struct BmsScript {
     headers: HashMap<String, String>,
     channels: HashMap<String, Vec<String>>,
+    lines: Vec<(usize, ScriptLine)>, // numbered from 1
 }
 
 impl BmsScript {
@@ -22,6 +30,10 @@ impl BmsScript {
         &self.headers
     }
 
+    pub fn lines(&self) -> &[(usize, ScriptLine)] {
+        &self.lines
+    }
+
     pub fn channel(&self, segment_id: &str, channel_id: &str) -> &Vec<String> {
         let key = format!("{0}{1}", segment_id, channel_id);
         self.channels.get(&key).unwrap()
@@ -41,54 +53,60 @@ pub struct BmsFileParser {
     pub path: String
 }
 
+impl BmsFileParser {
+    // a chart that isn't there or isn't text comes back as an error instead of a panic
+    pub fn read(&self) -> io::Result<BmsScript> {
+        let mut contents = String::new();
+        File::open(&self.path)?.read_to_string(&mut contents)?;
+        Ok(BmsStringParser { script: contents}.parse())
+    }
+}
+
 impl BmsParser for BmsFileParser {
     fn parse(&self) -> BmsScript {
-        let mut file = File::open(&self.path).expect("failed to open file");
-        let mut contents = String::new();
-        file.read_to_string(&mut contents).expect("failed to read file");
-        BmsStringParser { script: contents}.parse()
+        self.read().expect("failed to read file")
     }
 }
 
-struct BmsStringParser {
-    script: String
+pub struct BmsStringParser {
+    pub script: String
 }
 
 impl BmsParser for BmsStringParser {
     fn parse(&self) -> BmsScript {
-        println!("Start BmsStringParser::parse() at {}", time::precise_time_s());
-
         let mut headers = HashMap::new();
         let mut channels = HashMap::new();
-        for line in self.script.split('\n') {
+        let mut lines = vec![];
+        for (number, line) in self.script.split('\n').enumerate() {
             let trimmed = line.trim();
             if trimmed.is_empty() {
                 continue // empty line must be ignored
             } else {
                 match &trimmed[..1] {
                     "#" => {
-                        // command line or channel line
-                        let channel_re = Regex::new(r"^#\d{5}:.*$").unwrap();
+                        // command line or channel line, channels like the landmine D1 have letters
+                        let channel_re = Regex::new(r"^#\d{3}[0-9A-Za-z]{2}:.*$").unwrap();
                         if channel_re.is_match(&trimmed) {
-                            let key = &line[1..6];
-                            let value = &line[7..];
-                            if !channels.contains_key(key) {
-                                channels.insert(key.to_string(), vec![]);
+                            let key = trimmed[1..6].to_uppercase();
+                            let value = &trimmed[7..];
+                            if !channels.contains_key(&key) {
+                                channels.insert(key.clone(), vec![]);
                             }
-                            channels.get_mut(key).map(|x| x.push(value.to_string()));
+                            channels.get_mut(&key).map(|x| x.push(value.to_string()));
+                            lines.push((number + 1, ScriptLine::Channel(key, value.to_string())));
                         } else {
                             let tokens: Vec<&str> = trimmed.split(' ').collect();
                             let key = &tokens.get(0).unwrap()[1..];
                             let value = tokens[1..].join(" ");
                             headers.insert(key.to_string(), value.to_string());
+                            lines.push((number + 1, ScriptLine::Header(key.to_string(), value)));
                         }
                     },
                     _ => continue // comment line
                 };
             };
         };
-        println!("Finish BmsStringParser::parse() at {}", time::precise_time_s());
-        BmsScript { headers: headers, channels: channels }
+        BmsScript { headers: headers, channels: channels, lines: lines }
     }
 }
 
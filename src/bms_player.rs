@@ -1,692 +1,588 @@
 extern crate music;
 
-use time;
-
 use piston::window::WindowSettings;
 use piston::event_loop::*;
 use piston::input::*;
 use glutin_window::GlutinWindow as Window;
 use opengl_graphics::{GlGraphics, OpenGL, Texture};
+use opengl_graphics::glyph_cache::GlyphCache;
 use graphics::rectangle::square;
 
 use std::path::Path;
-use bms_loader::{self, Bms, Sound};
+use bms_loader::{self, Bms, SoundX};
+use game_session::{GameSession, PlayResult, SessionOutput};
+use replay::{Replay, ReplayEventType};
+use hispeed::HiSpeed;
+use lane_cover::LaneCover;
+use key_config::{Control, KeyBindings};
+use input::{InputAction, InputMapper, InputQueue, LatencyStats, RawInput};
+use controller::Controllers;
+use clock::{Clock, PlayClock};
+use lane_layout::LaneLayout;
+use partial_sound::{self, PartialSound};
+use practice::{Practice, PracticeLoop};
+use skin::Skin;
+use frame::{DrawCommand, Frame, TextureId};
+use play_view::{Overlay, PlayView};
 use std::collections::{HashSet, HashMap};
 use ears;
 use ears::{AudioController};
-use std::cmp;
 
 type Time = f64;
 
 pub struct BmsPlayer {
-    speed: f64,
-    bpm: f64,
-    obj_index_by_key: HashMap<bms_loader::Key, usize>,
-    event_index: usize,
-    objects_by_key: HashMap<bms_loader::Key, Vec<Draw>>,
-    events: Vec<Event>,
-    judge_index_by_key: HashMap<bms_loader::Key, usize>,
-    pushed_key_set: HashSet<bms_loader::Key>,
-    judge_display: JudgeDisplay,
-    y_offset: f64,
-    bpms: Vec<bms_loader::BpmChange>,
-    init_time: Option<f64>,
-    preset_textures: PresetTextures,
-    key_mapping: HashMap<Key, bms_loader::Key>,
-    bga_textures: Vec<Texture>,
+    hispeed: HiSpeed,
+    hispeed_display_until: Time,
+    lane_cover: LaneCover,
+    session: GameSession,
+    view: PlayView,
+    chart_end: Time,
+    clock: PlayClock,
+    input: InputMapper,
+    input_queue: InputQueue,
+    controllers: Option<Controllers>, // open while run() is
+    latency: Option<LatencyStats>, // input to judge latency, when diagnosing
     bga_id: Option<i32>,
-    judgerank: JudgeRank,
+    bars: Vec<Time>,
+    samples: HashMap<SoundX, bms_loader::Sample>,
+    partial_sounds: Vec<PartialSound>, // BGM restarted part way through after a seek or pause
+    seeked: bool,
+    practice: Option<PracticeLoop>,
     state: GameState,
-
+    recorder: Replay,
+    replaying: bool,
+    autoplay_lanes: HashSet<bms_loader::Key>,
 }
 
 #[derive(Eq, PartialEq)]
 enum GameState {
     PLAY,
     STOP,
+    FINISHED,
+    RETRY(Retry),
 }
 
-#[inline]
-fn note_info(key: bms_loader::Key) -> Option<(f64, f64, TextureLabel)> {
-    // x pos, size, color
-    use bms_loader::Key;
-    let x = key as u8;
-
-    match key {
-        Key::P1_KEY1 | Key::P1_KEY3 | Key::P1_KEY5 | Key::P1_KEY7 => {
-            Some((SCR_WIDTH + NOTES1_WIDTH * (x / 2) as f64 + NOTES2_WIDTH * ((x - 1) / 2) as f64 + OFFSET, NOTES1_WIDTH - OFFSET * 2.0, TextureLabel::NOTE_WHITE))
-        }
-        Key::P1_KEY2 | Key::P1_KEY4 | Key::P1_KEY6 => {
-            Some((SCR_WIDTH + NOTES1_WIDTH * (x / 2) as f64 + NOTES2_WIDTH * ((x - 1) / 2) as f64 + OFFSET, NOTES2_WIDTH - OFFSET * 2.0, TextureLabel::NOTE_BLUE))
-        }
-        Key::P1_SCRATCH => {
-            Some((OFFSET, SCR_WIDTH - OFFSET * 2.0, TextureLabel::NOTE_RED))
-        }
-        _ => None,
-    }
+// how to start over from the chart that is already loaded
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Retry {
+    SAME_OPTION,
+    REROLL, // RANDOM and the like get a new seed
 }
 
-#[inline]
-fn beam_info(key: bms_loader::Key) -> Option<(f64, f64, TextureLabel)> {
-    // x pos, size, color
-    use bms_loader::Key;
-    let x = key as u8;
+const AUTOPLAY_HOLD: f64 = 0.08;
+const HISPEED_DISPLAY_TIME: f64 = 1.5;
+const INPUT_UPS: u64 = 1000;
+// SEEK_BACKWARD this far into a measure goes to its start instead of the previous one
+const SEEK_BACK_GRACE: Time = 0.5;
 
-    match key {
-        Key::P1_KEY1 | Key::P1_KEY3 | Key::P1_KEY5 | Key::P1_KEY7 => {
-            Some((SCR_WIDTH + NOTES1_WIDTH * (x / 2) as f64 + NOTES2_WIDTH * ((x - 1) / 2) as f64 + OFFSET, NOTES1_WIDTH - OFFSET * 2.0, TextureLabel::WHITE_BEAM))
-        }
-        Key::P1_KEY2 | Key::P1_KEY4 | Key::P1_KEY6 => {
-            Some((SCR_WIDTH + NOTES1_WIDTH * (x / 2) as f64 + NOTES2_WIDTH * ((x - 1) / 2) as f64 + OFFSET, NOTES2_WIDTH - OFFSET * 2.0, TextureLabel::BLUE_BEAM))
-        }
-        Key::P1_SCRATCH => {
-            Some((OFFSET, SCR_WIDTH - OFFSET * 2.0, TextureLabel::RED_BEAM))
-        }
-        _ => None,
-    }
-}
+impl BmsPlayer {
+    pub fn new(
+        skin: Skin,
+        bms: &Bms,
+        clock: Box<Clock>,
+        hispeed: HiSpeed,
+        lane_cover: LaneCover,
+        key_bindings: KeyBindings,
+        layout: LaneLayout,
+    ) -> BmsPlayer {
+        let session = GameSession::new(bms);
 
-const SCR_WIDTH: f64 = 108f64;
-const NOTES1_WIDTH: f64 = 60f64;
-const NOTES2_WIDTH: f64 = 50f64;
-const NOTES_HEIGHT: f64 = 10.0;
-const BAR_HEIGHT: f64 = 1.0;
-const OFFSET: f64 = 2.5;
-const LANE_WIDTH: f64 = SCR_WIDTH + NOTES1_WIDTH * 4.0 + NOTES2_WIDTH * 3.0;
-
-fn calc_position(t: Time, bpms: &Vec<bms_loader::BpmChange>) -> f64 {
-    let mut y = 0f64;
-    let mut p_bpm = 130f64;
-    let mut p_timing = 0f64;
-    for bpm in bpms {
-        let d = if bpm.timing < t {
-            bpm.timing - p_timing
-        } else if t - p_timing > 0f64 {
-            t - p_timing
-        } else {
-            0f64
-        };
-        if d < 0f64 {
-            break;
+        let view = PlayView::new(skin, bms, &session, layout, hispeed);
+        let bars = bms.bars.clone();
+        let chart_end = bms.end();
+
+        BmsPlayer {
+            hispeed: hispeed,
+            hispeed_display_until: 0.0,
+            lane_cover: lane_cover,
+            session: session,
+            view: view,
+            chart_end: chart_end,
+            clock: PlayClock::new(clock),
+            input: InputMapper::new(key_bindings),
+            input_queue: InputQueue::new(),
+            controllers: None,
+            latency: None,
+            bga_id: None,
+            bars: bars,
+            samples: bms.samples.clone(),
+            partial_sounds: vec![],
+            seeked: false,
+            practice: None,
+            state: GameState::PLAY,
+            recorder: Replay::new("", hispeed, lane_cover),
+            replaying: false,
+            autoplay_lanes: HashSet::new(),
         }
-        y += d * p_bpm;
-        p_bpm = bpm.bpm;
-        p_timing = bpm.timing;
     }
-    y += if t - p_timing > 0f64 {
-        t - p_timing
-    } else {
-        0f64
-    } * p_bpm;
 
-    y
-}
+    fn set_hispeed(&mut self, hispeed: HiSpeed) {
+        self.hispeed = hispeed;
+        self.view.set_hispeed(hispeed);
+    }
 
-pub fn f64_eq(a: f64, b: f64) -> bool {
-    f64::abs(a - b) < 1e-9
-}
+    pub fn run(&mut self, window: &mut Window, gl: &mut GlGraphics, textures: &mut PresetTextures) -> PlayResult {
+        // frequent updates keep the loop polling the window between frames
+        let mut events = Events::new(EventSettings::new().ups(INPUT_UPS));
+        // opened here because SDL may only be touched from the thread music::start runs on
+        self.controllers = Some(Controllers::open());
 
-#[test]
-pub fn test_calc_position() {
-    let bpms = vec![
-        bms_loader::BpmChange { timing: 0f64, bpm: 100f64 },
-        bms_loader::BpmChange { timing: 10f64, bpm: 200f64 },
-        bms_loader::BpmChange { timing: 20f64, bpm: 400f64 },
-    ];
-
-    assert!(f64_eq(0f64, calc_position(0f64, &bpms)));
-    assert!(f64_eq(500f64, calc_position(5f64, &bpms)));
-    assert!(f64_eq(1000f64, calc_position(10f64, &bpms)));
-    assert!(f64_eq(2000f64, calc_position(15f64, &bpms)));
-    assert!(f64_eq(3000f64, calc_position(20f64, &bpms)));
-    assert!(f64_eq(5000f64, calc_position(25f64, &bpms)));
-    assert!(f64_eq(7000f64, calc_position(30f64, &bpms)));
-}
+        music::set_volume(music::MAX_VOLUME);
+        while let Some(e) = events.next(window) {
+            // stamped on the play clock's source as the loop takes them, and judged on the next update
+            if let Some(Button::Keyboard(key)) = e.press_args() {
+                let now = self.clock.source_now();
+                self.input_queue.push_at(RawInput::Key(key, true), now);
+            }
 
-impl BmsPlayer {
-    pub fn new(
-        preset_textures: PresetTextures,
-        bms: Bms,
-        time: Time,
-        speed: f64,
-    ) -> BmsPlayer {
-        println!("Start BmsPlayer Initialization at {}", time::precise_time_s());
-        let mut objects_by_key = HashMap::new();
-        for key in bms_loader::Key::visible_keys() {
-            objects_by_key.insert(key, vec![]);
-        }
+            if let Some(Button::Keyboard(key)) = e.release_args() {
+                let now = self.clock.source_now();
+                self.input_queue.push_at(RawInput::Key(key, false), now);
+            }
 
-        let mut events = vec![];
-        for sound in bms.sounds {
-            if bms_loader::Key::visible_keys().contains(&sound.key) {
-                if let Some((x, width, texture_label)) = note_info(sound.key) {
-                    objects_by_key.get_mut(&sound.key).unwrap().push(Draw { timing: sound.timing, x: x, y: calc_position(sound.timing, &bms.bpms), width: width, height: NOTES_HEIGHT, texture_label: texture_label, wav_id: Some(sound.wav_id) });
+            // polled every time round the loop, not only on updates
+            if let Some(ref mut controllers) = self.controllers {
+                let inputs = controllers.poll();
+                let now = self.clock.source_now();
+                for input in inputs {
+                    self.input_queue.push_at(input, now);
                 }
-            } else if sound.key == bms_loader::Key::BACK_CHORUS {
-                events.push(Event { timing: sound.timing, event_type: EventType::PlaySound(sound) });
             }
-        }
 
-        let mut bga_ends: f64 = 0.0;
-        for image in bms.bga {
-            events.push(Event {timing: image.timing, event_type: EventType::ChangeBga(Some(image.texture_id))});
-            if bga_ends < image.timing {
-                bga_ends = image.timing;
+            if let Some(r) = e.render_args() {
+                self.render(&r, gl, textures);
             }
-        }
-        events.push(Event {timing: bga_ends + 0.5, event_type: EventType::ChangeBga(None)});
 
-        objects_by_key.insert(bms_loader::Key::BACK_CHORUS, vec![]);
-        for bar in bms.bars.iter() {
-            objects_by_key.get_mut(&bms_loader::Key::BACK_CHORUS).unwrap().push(Draw { timing: *bar, x: 0.0, y: calc_position(*bar, &bms.bpms), width: 1000.0, height: BAR_HEIGHT, texture_label: TextureLabel::BACKGROUND, wav_id: None });
-        }
+            if e.update_args().is_some() {
+                self.handle_inputs();
+                self.process_event();
+            }
 
-        let mut obj_index_by_key = HashMap::new();
-        for (key, ref mut objects) in &mut objects_by_key {
-            objects.sort_by(|a, b| a.timing.partial_cmp(&b.timing).unwrap());
-            obj_index_by_key.insert(*key, 0usize);
+            if self.state != GameState::PLAY {
+                break;
+            }
         }
+        self.controllers = None;
 
-        for bpm in bms.bpms.iter() {
-            events.push(Event { timing: bpm.timing, event_type: EventType::ChangeBpm(bpm.bpm) });
+        if let Some(ref latency) = self.latency {
+            println!("{}", latency.summary());
         }
-        events.sort_by(|a, b| a.timing.partial_cmp(&b.timing).unwrap());
-        let end_timing = 1.0 + match events.last() {
-            Some(event) => event.timing,
-            None => 0.0
-        };
-        events.push(Event {timing: end_timing, event_type: EventType::EndMusic });
-
-        let mut key_mapping = HashMap::new();
-        key_mapping.insert(Key::A, bms_loader::Key::P1_SCRATCH);
-        key_mapping.insert(Key::Z, bms_loader::Key::P1_KEY1);
-        key_mapping.insert(Key::J, bms_loader::Key::P1_KEY1);
-        key_mapping.insert(Key::S, bms_loader::Key::P1_KEY2);
-        key_mapping.insert(Key::X, bms_loader::Key::P1_KEY3);
-        key_mapping.insert(Key::K, bms_loader::Key::P1_KEY3);
-        key_mapping.insert(Key::D, bms_loader::Key::P1_KEY4);
-        key_mapping.insert(Key::C, bms_loader::Key::P1_KEY5);
-        key_mapping.insert(Key::L, bms_loader::Key::P1_KEY5);
-        key_mapping.insert(Key::F, bms_loader::Key::P1_KEY6);
-        key_mapping.insert(Key::V, bms_loader::Key::P1_KEY7);
-        key_mapping.insert(Key::Semicolon, bms_loader::Key::P1_KEY7);
-
-        println!("Finish BmsPlayer Initialization at {}", time::precise_time_s());
-        BmsPlayer {
-            speed: speed,
-            bpm: 130f64,
-            obj_index_by_key: obj_index_by_key.clone(),
-            event_index: 0usize,
-            objects_by_key: objects_by_key,
-            events: events,
-            judge_index_by_key: obj_index_by_key.clone(),
-            pushed_key_set: HashSet::new(),
-            judge_display: JudgeDisplay::new(),
-            y_offset: 0f64,
-            bpms: bms.bpms,
-            init_time: None,
-            preset_textures: preset_textures,
-            key_mapping: key_mapping,
-            bga_textures: bms.textures,
-            bga_id: None,
-            judgerank: IIDX_JUDGERANK,
-            state: GameState::PLAY
+        if let Some(ref practice) = self.practice {
+            println!("{}", practice.summary());
         }
+        if let GameState::RETRY(_) = self.state {
+            partial_sound::halt_all();
+        }
+        self.result()
     }
 
-    pub fn run(&mut self, window: &mut Window, gl: &mut GlGraphics) {
-        let mut events = Events::new(EventSettings::new());
+    // reports how long presses wait between arriving and being judged
+    pub fn enable_latency_report(&mut self) {
+        self.latency = Some(LatencyStats::new());
+    }
 
-        music::set_volume(music::MAX_VOLUME);
-        while let Some(e) = events.next(window) {
-            if let Some(u) = e.update_args() {
-                self.update(&u);
+    // feeds the recorded inputs through the event queue instead of the keyboard
+    pub fn load_replay(&mut self, replay: &Replay) {
+        self.set_hispeed(replay.hispeed);
+        self.lane_cover = replay.lane_cover;
+        self.replaying = true;
+        self.set_autoplay(&replay.autoplay.iter().map(|key| *key).collect());
+        for event in &replay.events {
+            match event.event_type {
+                ReplayEventType::KeyDown(key) => self.session.schedule_input(event.timing, key, true),
+                ReplayEventType::KeyUp(key) => self.session.schedule_input(event.timing, key, false),
+                ReplayEventType::ChangeSpeed(speed) => self.session.schedule_speed(event.timing, speed),
+                ReplayEventType::ChangeCover(cover) => self.session.schedule_cover(event.timing, cover),
             }
+        }
+    }
 
-            if let Some(r) = e.render_args() {
-                self.render(&r, gl);
+    // schedules a perfectly timed press and release for every note in the given lanes
+    pub fn set_autoplay(&mut self, lanes: &HashSet<bms_loader::Key>) {
+        let visible_keys = bms_loader::Key::visible_keys();
+        for key in lanes.intersection(&visible_keys) {
+            if !self.autoplay_lanes.insert(*key) {
+                continue;
             }
-
-            if let Some(Button::Keyboard(key)) = e.press_args() {
-                self.on_key_down(&key);
+            self.recorder.autoplay.push(*key);
+
+            let mut inputs = vec![];
+            {
+                let notes = self.session.notes(*key);
+                for (i, note) in notes.iter().enumerate() {
+                    let next_timing = notes.get(i + 1).map_or(note.timing + AUTOPLAY_HOLD * 2.0, |next| next.timing);
+                    let hold = match note.ln_end {
+                        Some(end) => end - note.timing,
+                        None => f64::min(AUTOPLAY_HOLD, (next_timing - note.timing) / 2.0),
+                    };
+                    inputs.push((note.timing, true));
+                    inputs.push((note.timing + hold, false));
+                }
             }
-
-            if let Some(Button::Keyboard(key)) = e.release_args() {
-                self.on_key_up(&key);
+            for (timing, pressed) in inputs {
+                self.session.schedule_input(timing, *key, pressed);
             }
+        }
+    }
 
-            self.process_event();
+    pub fn is_autoplay(&self) -> bool {
+        !self.autoplay_lanes.is_empty()
+    }
 
-            if self.state == GameState::STOP {
-                break;
-            }
-        }
+    pub fn lane_cover(&self) -> LaneCover {
+        self.lane_cover
     }
 
-    fn render(&mut self, args: &RenderArgs, gl: &mut GlGraphics) {
-        let pt = self.get_precise_time();
-        self.y_offset = calc_position(pt, &self.bpms);
+    pub fn recorded_replay(&self) -> &Replay {
+        &self.recorder
+    }
 
-        use graphics::*;
+    pub fn is_replaying(&self) -> bool {
+        self.replaying
+    }
 
-        let digits = &self.preset_textures.digits;
-        let textures_map = &self.preset_textures.lane_components;
+    pub fn result(&self) -> PlayResult {
+        self.session.result()
+    }
 
-        let width = args.width as f64;
-        let height = args.height as f64;
+    pub fn hispeed(&self) -> HiSpeed {
+        self.hispeed
+    }
 
-        // drawable objects
-        let mut drawings = vec![];
-        for (key, objects) in &self.objects_by_key {
-            let start = *self.obj_index_by_key.get(key).unwrap();
-            let judge_consumed = *self.judge_index_by_key.get(key).unwrap_or(&0usize);
-            let start = cmp::max(start, judge_consumed);
+    // set when play was left with a retry key
+    pub fn retry(&self) -> Option<Retry> {
+        match self.state {
+            GameState::RETRY(retry) => Some(retry),
+            _ => None,
+        }
+    }
 
-            let mut next_start = start;
-            for draw in &objects[start..objects.len()] {
-                let y = (draw.y - self.y_offset) * self.speed;
-                let y = height - y;
+    // freezes play time and stops everything that is sounding
+    pub fn pause(&mut self) {
+        self.clock.pause();
+        partial_sound::halt_all();
+        self.partial_sounds.clear();
+    }
 
-                if y > height {
-                    next_start += 1;
-                } else {
-                    drawings.push(DrawInfo { x: draw.x, y: y - NOTES_HEIGHT, width: draw.width, height: draw.height, texture_label: draw.texture_label });
-                }
-                if y < 0.0 {
-                    break;
-                }
-            }
-            *self.obj_index_by_key.get_mut(key).unwrap() = next_start;
+    pub fn resume(&mut self) {
+        self.clock.resume();
+        let pt = self.get_precise_time();
+        for (wav_id, offset) in self.session.sounding_at(pt) {
+            self.play_partial(wav_id, offset);
         }
+    }
 
-        let judge_texture = if pt <= self.judge_display.show_until {
-            if let Some(judge) = self.judge_display.judge {
-                let mut x = match judge {
-                    Judge::PGREAT | Judge::GREAT | Judge::GOOD => self.judge_display.combo,
-                    _ => 0
-                };
-                let digits = if x > 0 {
-                    let mut v = Vec::new();
-                    while x > 0 {
-                        v.push(x % 10);
-                        x /= 10;
-                    }
-                    v.reverse();
-                    v
-                } else {
-                    Vec::new()
-                };
-
-                Some((match judge {
-                    Judge::PGREAT => TextureLabel::JUDGE_PERFECT,
-                    Judge::GREAT => TextureLabel::JUDGE_GREAT,
-                    Judge::GOOD => TextureLabel::JUDGE_GOOD,
-                    Judge::BAD => TextureLabel::JUDGE_BAD,
-                    Judge::POOR => TextureLabel::JUDGE_POOR,
-                    Judge::MISSED => TextureLabel::JUDGE_POOR,
-                }, digits))
-            } else {
-                None
-            }
-        } else { None };
-
-        let pushed_key_set = &self.pushed_key_set;
-        let bga_map = &self.bga_textures;
-        let bga = self.bga_id;
-
-        gl.draw(args.viewport(), |mut c, gl| {
-            // back ground
-            let image = Image::new().rect(rectangle::rectangle_by_corners(0.0, 0.0, width, height));
-            image.draw(&textures_map[&TextureLabel::BACKGROUND], &DrawState::new_alpha(), c.transform, gl);
-
-            // lanes
-            let image = Image::new().rect(rectangle::rectangle_by_corners(0.0, 0.0, LANE_WIDTH, height));
-            image.draw(&textures_map[&TextureLabel::LANE_BG], &DrawState::new_alpha(), c.transform, gl);
-
-            // beams
-            for pushed_key in pushed_key_set {
-                if let Some((x, beam_width, texture_label)) = beam_info(*pushed_key) {
-                    let image = Image::new().rect(rectangle::rectangle_by_corners(0.0, 0.0, beam_width, height - 14f64));
-                    image.draw(&textures_map[&texture_label], &DrawState::new_alpha(), c.transform.trans(x, 0f64), gl)
-                }
-            }
+    pub fn is_paused(&self) -> bool {
+        self.clock.is_paused()
+    }
 
-            // notes and bars
-            for draw in &drawings {
-                let image = Image::new().rect(rectangle::rectangle_by_corners(0.0, 0.0, draw.width, draw.height));
-                image.draw(&textures_map[&draw.texture_label], &DrawState::new_alpha(), c.transform.trans(draw.x, draw.y - draw.height / 2.0), gl);
-            }
-
-            // bga
-            bga.map(|id| {
-                let size = if width - LANE_WIDTH < height { width - LANE_WIDTH } else { height };
-                let image = Image::new().rect(rectangle::rectangle_by_corners(0.0, 0.0, size, size));
-                image.draw(&bga_map[id as usize], &DrawState::new_alpha(), c.transform.trans(LANE_WIDTH, 0f64), gl)
-            });
-
-            // judge
-            if let Some((texture_label, ref combo_digits)) = judge_texture {
-                let (w, h) = textures_map[&texture_label].get_size();
-                let scale = 2.0;
-                let w = w as f64 * scale;
-                let h = h as f64 * scale;
-                let mut combined = CombinedTexture::new();
-                combined.add(TextureDisplay {texture: &textures_map[&texture_label], w: w, h: h});
-
-                for &digit in combo_digits {
-                    let digit = &digits[digit as usize];
-                    // using the width of '0' for all digits to be monospaced...
-                    let (dw, dh) = digits[0usize].get_size();
-                    let scale = h as f64 / dh as f64;
-                    let dw = dw as f64 * scale;
-                    let dh = dh as f64 * scale;
-
-                    combined.add(TextureDisplay {texture: digit, w: dw, h: dh});
-                }
+    // notes before pt are skipped, not judged, so a play that seeked isn't scored
+    pub fn seek(&mut self, pt: Time) {
+        let pt = f64::max(pt, 0.0);
+        partial_sound::halt_all();
+        self.partial_sounds.clear();
+        self.clock.seek(pt);
+        self.session.seek(pt);
+        // render skips forward again from the first object to what is still on screen
+        self.view.rewind();
+        self.seeked = true;
+    }
 
-                let lx = (LANE_WIDTH - combined.get_w()) / 2.0;
-                combined.draw(&mut c, gl, lx, 0.7 * height as f64);
-            }
-        });
+    // to the n-th bar line, the start of the chart if there are not that many
+    pub fn seek_measure(&mut self, measure: usize) {
+        let pt = self.bars.get(measure).map_or(0.0, |bar| *bar);
+        self.seek(pt);
     }
 
-    fn update(&mut self, args: &UpdateArgs) {
-        if self.bpm < 1.0 {
-            self.bpm = 1.0;
-        }
+    pub fn has_seeked(&self) -> bool {
+        self.seeked
     }
 
-    fn on_key_down(&mut self, key: &Key) {
-        let down = match *key {
-            Key::Up => {
-                self.speed += 0.1;
-                None
-            }
-            Key::Down => {
-                self.speed -= 0.1;
-                None
-            }
-            Key::Escape => {
-                self.state = GameState::STOP;
-                None
-            }
-            Key::Space => {
-                None
-            }
-            _ => {
-                self.key_mapping.get(key).map(|op| *op)
+    // loops over the measure range until quit, judges start over every loop
+    pub fn set_practice(&mut self, practice: Practice) {
+        let mut practice_loop = PracticeLoop::new(practice, &self.bars, self.chart_end);
+        let (start, end) = (practice_loop.start(), practice_loop.end());
+        let notes = self.session.judges_between(start, end);
+        practice_loop.set_notes(notes);
+        self.clock.set_rate(practice_loop.rate());
+        self.practice = Some(practice_loop);
+        self.seek(start);
+    }
+
+    fn next_loop(&mut self) {
+        let start = match self.practice {
+            Some(ref mut practice) => {
+                // the loop goes back at its end, notes up to it that were not hit count as missed
+                self.session.miss_before(practice.end());
+                practice.finish_loop(self.session.result());
+                practice.start()
             }
+            None => return,
         };
+        // seeking starts the judges over
+        self.seek(start);
+    }
 
-        // judge
-        if let Some(note_key) = down {
-            let pt = self.get_precise_time();
-            if !self.pushed_key_set.contains(&note_key) {
-                self.pushed_key_set.insert(note_key);
-
-                while let Some(mut index) = self.judge_index_by_key.get_mut(&note_key) {
-                    match self.objects_by_key[&note_key].get(*index) {
-                        Some(note) => {
-                            let timing = note.timing;
-                            if pt <= timing + 2.0 {
-                                let time_diff = timing - pt;
-                                if let Some(judge) = self.judgerank.get_judge(f64::abs(time_diff)) {
-                                    self.judge_display.update_judge(judge, pt);
-                                    *index += 1;
-                                }
-                                if let Some(wav_id) = note.wav_id {
-                                    music::play_sound(&wav_id, music::Repeat::Times(0));
-                                }
-                                break;
-                            }
-                        }
-                        None => break
-                    }
-                    *index += 1;
-                }
+    fn play_partial(&mut self, wav_id: SoundX, offset: Time) {
+        if let Some(sample) = self.samples.get(&wav_id) {
+            match PartialSound::play(&sample.path, offset) {
+                Ok(sound) => self.partial_sounds.push(sound),
+                Err(e) => println!("failed to resume {}: {}", sample.path.to_str().unwrap_or(""), e),
             }
         }
     }
 
-    pub fn get_precise_time(&mut self) -> f64 {
-        if let Some(init_t) = self.init_time {
-            (time::precise_time_s() - init_t) as f64
-        } else {
-            self.init_time = Some(time::precise_time_s());
-            0.0f64
+    fn render(&mut self, args: &RenderArgs, gl: &mut GlGraphics, textures: &mut PresetTextures) {
+        let pt = self.get_precise_time();
+        let overlay = self.overlay(pt);
+        let frame = self.view.frame(pt, &self.session, &overlay, args.width as f64, args.height as f64);
+        textures.draw_frame(&frame, self.view.skin(), args, gl);
+    }
+
+    // the frame at a play time without a window or sound, for rendering offline.
+    // times have to come in order, as the session only moves forward
+    pub fn frame_at(&mut self, pt: Time, width: f64, height: f64) -> Frame {
+        self.session.update(pt);
+        for output in self.session.drain_outputs() {
+            self.show_output(output);
         }
+        let overlay = self.overlay(pt);
+        self.view.frame(pt, &self.session, &overlay, width, height)
     }
 
-    pub fn process_event(&mut self) {
-        let pt = self.get_precise_time();
+    pub fn skin(&self) -> &Skin {
+        self.view.skin()
+    }
 
-        // process missed notes
-        for note_key in bms_loader::Key::visible_keys() {
-            while let Some(mut index) = self.judge_index_by_key.get_mut(&note_key) {
-                match self.objects_by_key[&note_key].get(*index) {
-                    Some(note) => {
-                        let timing = note.timing;
-                        if pt > timing + self.judgerank.bad {
-                            self.judge_display.update_judge(Judge::MISSED, pt);
-                            *index += 1;
-                        } else {
-                            break;
-                        }
-                    }
-                    None => break
-                }
-            }
+    fn overlay(&self, pt: Time) -> Overlay {
+        Overlay {
+            hispeed: self.hispeed,
+            lane_cover: self.lane_cover,
+            show_hispeed: pt <= self.hispeed_display_until,
+            bga: self.bga_id,
+            paused: self.clock.is_paused(),
+            latency: self.latency.as_ref().and_then(|latency| {
+                latency.last().map(|last| format!("LATENCY {:.2}ms  AVG {:.2}ms  MAX {:.2}ms", last * 1000.0, latency.average().unwrap() * 1000.0, latency.max().unwrap() * 1000.0))
+            }),
+            practice: self.practice.as_ref().map(|practice| practice.status_text()),
         }
+    }
 
-        // process events
-        let start = self.event_index;
-        for event in &self.events[start..self.events.len()] {
-            if event.timing <= pt {
-                self.event_index += 1;
-                match event.event_type {
-                    EventType::ChangeBpm(ref x) => {
-                        self.bpm = *x;
-                    }
-                    EventType::PlaySound(ref snd) => {
-                        music::play_sound(&snd.wav_id, music::Repeat::Times(0));
-                        //                        println!("sound: expected = {}, actual = {}", event.timing, pt);
-                    }
-                    EventType::ChangeBga(ref id) => {
-                        self.bga_id = *id;
-                    }
-                    EventType::EndMusic => {
-                        self.state = GameState::STOP;
-                    }
+    // judges queued inputs at the time they arrived rather than now
+    fn handle_inputs(&mut self) {
+        for timed in self.input_queue.drain() {
+            let pt = self.clock.at(timed.time);
+            for action in self.input.feed(timed.input, pt) {
+                self.on_action(action, pt);
+                if let (InputAction::Press(_), Some(latency)) = (action, self.latency.as_mut()) {
+                    let now = self.clock.source_now();
+                    latency.push(now - timed.time);
                 }
-            } else {
-                break;
             }
         }
+
+        let pt = self.get_precise_time();
+        for action in self.input.tick(pt) {
+            self.on_action(action, pt);
+        }
     }
 
-    fn on_key_up(&mut self, key: &Key) {
-        let up = match *key {
-            Key::Up => {
-                None
-            }
-            Key::Down => {
-                None
+    fn on_action(&mut self, action: InputAction, pt: Time) {
+        match action {
+            InputAction::Control(control) => self.on_control(control),
+            // lanes driven by the replay or autoplay ignore the player, and nothing is played while paused
+            _ if self.replaying || self.clock.is_paused() => (),
+            InputAction::Press(note_key) if !self.autoplay_lanes.contains(&note_key) => {
+                self.recorder.push(pt, ReplayEventType::KeyDown(note_key));
+                self.session.press(note_key, pt);
             }
-            _ => {
-                self.key_mapping.get(key)
+            InputAction::Release(note_key) if !self.autoplay_lanes.contains(&note_key) => {
+                self.recorder.push(pt, ReplayEventType::KeyUp(note_key));
+                self.session.release(note_key, pt);
             }
-        };
-
-        if let Some(key) = up {
-            self.pushed_key_set.remove(&key);
+            _ => (),
         }
     }
-}
-
-struct Event {
-    timing: Time,
-    event_type: EventType
-}
-
-enum EventType {
-    ChangeBpm(f64),
-    PlaySound(bms_loader::Sound),
-    ChangeBga(Option<i32>),
-    EndMusic
-}
-
-#[derive(Clone)]
-struct Draw {
-    pub timing: Time,
-    pub y: f64,
-    pub x: f64,
-    pub width: f64,
-    pub height: f64,
-    pub texture_label: TextureLabel,
-    pub wav_id: Option<bms_loader::SoundX>
-}
-
-struct DrawInfo {
-    pub x: f64,
-    pub y: f64,
-    pub width: f64,
-    pub height: f64,
-    pub texture_label: TextureLabel,
-}
-
-pub struct PresetTextures {
-    pub lane_components: HashMap<TextureLabel, Texture>,
-    pub digits: Vec<Texture>,
-}
-
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
-pub enum TextureLabel {
-    BACKGROUND,
-    LANE_BG,
-    NOTE_BLUE,
-    NOTE_RED,
-    NOTE_WHITE,
-    JUDGE_PERFECT,
-    JUDGE_GREAT,
-    JUDGE_GOOD,
-    JUDGE_BAD,
-    JUDGE_POOR,
-    RED_BEAM,
-    WHITE_BEAM,
-    BLUE_BEAM,
-}
 
-#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
-enum Judge {
-    PGREAT,
-    GREAT,
-    GOOD,
-    BAD,
-    POOR,
-    MISSED,
-}
-
-impl Judge {
-    fn combo_lasts(judge: Judge) -> bool {
-        match judge {
-            Judge::PGREAT | Judge::GREAT | Judge::GOOD | Judge::POOR => true,
-            _ => false
+    fn on_control(&mut self, control: Control) {
+        // a replay can still be paused, skipped through, restarted and quit
+        let changes_play = match control {
+            Control::PAUSE | Control::SEEK_FORWARD | Control::SEEK_BACKWARD | Control::RETRY | Control::RETRY_REROLL | Control::QUIT => false,
+            _ => true,
+        };
+        if self.replaying && changes_play {
+            return;
+        }
+        match control {
+            Control::SPEED_UP => {
+                let value = self.hispeed.faster();
+                self.change_speed(value);
+            }
+            Control::SPEED_DOWN => {
+                let value = self.hispeed.slower();
+                self.change_speed(value);
+            }
+            Control::SUDDEN_PLUS => {
+                let cover = self.lane_cover.adjust_sudden(1.0);
+                self.change_cover(cover);
+            }
+            Control::SUDDEN_MINUS => {
+                let cover = self.lane_cover.adjust_sudden(-1.0);
+                self.change_cover(cover);
+            }
+            Control::HIDDEN_PLUS => {
+                let cover = self.lane_cover.adjust_hidden(1.0);
+                self.change_cover(cover);
+            }
+            Control::HIDDEN_MINUS => {
+                let cover = self.lane_cover.adjust_hidden(-1.0);
+                self.change_cover(cover);
+            }
+            Control::LIFT_PLUS => {
+                let cover = self.lane_cover.adjust_lift(1.0);
+                self.change_cover(cover);
+            }
+            Control::LIFT_MINUS => {
+                let cover = self.lane_cover.adjust_lift(-1.0);
+                self.change_cover(cover);
+            }
+            Control::PAUSE => {
+                if self.clock.is_paused() {
+                    self.resume();
+                } else {
+                    self.pause();
+                }
+            }
+            Control::SEEK_FORWARD => {
+                let pt = self.get_precise_time();
+                let next_bar = self.bars.iter().find(|bar| **bar > pt).map(|bar| *bar);
+                if let Some(bar) = next_bar {
+                    self.seek(bar);
+                }
+            }
+            Control::SEEK_BACKWARD => {
+                let pt = self.get_precise_time();
+                let bar = self.bars.iter().rev().find(|bar| **bar < pt - SEEK_BACK_GRACE).map_or(0.0, |bar| *bar);
+                self.seek(bar);
+            }
+            Control::RETRY => {
+                self.state = GameState::RETRY(Retry::SAME_OPTION);
+            }
+            Control::RETRY_REROLL => {
+                self.state = GameState::RETRY(Retry::REROLL);
+            }
+            Control::QUIT => {
+                self.state = GameState::STOP;
+            }
         }
     }
 
-    fn consume_note(&self) -> bool {
-        *self != Judge::POOR
+    fn change_speed(&mut self, value: f64) {
+        let pt = self.get_precise_time();
+        self.recorder.push(pt, ReplayEventType::ChangeSpeed(value));
+        self.hispeed.value = value;
+        self.hispeed_display_until = pt + HISPEED_DISPLAY_TIME;
     }
-}
 
-struct JudgeRank {
-    pub pgreat: f64,
-    pub great: f64,
-    pub good: f64,
-    pub bad: f64,
-    pub poor: f64,
-}
-
-impl JudgeRank {
-    fn get_judge(&self, d: Time) -> Option<Judge> {
-        if d < self.poor {
-            Some(if d < self.pgreat {
-                Judge::PGREAT
-            } else if d < self.great {
-                Judge::GREAT
-            } else if d < self.good {
-                Judge::GOOD
-            } else if d < self.bad {
-                Judge::BAD
-            } else {
-                Judge::POOR
-            })
-        } else {
-            None
-        }
+    fn change_cover(&mut self, cover: LaneCover) {
+        let pt = self.get_precise_time();
+        self.recorder.push(pt, ReplayEventType::ChangeCover(cover));
+        self.lane_cover = cover;
+        self.hispeed_display_until = pt + HISPEED_DISPLAY_TIME;
     }
-}
 
-const IIDX_JUDGERANK: JudgeRank = JudgeRank { pgreat: 0.02, great: 0.04, good: 0.105, bad: 0.15, poor: 0.2 };
+    pub fn get_precise_time(&mut self) -> f64 {
+        self.clock.now()
+    }
 
-struct JudgeDisplay {
-    judge: Option<Judge>,
-    pub show_until: Time,
-    count: HashMap<Judge, u32>,
-    combo: u32
-}
+    // advances the session and plays, shows and applies what came out of it
+    pub fn process_event(&mut self) {
+        let pt = self.get_precise_time();
+        if self.practice.as_ref().map_or(false, |practice| practice.is_over(pt)) {
+            self.next_loop();
+        }
+        let pt = self.get_precise_time();
+        self.session.update(pt);
+
+        for output in self.session.drain_outputs() {
+            match output {
+                // sounds whose file was missing were never bound
+                SessionOutput::PlaySound(wav_id) => if self.samples.contains_key(&wav_id) {
+                    music::play_sound(&wav_id, music::Repeat::Times(0));
+                },
+                // resume() starts them when seeking while paused
+                SessionOutput::ResumeSound(wav_id, offset) => {
+                    if !self.clock.is_paused() {
+                        self.play_partial(wav_id, offset);
+                    }
+                }
+                output => self.show_output(output),
+            }
+        }
 
-impl JudgeDisplay {
-    pub fn new() -> JudgeDisplay {
-        JudgeDisplay { judge: None, show_until: 0.0, count: HashMap::new(), combo: 0u32 }
+        if self.session.is_finished() {
+            self.state = GameState::FINISHED;
+        }
     }
 
-    pub fn update_judge(&mut self, judge: Judge, t: Time) {
-        if let Some(prev) = self.judge {
-            if Judge::combo_lasts(prev) && Judge::combo_lasts(judge) {
-                self.combo += 1;
-            } else {
-                self.combo = 0;
+    // applies the outputs that only change what is on screen
+    fn show_output(&mut self, output: SessionOutput) {
+        match output {
+            SessionOutput::ChangeBga(id) => {
+                self.bga_id = id;
+            }
+            SessionOutput::ChangeSpeed(value, timing) => {
+                self.hispeed.value = value;
+                self.hispeed_display_until = timing + HISPEED_DISPLAY_TIME;
             }
+            SessionOutput::ChangeCover(cover, timing) => {
+                self.lane_cover = cover;
+                self.hispeed_display_until = timing + HISPEED_DISPLAY_TIME;
+            }
+            _ => (),
         }
-        *self.count.entry(judge).or_insert(0) += 1;
-        self.judge = Some(judge);
-        self.show_until = t + 1.0;
     }
-}
 
-struct TextureDisplay<'a> {
-    texture: &'a Texture,
-    w: f64,
-    h: f64,
 }
 
-struct CombinedTexture<'a> {
-    texture_displays: Vec<TextureDisplay<'a>>,
-    total_w: f64,
+// the GL side of the skin and chart images, loaded once and kept over retries
+pub struct PresetTextures {
+    pub textures: Vec<Texture>, // in the order of skin.files
+    pub bga: Vec<Texture>,
+    pub glyphs: GlyphCache<'static>,
 }
 
 use graphics::*;
-impl <'a>CombinedTexture<'a> {
-    pub fn new() -> CombinedTexture<'a> {
-        CombinedTexture {texture_displays: vec![], total_w: 0.0}
-    }
-
-    pub fn add(&mut self, texture: TextureDisplay<'a>) {
-        self.total_w += texture.w;
-        self.texture_displays.push(texture);
-    }
-
-    pub fn get_w(&self) -> f64 {
-        self.total_w
-    }
-
-    pub fn draw(&self, c: &mut Context, gl: &mut GlGraphics, x: f64, y: f64) {
-        let mut w = 0.0;
-        for t in &self.texture_displays {
-            let image = Image::new().rect(rectangle::rectangle_by_corners(0.0, 0.0, t.w, t.h));
-            image.draw(t.texture, &DrawState::new_alpha(), c.transform.trans(x + w, y), gl);
-            w += t.w;
-        }
+impl PresetTextures {
+    pub fn draw_frame(&mut self, frame: &Frame, skin: &Skin, args: &RenderArgs, gl: &mut GlGraphics) {
+        let textures = &self.textures;
+        let bga = &self.bga;
+        let glyphs = &mut self.glyphs;
+        gl.draw(args.viewport(), |c, gl| {
+            for command in &frame.commands {
+                match *command {
+                    DrawCommand::Image { texture, rect, alpha } => {
+                        let (texture, src) = match texture {
+                            TextureId::Skin(id) => (&textures[skin.images[id].file], skin.images[id].src),
+                            TextureId::Bga(id) => match bga.get(id) {
+                                Some(texture) => (texture, None),
+                                None => continue,
+                            },
+                        };
+                        let mut image = Image::new_color([1.0, 1.0, 1.0, alpha]).rect([rect.x, rect.y, rect.w, rect.h]);
+                        if let Some(src) = src {
+                            image = image.src_rect([src.x, src.y, src.w, src.h]);
+                        }
+                        image.draw(texture, &DrawState::new_alpha(), c.transform, gl);
+                    }
+                    DrawCommand::Fill { color, rect } => {
+                        rectangle(color, [rect.x, rect.y, rect.w, rect.h], c.transform, gl);
+                    }
+                    DrawCommand::Text { ref text, color, size, x, y } => {
+                        Text::new_color(color, size).draw(text, glyphs, &DrawState::new_alpha(), c.transform.trans(x, y), gl);
+                    }
+                }
+            }
+        });
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,163 @@
+use bms_loader::{Bms, Key};
+use frame::Frame;
+use skin::Rect;
+
+type Time = f64;
+
+pub const DENSITY_STEP: Time = 1.0; // seconds per density bucket
+const CHORD_WINDOW: Time = 0.001; // notes closer than this are pressed together
+
+const GRAPH_BG: [f32; 4] = [0.0, 0.0, 0.0, 0.6];
+const DENSITY_COLOR: [f32; 4] = [0.3, 0.7, 1.0, 0.9];
+const BPM_COLOR: [f32; 4] = [0.2, 1.0, 0.2, 1.0];
+const MISS_COLOR: [f32; 4] = [1.0, 0.2, 0.2, 0.7];
+const MIN_DENSITY_SCALE: f64 = 10.0; // notes per second at the top, unless the chart goes higher
+
+// what the notes of a measure ask for
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MeasureStats {
+    pub notes: u32,
+    pub scratch: u32,
+    pub long_notes: u32,
+    pub chords: u32, // two or more lanes at once, counted once each
+}
+
+// where a chart spikes, from the loaded notes alone
+pub struct ChartAnalysis {
+    pub density: Vec<f64>, // notes per second, one for each DENSITY_STEP from the start
+    pub bpms: Vec<(Time, f64)>, // from the start to the end, a step each change
+    pub measures: Vec<MeasureStats>,
+    pub length: Time,
+}
+
+fn is_scratch(key: Key) -> bool {
+    key == Key::P1_SCRATCH || key == Key::P2_SCRATCH
+}
+
+// measure numbers count bars at or before the timing, notes before the first bar go in the first
+fn measure_at(bars: &[Time], timing: Time) -> usize {
+    bars.iter().take_while(|bar| **bar <= timing + CHORD_WINDOW).count().max(1) - 1
+}
+
+impl ChartAnalysis {
+    pub fn new(bms: &Bms) -> ChartAnalysis {
+        let visible = Key::visible_keys();
+        let notes: Vec<_> = bms.sounds.iter().filter(|sound| visible.contains(&sound.key)).collect();
+        let length = bms.end();
+
+        let mut density = vec![0.0; (length / DENSITY_STEP) as usize + 1];
+        for note in &notes {
+            density[(note.timing / DENSITY_STEP) as usize] += 1.0 / DENSITY_STEP;
+        }
+
+        let mut bpms: Vec<(Time, f64)> = bms.bpms.iter().map(|change| (change.timing, change.bpm)).collect();
+        bpms.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let mut measures = vec![MeasureStats::default(); bms.bars.len().max(1)];
+        let mut previous: Option<Time> = None;
+        let mut in_chord = false;
+        for note in &notes {
+            let measure = &mut measures[measure_at(&bms.bars, note.timing)];
+            measure.notes += 1;
+            if is_scratch(note.key) {
+                measure.scratch += 1;
+            }
+            if note.ln_end.is_some() {
+                measure.long_notes += 1;
+            }
+            // sounds are sorted by timing, so a chord is a run of notes at the same time
+            let together = previous.map_or(false, |previous| note.timing - previous < CHORD_WINDOW);
+            if together && !in_chord {
+                measure.chords += 1;
+            }
+            in_chord = together;
+            previous = Some(note.timing);
+        }
+
+        ChartAnalysis { density: density, bpms: bpms, measures: measures, length: length }
+    }
+
+    // the most notes in any DENSITY_STEP
+    pub fn peak_density(&self) -> f64 {
+        self.density.iter().fold(0.0, |peak, density| f64::max(peak, *density))
+    }
+
+    // density as bars, bpm as a line over them and each miss as a red mark, squeezed into rect
+    pub fn graph(&self, frame: &mut Frame, rect: Rect, misses: &[Time]) {
+        frame.fill(GRAPH_BG, rect);
+        if self.length <= 0.0 {
+            return;
+        }
+        let x_at = |timing: Time| rect.x + rect.w * f64::min(timing / self.length, 1.0);
+
+        let scale = f64::max(self.peak_density(), MIN_DENSITY_SCALE);
+        for (i, density) in self.density.iter().enumerate() {
+            let (start, end) = (x_at(i as f64 * DENSITY_STEP), x_at((i + 1) as f64 * DENSITY_STEP));
+            let h = rect.h * density / scale;
+            frame.fill(DENSITY_COLOR, Rect::new(start, rect.y + rect.h - h, end - start, h));
+        }
+
+        // flat bpm charts sit in the middle
+        let min = self.bpms.iter().fold(::std::f64::MAX, |min, &(_, bpm)| f64::min(min, bpm));
+        let max = self.bpms.iter().fold(0.0, |max, &(_, bpm)| f64::max(max, bpm));
+        let y_at = |bpm: f64| if max > min {
+            rect.y + rect.h - 1.0 - (rect.h - 2.0) * (bpm - min) / (max - min)
+        } else {
+            rect.y + rect.h / 2.0
+        };
+        for (i, &(timing, bpm)) in self.bpms.iter().enumerate() {
+            let end = self.bpms.get(i + 1).map_or(self.length, |next| next.0);
+            if end > timing {
+                frame.fill(BPM_COLOR, Rect::new(x_at(timing), y_at(bpm) - 1.0, x_at(end) - x_at(timing), 2.0));
+            }
+        }
+
+        for miss in misses {
+            frame.fill(MISS_COLOR, Rect::new(x_at(*miss), rect.y, 1.0, rect.h));
+        }
+    }
+}
+
+#[test]
+fn chart_analysis_test() {
+    use bms_loader::BpmChange;
+    use game_session::{test_bms, test_sound};
+
+    let mut long_note = test_sound(Key::P1_KEY2, 2.5, 1);
+    long_note.ln_end = Some(3.5);
+    let mut bms = test_bms(vec![
+        test_sound(Key::P1_SCRATCH, 0.5, 1),
+        test_sound(Key::P1_KEY1, 0.5, 1),
+        test_sound(Key::P1_KEY3, 0.5, 1),
+        test_sound(Key::P1_KEY1, 0.8, 1),
+        test_sound(Key::BACK_CHORUS, 1.0, 1),
+        test_sound(Key::P1_KEY1, 2.0, 1),
+        test_sound(Key::P1_KEY3, 2.0, 1),
+        long_note,
+    ], None);
+    bms.bars = vec![0.0, 1.6];
+    bms.bpms.push(BpmChange { timing: 2.0, bpm: 300.0 });
+
+    let analysis = ChartAnalysis::new(&bms);
+    assert_eq!(3.5, analysis.length);
+    assert_eq!(vec![4.0, 0.0, 3.0, 0.0], analysis.density);
+    assert_eq!(4.0, analysis.peak_density());
+    assert_eq!(vec![(0.0, 150.0), (2.0, 300.0)], analysis.bpms);
+    assert_eq!(vec![
+        MeasureStats { notes: 4, scratch: 1, long_notes: 0, chords: 1 },
+        MeasureStats { notes: 3, scratch: 0, long_notes: 1, chords: 1 },
+    ], analysis.measures);
+
+    let mut frame = Frame::new(800.0, 600.0);
+    analysis.graph(&mut frame, Rect::new(0.0, 0.0, 350.0, 100.0), &[2.0]);
+    assert_eq!(vec![
+        "fill 0.0,0.0,0.0,0.6 0,0 350x100",
+        "fill 0.3,0.7,1.0,0.9 0,60 100x40",
+        "fill 0.3,0.7,1.0,0.9 100,100 100x0",
+        "fill 0.3,0.7,1.0,0.9 200,70 100x30",
+        "fill 0.3,0.7,1.0,0.9 300,100 50x0",
+        "fill 0.2,1.0,0.2,1.0 0,98 200x2",
+        "fill 0.2,1.0,0.2,1.0 200,0 150x2",
+        "fill 1.0,0.2,0.2,0.7 200,0 1x100",
+    ].join("\n"), frame.snapshot());
+}
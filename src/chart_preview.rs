@@ -0,0 +1,249 @@
+use bms_loader::{self, Bms};
+use lane_layout::{LaneColor, LaneLayout};
+use play_view::calc_position;
+use skin::Rect;
+use software_render::Canvas;
+
+// the whole chart as columns of measures, bottom to top like it scrolls
+const LANE_SCALE: f64 = 0.25; // of the play screen lane widths
+const BEAT_HEIGHT: f64 = 16.0;
+const COLUMN_BEATS: f64 = 32.0; // eight 4/4 measures, a longer measure gets a taller column
+const NOTE_HEIGHT: f64 = 3.0;
+const LABEL_WIDTH: f64 = 28.0; // measure numbers, left of the lanes
+const ANNOTATION_WIDTH: f64 = 80.0; // bpm changes and stops, right of the lanes
+const MARGIN: f64 = 8.0;
+const FONT_SCALE: f64 = 2.0;
+
+const BACKGROUND: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+const LANE_BG: [f32; 4] = [0.12, 0.12, 0.12, 1.0];
+const BAR_LINE: [f32; 4] = [0.5, 0.5, 0.5, 1.0];
+const LABEL: [f32; 4] = [0.8, 0.8, 0.8, 1.0];
+const BPM_COLOR: [f32; 4] = [0.2, 1.0, 0.2, 1.0];
+const STOP_COLOR: [f32; 4] = [1.0, 0.3, 0.8, 1.0];
+
+fn note_color(color: LaneColor) -> [f32; 4] {
+    match color {
+        LaneColor::WHITE => [0.9, 0.9, 0.9, 1.0],
+        LaneColor::BLUE => [0.3, 0.5, 1.0, 1.0],
+        LaneColor::RED => [1.0, 0.2, 0.2, 1.0],
+    }
+}
+
+// where a measure went: beats from the chart start, and from the bottom of its column
+struct Placement {
+    start: f64,
+    end: f64,
+    column: usize,
+    offset: f64,
+}
+
+struct Sheet {
+    placements: Vec<Placement>,
+    column_beats: f64,
+    column_width: f64,
+    lanes_x: f64, // within a column
+}
+
+impl Sheet {
+    // measures don't break across columns
+    fn new(measures: &[(f64, f64)], lanes_width: f64) -> Sheet {
+        let column_beats = measures.iter().fold(COLUMN_BEATS, |beats, &(start, end)| f64::max(beats, end - start));
+        let mut placements = vec![];
+        let mut column = 0;
+        let mut offset = 0.0;
+        for &(start, end) in measures {
+            if offset > 0.0 && offset + end - start > column_beats {
+                column += 1;
+                offset = 0.0;
+            }
+            placements.push(Placement { start: start, end: end, column: column, offset: offset });
+            offset += end - start;
+        }
+        Sheet {
+            placements: placements,
+            column_beats: column_beats,
+            column_width: LABEL_WIDTH + lanes_width + ANNOTATION_WIDTH,
+            lanes_x: LABEL_WIDTH,
+        }
+    }
+
+    fn columns(&self) -> usize {
+        self.placements.last().map_or(1, |placement| placement.column + 1)
+    }
+
+    fn width(&self) -> u32 {
+        (MARGIN * 2.0 + self.columns() as f64 * self.column_width) as u32
+    }
+
+    fn height(&self) -> u32 {
+        (MARGIN * 2.0 + self.column_beats * BEAT_HEIGHT) as u32
+    }
+
+    fn column_x(&self, column: usize) -> f64 {
+        MARGIN + column as f64 * self.column_width
+    }
+
+    fn y(&self, placement: &Placement, beat: f64) -> f64 {
+        MARGIN + (self.column_beats - placement.offset - (beat - placement.start)) * BEAT_HEIGHT
+    }
+
+    // the column and y of a beat, the last measure keeps what is past its end
+    fn position(&self, beat: f64) -> Option<(f64, f64)> {
+        let placement = self.placements.iter().find(|placement| beat < placement.end).or(self.placements.last());
+        placement.map(|placement| (self.column_x(placement.column), self.y(placement, beat)))
+    }
+
+    // a long note split into a part per measure, as (column x, top, bottom)
+    fn spans(&self, from: f64, to: f64) -> Vec<(f64, f64, f64)> {
+        let last = self.placements.len() - 1;
+        self.placements.iter().enumerate()
+            .filter(|&(i, placement)| placement.start < to && (from < placement.end || i == last))
+            .map(|(_, placement)| {
+                let top = self.y(placement, to.min(placement.end));
+                let bottom = self.y(placement, from.max(placement.start));
+                (self.column_x(placement.column), top, bottom)
+            })
+            .collect()
+    }
+}
+
+pub fn render(bms: &Bms, layout: &LaneLayout) -> Canvas {
+    let bpms = bms_loader::scroll_bpms(&bms.bpms, &bms.stops);
+    let beat = |timing: f64| calc_position(timing, &bpms) / 60.0;
+
+    // the last bar has no next one, so its measure runs 4 beats or to the end of the chart
+    let bar_beats: Vec<f64> = if bms.bars.is_empty() { vec![0.0] } else { bms.bars.iter().map(|bar| beat(*bar)).collect() };
+    let last = *bar_beats.last().unwrap();
+    let chart_end = f64::max(last + 4.0, beat(bms.end()));
+    let measures: Vec<(f64, f64)> = bar_beats.iter().enumerate()
+        .map(|(i, start)| (*start, bar_beats.get(i + 1).map_or(chart_end, |end| *end)))
+        .collect();
+    let sheet = Sheet::new(&measures, layout.width() * LANE_SCALE);
+
+    let mut canvas = Canvas::new(sheet.width(), sheet.height());
+    canvas.fill(Rect::new(0.0, 0.0, sheet.width() as f64, sheet.height() as f64), BACKGROUND);
+    let lanes_width = layout.width() * LANE_SCALE;
+
+    for (i, placement) in sheet.placements.iter().enumerate() {
+        let x = sheet.column_x(placement.column) + sheet.lanes_x;
+        let bottom = sheet.y(placement, placement.start);
+        let top = sheet.y(placement, placement.end);
+        canvas.fill(Rect::new(x, top, lanes_width, bottom - top), LANE_BG);
+        canvas.fill(Rect::new(x, bottom - 1.0, lanes_width, 1.0), BAR_LINE);
+        draw_text(&mut canvas, &format!("{:03}", i), x - LABEL_WIDTH + 2.0, bottom - 5.0 * FONT_SCALE - 1.0, LABEL);
+    }
+
+    for sound in &bms.sounds {
+        let lane = match layout.lane(sound.key) {
+            Some(lane) => lane,
+            None => continue,
+        };
+        let lane_x = sheet.lanes_x + lane.x * LANE_SCALE;
+        let lane_width = lane.width * LANE_SCALE;
+        let color = note_color(lane.color);
+        if let Some(ln_end) = sound.ln_end {
+            let body = [color[0], color[1], color[2], 0.5];
+            for (x, top, bottom) in sheet.spans(beat(sound.timing), beat(ln_end)) {
+                canvas.fill(Rect::new(x + lane_x + 1.0, top, lane_width - 2.0, bottom - top), body);
+            }
+        }
+        if let Some((x, y)) = sheet.position(beat(sound.timing)) {
+            canvas.fill(Rect::new(x + lane_x, y - NOTE_HEIGHT, lane_width, NOTE_HEIGHT), color);
+        }
+    }
+
+    // the first bpm is the starting one, not a change
+    for change in bms.bpms.iter().skip(1) {
+        annotate(&mut canvas, &sheet, lanes_width, beat(change.timing), &format!("BPM {}", change.bpm), BPM_COLOR, 0.0);
+    }
+    // below the line, out of the way of a bpm change at the same time
+    for stop in &bms.stops {
+        annotate(&mut canvas, &sheet, lanes_width, beat(stop.timing), &format!("STOP {:.2}", stop.duration), STOP_COLOR, 6.0 * FONT_SCALE);
+    }
+
+    canvas
+}
+
+fn annotate(canvas: &mut Canvas, sheet: &Sheet, lanes_width: f64, beat: f64, text: &str, color: [f32; 4], text_offset: f64) {
+    if let Some((x, y)) = sheet.position(beat) {
+        let x = x + sheet.lanes_x;
+        canvas.fill(Rect::new(x, y - 1.0, lanes_width + 4.0, 1.0), color);
+        draw_text(canvas, text, x + lanes_width + 6.0, y - 5.0 * FONT_SCALE - 1.0 + text_offset, color);
+    }
+}
+
+// 3x5 pixels per character, the bits of a row from the left
+fn glyph(c: char) -> Option<[u8; 5]> {
+    let rows = match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        _ => return None,
+    };
+    Some(rows)
+}
+
+// x, y is the top left, characters it has no glyph for are left blank
+fn draw_text(canvas: &mut Canvas, text: &str, x: f64, y: f64, color: [f32; 4]) {
+    for (i, c) in text.chars().enumerate() {
+        let rows = match glyph(c) {
+            Some(rows) => rows,
+            None => continue,
+        };
+        let left = x + i as f64 * 4.0 * FONT_SCALE;
+        for (row, bits) in rows.iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) != 0 {
+                    canvas.fill(Rect::new(left + column as f64 * FONT_SCALE, y + row as f64 * FONT_SCALE, FONT_SCALE, FONT_SCALE), color);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn chart_preview_test() {
+    use bms_loader::{Key, PlayMode, Stop};
+    use game_session::{test_bms, test_sound};
+    use lane_layout::{PlaySide, ScratchSide};
+
+    // 150 bpm, so a 4/4 measure is 1.6 seconds. a stop of one beat in the second measure pushes the rest back
+    let mut bms = test_bms(vec![
+        test_sound(Key::P1_SCRATCH, 0.0, 1),
+        test_sound(Key::P1_KEY1, 2.4, 1),
+        test_sound(Key::P1_KEY2, 3.6, 1),
+    ], None);
+    bms.bars = vec![0.0, 1.6, 3.6];
+    bms.stops = vec![Stop { timing: 2.4, duration: 0.4 }];
+    let layout = LaneLayout::new(PlayMode::BEAT_7K, ScratchSide::LEFT, PlaySide::P1);
+    let canvas = render(&bms, &layout);
+
+    let lanes_width = layout.width() * LANE_SCALE;
+    assert_eq!((MARGIN * 2.0 + LABEL_WIDTH + lanes_width + ANNOTATION_WIDTH) as u32, canvas.width);
+    assert_eq!((MARGIN * 2.0 + COLUMN_BEATS * BEAT_HEIGHT) as u32, canvas.height);
+
+    let bottom = MARGIN + COLUMN_BEATS * BEAT_HEIGHT;
+    let note_row = |beats: f64| (bottom - beats * BEAT_HEIGHT - 2.0) as u32;
+    let lane_x = |key: Key| (MARGIN + LABEL_WIDTH + layout.lane(key).unwrap().x * LANE_SCALE + 2.0) as u32;
+    assert_eq!([255, 51, 51, 255], canvas.pixel(lane_x(Key::P1_SCRATCH), note_row(0.0)));
+    assert_eq!([230, 230, 230, 255], canvas.pixel(lane_x(Key::P1_KEY1), note_row(6.0)));
+    // the stop doesn't take up room, the third measure starts right after the second
+    assert_eq!([77, 128, 255, 255], canvas.pixel(lane_x(Key::P1_KEY2), note_row(8.0)));
+    assert_eq!([31, 31, 31, 255], canvas.pixel(lane_x(Key::P1_KEY2), note_row(7.0)));
+    // the stop's line
+    assert_eq!([255, 77, 204, 255], canvas.pixel(lane_x(Key::P1_KEY3), (bottom - 6.0 * BEAT_HEIGHT - 1.0) as u32));
+}
@@ -0,0 +1,162 @@
+use bms_loader::{self, Bms, Key, PlayMode};
+use chart_analysis::{self, ChartAnalysis};
+use game_session;
+use hispeed::{self, BaseBpm};
+
+type Time = f64;
+
+// what a chart has in it, without playing it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChartStats {
+    pub play_mode: PlayMode,
+    pub total_notes: u32,
+    pub lane_notes: Vec<(Key, u32)>, // in the lane order of the play mode
+    pub scratch: u32,
+    pub long_notes: u32,
+    pub mines: u32,
+    pub chords: u32,
+    pub min_bpm: f64,
+    pub max_bpm: f64,
+    pub main_bpm: f64,
+    pub length: Time, // to the end of the last note
+    pub stops: u32,
+    pub stop_time: Time,
+    pub total: Option<f64>, // #TOTAL as written
+    pub gauge_total: f64, // what the gauge goes by, the default when there is no #TOTAL
+    pub peak_density: f64, // notes in the busiest DENSITY_STEP
+}
+
+impl ChartStats {
+    pub fn new(bms: &Bms) -> ChartStats {
+        let analysis = ChartAnalysis::new(bms);
+        let play_mode = bms.play_mode();
+        let visible = Key::visible_keys();
+        let notes: Vec<_> = bms.sounds.iter().filter(|sound| visible.contains(&sound.key)).collect();
+        let total_notes = notes.len() as u32;
+        let length = bms.end();
+        let scroll_bpms = bms_loader::scroll_bpms(&bms.bpms, &bms.stops);
+
+        ChartStats {
+            play_mode: play_mode,
+            total_notes: total_notes,
+            lane_notes: play_mode.lanes().into_iter().map(|key| (key, notes.iter().filter(|note| note.key == key).count() as u32)).collect(),
+            scratch: analysis.measures.iter().map(|measure| measure.scratch).sum(),
+            long_notes: analysis.measures.iter().map(|measure| measure.long_notes).sum(),
+            mines: bms.mines.len() as u32,
+            chords: analysis.measures.iter().map(|measure| measure.chords).sum(),
+            min_bpm: hispeed::base_bpm(&scroll_bpms, length, BaseBpm::MIN),
+            max_bpm: hispeed::base_bpm(&scroll_bpms, length, BaseBpm::MAX),
+            main_bpm: hispeed::base_bpm(&scroll_bpms, length, BaseBpm::MAIN),
+            length: length,
+            stops: bms.stops.len() as u32,
+            stop_time: bms.stops.iter().map(|stop| stop.duration).sum(),
+            total: bms.total,
+            gauge_total: bms.total.unwrap_or(game_session::default_total(total_notes)),
+            peak_density: analysis.peak_density(),
+        }
+    }
+
+    pub fn text(&self) -> String {
+        let mut lines = vec![
+            format!("mode: {}", self.play_mode.name()),
+            format!("notes: {}", self.total_notes),
+        ];
+        for &(key, count) in &self.lane_notes {
+            lines.push(format!("  {:?}: {}", key, count));
+        }
+        lines.push(format!("scratch: {}", self.scratch));
+        lines.push(format!("long notes: {}", self.long_notes));
+        lines.push(format!("mines: {}", self.mines));
+        lines.push(format!("chords: {}", self.chords));
+        lines.push(format!("bpm: {} - {} (main {})", self.min_bpm, self.max_bpm, self.main_bpm));
+        lines.push(format!("length: {:.2}s", self.length));
+        lines.push(format!("stops: {} ({:.2}s)", self.stops, self.stop_time));
+        lines.push(format!("total: {}", self.total.map_or(format!("none (gauge uses {:.1})", self.gauge_total), |total| total.to_string())));
+        lines.push(format!("peak density: {} notes / {}s", self.peak_density, chart_analysis::DENSITY_STEP));
+        lines.join("\n")
+    }
+
+    // one line, so a run over a library gives a JSON object per chart
+    pub fn json(&self, path: &str) -> String {
+        let lanes: Vec<String> = self.lane_notes.iter().map(|&(key, count)| format!("{}:{}", json_string(&format!("{:?}", key)), count)).collect();
+        let fields = vec![
+            ("path", json_string(path)),
+            ("mode", json_string(self.play_mode.name())),
+            ("notes", self.total_notes.to_string()),
+            ("lanes", format!("{{{}}}", lanes.join(","))),
+            ("scratch", self.scratch.to_string()),
+            ("long_notes", self.long_notes.to_string()),
+            ("mines", self.mines.to_string()),
+            ("chords", self.chords.to_string()),
+            ("min_bpm", json_number(self.min_bpm)),
+            ("max_bpm", json_number(self.max_bpm)),
+            ("main_bpm", json_number(self.main_bpm)),
+            ("length", json_number(self.length)),
+            ("stops", self.stops.to_string()),
+            ("stop_time", json_number(self.stop_time)),
+            ("total", self.total.map_or("null".to_string(), json_number)),
+            ("gauge_total", json_number(self.gauge_total)),
+            ("peak_density", json_number(self.peak_density)),
+        ];
+        let fields: Vec<String> = fields.into_iter().map(|(name, value)| format!("{}:{}", json_string(name), value)).collect();
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+// JSON has no NaN or infinity
+fn json_number(value: f64) -> String {
+    if value.is_finite() { value.to_string() } else { "null".to_string() }
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[test]
+fn chart_stats_test() {
+    use bms_loader::{BpmChange, Mine, Stop};
+    use game_session::{test_bms, test_sound};
+
+    let mut long_note = test_sound(Key::P1_KEY2, 2.5, 1);
+    long_note.ln_end = Some(4.0);
+    let mut bms = test_bms(vec![
+        test_sound(Key::P1_SCRATCH, 0.5, 1),
+        test_sound(Key::P1_KEY1, 0.5, 1),
+        test_sound(Key::BACK_CHORUS, 1.0, 1),
+        test_sound(Key::P1_KEY6, 2.0, 1),
+        long_note,
+    ], None);
+    bms.bpms.push(BpmChange { timing: 3.0, bpm: 300.0 });
+    bms.stops = vec![Stop { timing: 1.0, duration: 0.25 }];
+    bms.mines = vec![Mine { key: Key::P1_KEY3, timing: 1.5, damage: 10 }];
+
+    let stats = ChartStats::new(&bms);
+    assert_eq!(PlayMode::BEAT_7K, stats.play_mode);
+    assert_eq!(4, stats.total_notes);
+    assert_eq!((Key::P1_SCRATCH, 1), stats.lane_notes[0]);
+    assert_eq!((Key::P1_KEY6, 1), stats.lane_notes[6]);
+    assert_eq!((1, 1, 1, 1), (stats.scratch, stats.long_notes, stats.mines, stats.chords));
+    assert_eq!((150.0, 300.0, 150.0), (stats.min_bpm, stats.max_bpm, stats.main_bpm));
+    assert_eq!((4.0, 1, 0.25), (stats.length, stats.stops, stats.stop_time));
+    assert_eq!(2.0, stats.peak_density);
+
+    assert_eq!("{\"path\":\"a \\\"b\\\"\\\\c.bme\",\"mode\":\"7K\",\"notes\":4,\
+        \"lanes\":{\"P1_SCRATCH\":1,\"P1_KEY1\":1,\"P1_KEY2\":1,\"P1_KEY3\":0,\"P1_KEY4\":0,\"P1_KEY5\":0,\"P1_KEY6\":1,\"P1_KEY7\":0},\
+        \"scratch\":1,\"long_notes\":1,\"mines\":1,\"chords\":1,\"min_bpm\":150,\"max_bpm\":300,\"main_bpm\":150,\
+        \"length\":4,\"stops\":1,\"stop_time\":0.25,\"total\":null,\"gauge_total\":160.64,\"peak_density\":2}",
+        stats.json("a \"b\"\\c.bme"));
+    assert!(stats.text().contains("total: none (gauge uses 160.6)"));
+}
@@ -0,0 +1,260 @@
+use std::cell::Cell;
+use std::os::raw::{c_int, c_void};
+use std::ptr;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use sdl2::mixer;
+use time;
+
+type Time = f64;
+
+// piston-music opens the mixer with chunks of this many samples
+const AUDIO_CHUNK_SIZE: f64 = 1024.0;
+
+// seconds of play time since start()
+pub trait Clock {
+    fn start(&mut self);
+    fn now(&self) -> Time;
+}
+
+pub struct WallClock {
+    start: Time,
+}
+
+impl WallClock {
+    pub fn new() -> WallClock {
+        WallClock { start: time::precise_time_s() }
+    }
+}
+
+impl Clock for WallClock {
+    fn start(&mut self) {
+        self.start = time::precise_time_s();
+    }
+
+    fn now(&self) -> Time {
+        time::precise_time_s() - self.start
+    }
+}
+
+// what is coming out of the speakers, counted in the samples the mixer has handed to the device.
+// SDL_mixer calls the postmix hook with each chunk just before it is played, so between two calls
+// the position is moved on by the wall clock, never past the end of the chunk.
+// a chunk only reaches the speakers once the one already in the device buffer has played, so the
+// position is held back by the length of that buffer.
+// the postmix hook is the mixer's only one: one AudioClock at a time, and nothing else may set it
+pub struct AudioClock {
+    bytes_per_second: f64,
+    chunk_time: Time,
+    start: Time,
+    last: Cell<Time>, // so the time never goes back when a chunk comes a little early
+}
+
+extern "C" {
+    fn Mix_SetPostMix(mix_func: Option<extern "C" fn(*mut c_void, *mut u8, c_int)>, arg: *mut c_void);
+}
+
+// bytes the device had been given before the chunk now playing, and when that chunk was asked for
+static PLAYED_BYTES: AtomicUsize = AtomicUsize::new(0);
+static MIXED_BYTES: AtomicUsize = AtomicUsize::new(0);
+static CHUNK_AT_US: AtomicUsize = AtomicUsize::new(0);
+
+// runs on the audio thread
+extern "C" fn count_mixed(_arg: *mut c_void, _stream: *mut u8, len: c_int) {
+    CHUNK_AT_US.store((time::precise_time_ns() / 1000) as usize, Ordering::SeqCst);
+    PLAYED_BYTES.store(MIXED_BYTES.fetch_add(len as usize, Ordering::SeqCst), Ordering::SeqCst);
+}
+
+impl AudioClock {
+    // the mixer has to be open
+    pub fn new() -> Result<AudioClock, String> {
+        let (frequency, format, channels) = mixer::query_spec()?;
+        // the low byte of the format is the bits per sample
+        let bytes_per_second = frequency as f64 * ((format & 0xff) as f64 / 8.0) * channels as f64;
+        if bytes_per_second <= 0.0 {
+            return Err(format!("the mixer plays {} Hz, {} channels", frequency, channels));
+        }
+        // a clock made after an earlier one has to count from nothing
+        PLAYED_BYTES.store(0, Ordering::SeqCst);
+        MIXED_BYTES.store(0, Ordering::SeqCst);
+        CHUNK_AT_US.store(0, Ordering::SeqCst);
+        unsafe {
+            // replaces any hook already set
+            Mix_SetPostMix(Some(count_mixed), ptr::null_mut());
+        }
+        Ok(AudioClock { bytes_per_second: bytes_per_second, chunk_time: AUDIO_CHUNK_SIZE / frequency as f64, start: 0.0, last: Cell::new(0.0) })
+    }
+
+    fn position(&self) -> Time {
+        let chunk_at = CHUNK_AT_US.load(Ordering::SeqCst) as f64 / 1000000.0;
+        let played = PLAYED_BYTES.load(Ordering::SeqCst) as f64 / self.bytes_per_second;
+        if chunk_at == 0.0 {
+            return 0.0;
+        }
+        let position = played + f64::min(time::precise_time_s() - chunk_at, self.chunk_time) - self.chunk_time;
+        let position = f64::max(position, self.last.get());
+        self.last.set(position);
+        position
+    }
+}
+
+impl Clock for AudioClock {
+    fn start(&mut self) {
+        self.start = self.position();
+    }
+
+    fn now(&self) -> Time {
+        self.position() - self.start
+    }
+}
+
+impl Drop for AudioClock {
+    fn drop(&mut self) {
+        unsafe {
+            Mix_SetPostMix(None, ptr::null_mut());
+        }
+    }
+}
+
+// only moves when told to; clones share the same time
+#[derive(Clone)]
+pub struct ManualClock {
+    time: Rc<Cell<Time>>,
+}
+
+impl ManualClock {
+    pub fn new() -> ManualClock {
+        ManualClock { time: Rc::new(Cell::new(0.0)) }
+    }
+
+    pub fn set(&self, time: Time) {
+        self.time.set(time);
+    }
+
+    pub fn advance(&self, delta: Time) {
+        self.time.set(self.time.get() + delta);
+    }
+}
+
+impl Clock for ManualClock {
+    // the test decides where play time is
+    fn start(&mut self) {}
+
+    fn now(&self) -> Time {
+        self.time.get()
+    }
+}
+
+// the play time everything reads, started on the first reading.
+// pausing freezes it, seeking moves it and the rate slows it down, the source keeps running underneath
+pub struct PlayClock {
+    source: Box<Clock>,
+    started: bool,
+    offset: Time,
+    rate: f64,
+    paused_at: Option<Time>,
+}
+
+impl PlayClock {
+    pub fn new(source: Box<Clock>) -> PlayClock {
+        PlayClock { source: source, started: false, offset: 0.0, rate: 1.0, paused_at: None }
+    }
+
+    // what inputs are stamped with, play time follows from it with at()
+    pub fn source_now(&mut self) -> Time {
+        if !self.started {
+            self.source.start();
+            self.started = true;
+        }
+        self.source.now()
+    }
+
+    pub fn now(&mut self) -> Time {
+        let now = self.source_now();
+        match self.paused_at {
+            Some(time) => time,
+            None => now * self.rate + self.offset,
+        }
+    }
+
+    pub fn pause(&mut self) {
+        if self.paused_at.is_none() {
+            self.paused_at = Some(self.now());
+        }
+    }
+
+    // carries on from where it was paused
+    pub fn resume(&mut self) {
+        if let Some(time) = self.paused_at.take() {
+            self.offset = time - self.source_now() * self.rate;
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused_at.is_some()
+    }
+
+    // stays paused if it was
+    pub fn seek(&mut self, time: Time) {
+        if self.paused_at.is_some() {
+            self.paused_at = Some(time);
+        } else {
+            self.offset = time - self.source_now() * self.rate;
+        }
+    }
+
+    // play time seconds per real second, from now on
+    pub fn set_rate(&mut self, rate: f64) {
+        let now = self.now();
+        self.rate = rate;
+        self.seek(now);
+    }
+
+    // play time when the source read source_time, earlier than now
+    pub fn at(&mut self, source_time: Time) -> Time {
+        match self.paused_at {
+            Some(time) => time,
+            None => source_time * self.rate + self.offset,
+        }
+    }
+}
+
+#[test]
+fn play_clock_test() {
+    let clock = ManualClock::new();
+    let mut play_clock = PlayClock::new(Box::new(clock.clone()));
+
+    assert_eq!(0.0, play_clock.now());
+    clock.advance(1.5);
+    assert_eq!(1.5, play_clock.now());
+    assert_eq!(1.0, play_clock.at(1.0));
+
+    play_clock.pause();
+    clock.advance(2.0);
+    assert_eq!(1.5, play_clock.now());
+    play_clock.resume();
+    clock.advance(0.5);
+    assert_eq!(2.0, play_clock.now());
+
+    play_clock.seek(10.0);
+    clock.advance(1.0);
+    assert_eq!(11.0, play_clock.now());
+    play_clock.pause();
+    play_clock.seek(4.0);
+    assert_eq!(4.0, play_clock.now());
+    play_clock.resume();
+    clock.advance(0.25);
+    assert_eq!(4.25, play_clock.now());
+
+    play_clock.set_rate(0.5);
+    clock.advance(1.0);
+    assert_eq!(4.75, play_clock.now());
+    // half a second of the source before now is a quarter of play time
+    let source = play_clock.source_now();
+    assert_eq!(4.5, play_clock.at(source - 0.5));
+
+    let mut wall = PlayClock::new(Box::new(WallClock::new()));
+    let first = wall.now();
+    assert!(first >= 0.0 && first < 0.1);
+}
@@ -0,0 +1,90 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io;
+use std::path::PathBuf;
+
+use user_data;
+
+// `key = value` lines, `#` starts a comment line
+pub struct Config {
+    path: PathBuf,
+    values: BTreeMap<String, String>,
+}
+
+impl Config {
+    pub fn open(file_name: &str) -> Config {
+        Config::open_path(user_data::data_dir().join(file_name))
+    }
+
+    pub fn open_path(path: PathBuf) -> Config {
+        let mut contents = String::new();
+        if let Ok(mut file) = File::open(&path) {
+            if let Err(e) = file.read_to_string(&mut contents) {
+                println!("failed to read {}: {}", path.to_str().unwrap_or(""), e);
+            }
+        }
+        Config { path: path, values: Config::parse(&contents) }
+    }
+
+    #[cfg(test)]
+    pub fn from_contents(contents: &str) -> Config {
+        Config { path: PathBuf::new(), values: Config::parse(contents) }
+    }
+
+    fn parse(contents: &str) -> BTreeMap<String, String> {
+        let mut values = BTreeMap::new();
+        for line in contents.lines() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            match trimmed.find('=') {
+                Some(index) => {
+                    values.insert(trimmed[..index].trim().to_string(), trimmed[index + 1..].trim().to_string());
+                }
+                None => println!("skipping broken config line: {}", line),
+            }
+        }
+        values
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(|value| value.as_str())
+    }
+
+    pub fn get_f64(&self, key: &str, default: f64) -> f64 {
+        self.get(key).and_then(|value| value.parse().ok()).unwrap_or(default)
+    }
+
+    pub fn keys_with_prefix<'a>(&'a self, prefix: &'a str) -> Vec<&'a str> {
+        self.values.keys().filter(|key| key.starts_with(prefix)).map(|key| key.as_str()).collect()
+    }
+
+    pub fn set(&mut self, key: &str, value: String) {
+        self.values.insert(key.to_string(), value);
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.values.remove(key);
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let tmp_path = self.path.with_extension("tmp");
+        {
+            let mut file = File::create(&tmp_path)?;
+            for (key, value) in &self.values {
+                writeln!(file, "{} = {}", key, value)?;
+            }
+        }
+        fs::rename(&tmp_path, &self.path)
+    }
+}
+
+#[test]
+fn config_parse_test() {
+    let values = Config::parse("# comment\n\nsudden = 120\n  keys.7K.P1_KEY1=Z, J  \nbroken line\n");
+    assert_eq!(2, values.len());
+    assert_eq!("120", values["sudden"]);
+    assert_eq!("Z, J", values["keys.7K.P1_KEY1"]);
+}
@@ -0,0 +1,178 @@
+use sdl2::sys::controller as gc;
+use sdl2::sys::joystick as ll;
+use sdl2::sys::sdl;
+
+use input::RawInput;
+
+// music::start owns the only Sdl context, so the controller subsystem is driven through the raw bindings,
+// and only from the thread music::start runs on: open and poll these from the game loop, never another thread.
+// Pads SDL knows the layout of are read through their game controller mapping, anything else button by button.
+// State is polled every time the game loop comes round and compared with the last poll.
+pub struct Controllers {
+    initialized: bool,
+    joysticks: Vec<Joystick>,
+}
+
+enum Device {
+    Mapped(*mut gc::SDL_GameController), // ButtonN and AxisN follow SDL's standard layout, A is Button0, left stick X is Axis0
+    Raw(*mut ll::SDL_Joystick),          // ButtonN and AxisN as the device numbers them, like most arcade controllers
+}
+
+struct Joystick {
+    index: u32, // device index, the N in JoyN bindings
+    device: Device,
+    state: PadState,
+}
+
+// what a device reported last, inputs are the differences from it
+struct PadState {
+    buttons: Vec<bool>,
+    axes: Vec<i16>,
+}
+
+impl Controllers {
+    pub fn open() -> Controllers {
+        let mut joysticks = vec![];
+        unsafe {
+            if sdl::SDL_InitSubSystem(sdl::SDL_INIT_GAMECONTROLLER) != 0 {
+                println!("failed to initialize controllers");
+                return Controllers { initialized: false, joysticks: joysticks };
+            }
+            for index in 0..ll::SDL_NumJoysticks() {
+                let joystick = if gc::SDL_IsGameController(index) != 0 {
+                    let raw = gc::SDL_GameControllerOpen(index);
+                    if raw.is_null() {
+                        println!("failed to open controller {}", index);
+                        continue;
+                    }
+                    println!("controller {}: standard layout", index);
+                    let (buttons, axes) = MAPPED_LAYOUT;
+                    Joystick::new(index as u32, Device::Mapped(raw), buttons, axes)
+                } else {
+                    let raw = ll::SDL_JoystickOpen(index);
+                    if raw.is_null() {
+                        println!("failed to open joystick {}", index);
+                        continue;
+                    }
+                    let buttons = ll::SDL_JoystickNumButtons(raw).max(0) as usize;
+                    let axes = ll::SDL_JoystickNumAxes(raw).max(0) as usize;
+                    println!("joystick {}: {} buttons, {} axes", index, buttons, axes);
+                    Joystick::new(index as u32, Device::Raw(raw), buttons, axes)
+                };
+                joysticks.push(joystick);
+            }
+        }
+        Controllers { initialized: true, joysticks: joysticks }
+    }
+
+    pub fn poll(&mut self) -> Vec<RawInput> {
+        if self.joysticks.is_empty() {
+            return vec![];
+        }
+        unsafe {
+            // updates mapped controllers as well, they read the joystick underneath
+            ll::SDL_JoystickUpdate();
+        }
+        let mut inputs = vec![];
+        for joystick in self.joysticks.iter_mut() {
+            inputs.extend(joystick.poll());
+        }
+        inputs
+    }
+}
+
+// buttons and axes of the standard layout, in the order SDL numbers them
+const MAPPED_LAYOUT: (usize, usize) = (gc::SDL_CONTROLLER_BUTTON_MAX as usize, gc::SDL_CONTROLLER_AXIS_MAX as usize);
+
+impl Joystick {
+    fn new(index: u32, device: Device, buttons: usize, axes: usize) -> Joystick {
+        let mut joystick = Joystick { index: index, device: device, state: PadState::new(buttons, axes) };
+        // the first poll should not report whatever is held at start up
+        joystick.poll();
+        joystick
+    }
+
+    fn button(&self, button: usize) -> bool {
+        unsafe {
+            match self.device {
+                Device::Mapped(raw) => gc::SDL_GameControllerGetButton(raw, button as gc::SDL_GameControllerButton) != 0,
+                Device::Raw(raw) => ll::SDL_JoystickGetButton(raw, button as i32) != 0,
+            }
+        }
+    }
+
+    fn axis(&self, axis: usize) -> i16 {
+        unsafe {
+            match self.device {
+                Device::Mapped(raw) => gc::SDL_GameControllerGetAxis(raw, axis as gc::SDL_GameControllerAxis),
+                Device::Raw(raw) => ll::SDL_JoystickGetAxis(raw, axis as i32),
+            }
+        }
+    }
+
+    fn poll(&mut self) -> Vec<RawInput> {
+        let buttons: Vec<bool> = (0..self.state.buttons.len()).map(|button| self.button(button)).collect();
+        let axes: Vec<i16> = (0..self.state.axes.len()).map(|axis| self.axis(axis)).collect();
+        self.state.update(self.index, &buttons, &axes)
+    }
+}
+
+impl PadState {
+    fn new(buttons: usize, axes: usize) -> PadState {
+        PadState { buttons: vec![false; buttons], axes: vec![0; axes] }
+    }
+
+    fn update(&mut self, joystick: u32, buttons: &[bool], axes: &[i16]) -> Vec<RawInput> {
+        let mut inputs = vec![];
+        for (button, &pressed) in buttons.iter().enumerate() {
+            if pressed != self.buttons[button] {
+                self.buttons[button] = pressed;
+                inputs.push(RawInput::Button(joystick, button as u8, pressed));
+            }
+        }
+        for (axis, &position) in axes.iter().enumerate() {
+            if position != self.axes[axis] {
+                self.axes[axis] = position;
+                inputs.push(RawInput::Axis(joystick, axis as u8, position as f64 / 32768.0));
+            }
+        }
+        inputs
+    }
+}
+
+impl Drop for Controllers {
+    fn drop(&mut self) {
+        unsafe {
+            for joystick in &self.joysticks {
+                match joystick.device {
+                    Device::Mapped(raw) => gc::SDL_GameControllerClose(raw),
+                    Device::Raw(raw) => ll::SDL_JoystickClose(raw),
+                }
+            }
+            if self.initialized {
+                sdl::SDL_QuitSubSystem(sdl::SDL_INIT_GAMECONTROLLER);
+            }
+        }
+    }
+}
+
+#[test]
+fn controller_mapping_test() {
+    // a standard pad is bound like any joystick, A is Button0 and the left stick X is Axis0
+    assert_eq!((15, 6), MAPPED_LAYOUT);
+    assert_eq!((0, 0), (gc::SDL_CONTROLLER_BUTTON_A, gc::SDL_CONTROLLER_AXIS_LEFTX));
+
+    let (buttons, axes) = MAPPED_LAYOUT;
+    let mut state = PadState::new(buttons, axes);
+    let mut pressed = vec![false; buttons];
+    let mut positions = vec![0; axes];
+    assert!(state.update(1, &pressed, &positions).is_empty());
+
+    pressed[gc::SDL_CONTROLLER_BUTTON_Y as usize] = true;
+    positions[gc::SDL_CONTROLLER_AXIS_LEFTX as usize] = -16384;
+    assert_eq!(vec![RawInput::Button(1, 3, true), RawInput::Axis(1, 0, -0.5)], state.update(1, &pressed, &positions));
+    // only changes are reported
+    assert!(state.update(1, &pressed, &positions).is_empty());
+    pressed[3] = false;
+    assert_eq!(vec![RawInput::Button(1, 3, false)], state.update(1, &pressed, &positions));
+}
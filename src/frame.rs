@@ -0,0 +1,66 @@
+use std::fmt;
+
+use skin::{ImageId, Rect};
+
+// which texture an image command draws, a part of it is already picked by the skin image's src
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TextureId {
+    Skin(ImageId),
+    Bga(usize),
+}
+
+// one thing to draw, in window pixels and in order. transforms are applied while the frame is built,
+// so a rect is all a backend needs to place anything
+#[derive(Debug, Clone, PartialEq)]
+pub enum DrawCommand {
+    Image { texture: TextureId, rect: Rect, alpha: f32 },
+    Fill { color: [f32; 4], rect: Rect },
+    // x, y is the left end of the baseline
+    Text { text: String, color: [f32; 4], size: u32, x: f64, y: f64 },
+}
+
+// everything on screen at one moment, whichever backend draws it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub width: f64,
+    pub height: f64,
+    pub commands: Vec<DrawCommand>,
+}
+
+impl Frame {
+    pub fn new(width: f64, height: f64) -> Frame {
+        Frame { width: width, height: height, commands: vec![] }
+    }
+
+    pub fn image(&mut self, texture: TextureId, rect: Rect) {
+        self.commands.push(DrawCommand::Image { texture: texture, rect: rect, alpha: 1.0 });
+    }
+
+    pub fn fill(&mut self, color: [f32; 4], rect: Rect) {
+        self.commands.push(DrawCommand::Fill { color: color, rect: rect });
+    }
+
+    pub fn text(&mut self, text: &str, color: [f32; 4], size: u32, x: f64, y: f64) {
+        self.commands.push(DrawCommand::Text { text: text.to_string(), color: color, size: size, x: x, y: y });
+    }
+
+    // one line per command, rounded to whole pixels so snapshots don't break on float noise
+    pub fn snapshot(&self) -> String {
+        self.commands.iter().map(|command| command.to_string()).collect::<Vec<_>>().join("\n")
+    }
+}
+
+fn rect_text(rect: &Rect) -> String {
+    format!("{:.0},{:.0} {:.0}x{:.0}", rect.x, rect.y, rect.w, rect.h)
+}
+
+impl fmt::Display for DrawCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DrawCommand::Image { texture: TextureId::Skin(id), ref rect, alpha } => write!(f, "image skin {} {} a{:.2}", id, rect_text(rect), alpha),
+            DrawCommand::Image { texture: TextureId::Bga(id), ref rect, alpha } => write!(f, "image bga {} {} a{:.2}", id, rect_text(rect), alpha),
+            DrawCommand::Fill { color, ref rect } => write!(f, "fill {:.1},{:.1},{:.1},{:.1} {}", color[0], color[1], color[2], color[3], rect_text(rect)),
+            DrawCommand::Text { ref text, size, x, y, .. } => write!(f, "text {:?} {} {:.0},{:.0}", text, size, x, y),
+        }
+    }
+}
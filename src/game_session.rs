@@ -0,0 +1,789 @@
+use std::collections::{HashMap, HashSet};
+
+use bms_loader::{self, Bms, SoundX};
+use lane_cover::LaneCover;
+use score_db::Lamp;
+
+type Time = f64;
+
+const GAUGE_START: f64 = 22.0;
+const GAUGE_MIN: f64 = 2.0;
+const GAUGE_MAX: f64 = 100.0;
+const GAUGE_CLEAR: f64 = 80.0;
+const DEFAULT_BPM: f64 = 130.0;
+
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum Judge {
+    PGREAT,
+    GREAT,
+    GOOD,
+    BAD,
+    POOR,
+    MISSED,
+}
+
+impl Judge {
+    fn combo_lasts(judge: Judge) -> bool {
+        match judge {
+            Judge::PGREAT | Judge::GREAT | Judge::GOOD => true,
+            _ => false
+        }
+    }
+
+    // a POOR before the note leaves it to be hit, one after it takes the note
+    fn consume_note(&self, early: bool) -> bool {
+        *self != Judge::POOR || !early
+    }
+}
+
+struct JudgeRank {
+    pub pgreat: f64,
+    pub great: f64,
+    pub good: f64,
+    pub bad: f64,
+    pub poor: f64,
+}
+
+impl JudgeRank {
+    fn get_judge(&self, d: Time) -> Option<Judge> {
+        if d < self.poor {
+            Some(if d < self.pgreat {
+                Judge::PGREAT
+            } else if d < self.great {
+                Judge::GREAT
+            } else if d < self.good {
+                Judge::GOOD
+            } else if d < self.bad {
+                Judge::BAD
+            } else {
+                Judge::POOR
+            })
+        } else {
+            None
+        }
+    }
+}
+
+const IIDX_JUDGERANK: JudgeRank = JudgeRank { pgreat: 0.02, great: 0.04, good: 0.105, bad: 0.15, poor: 0.2 };
+
+pub struct PlayResult {
+    pub judge_count: HashMap<Judge, u32>,
+    pub max_combo: u32,
+    pub total_notes: u32,
+    pub gauge: f64,
+    pub finished: bool,
+    pub misses: Vec<Time>, // when the combo broke
+}
+
+impl PlayResult {
+    pub fn count(&self, judge: Judge) -> u32 {
+        *self.judge_count.get(&judge).unwrap_or(&0)
+    }
+
+    pub fn ex_score(&self) -> u32 {
+        self.count(Judge::PGREAT) * 2 + self.count(Judge::GREAT)
+    }
+
+    pub fn bp(&self) -> u32 {
+        self.count(Judge::BAD) + self.count(Judge::POOR) + self.count(Judge::MISSED)
+    }
+
+    pub fn lamp(&self) -> Lamp {
+        if !self.finished || self.gauge < GAUGE_CLEAR {
+            Lamp::FAILED
+        } else if self.bp() > 0 {
+            Lamp::CLEAR
+        } else if self.count(Judge::GOOD) > 0 {
+            Lamp::FULL_COMBO
+        } else if self.count(Judge::GREAT) > 0 {
+            Lamp::PERFECT
+        } else {
+            Lamp::MAX
+        }
+    }
+}
+
+// groove gauge in percent, cleared at 80% when the chart ends
+pub struct Gauge {
+    value: f64,
+    gain: f64, // per PGREAT or GREAT
+}
+
+impl Gauge {
+    pub fn new(total: f64, total_notes: u32) -> Gauge {
+        Gauge { value: GAUGE_START, gain: total / f64::max(total_notes as f64, 1.0) }
+    }
+
+    pub fn update(&mut self, judge: Judge) {
+        let delta = match judge {
+            Judge::PGREAT | Judge::GREAT => self.gain,
+            Judge::GOOD => self.gain / 2.0,
+            Judge::BAD => -4.0,
+            Judge::POOR => -2.0,
+            Judge::MISSED => -6.0,
+        };
+        self.value = f64::min(f64::max(self.value + delta, GAUGE_MIN), GAUGE_MAX);
+    }
+
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+
+    pub fn reset(&mut self) {
+        self.value = GAUGE_START;
+    }
+
+    pub fn is_clear(&self) -> bool {
+        self.value >= GAUGE_CLEAR
+    }
+}
+
+// what charts without #TOTAL get, grows with the note count like most BMS players do
+pub fn default_total(total_notes: u32) -> f64 {
+    let notes = total_notes as f64;
+    160.0 + (notes + f64::min(f64::max(notes - 400.0, 0.0), 200.0)) * 0.16
+}
+
+#[derive(Debug, Clone)]
+pub struct Note {
+    pub timing: Time,
+    pub wav_id: SoundX,
+    pub ln_end: Option<Time>,
+}
+
+// everything the frontend has to play, show or react to, in the order it happened
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionOutput {
+    Judged(bms_loader::Key, Judge, Time),
+    PlaySound(SoundX),
+    ChangeBpm(f64),
+    ChangeBga(Option<i32>),
+    ChangeSpeed(f64, Time),
+    ChangeCover(LaneCover, Time),
+    ResumeSound(SoundX, Time), // a BGM sample that should already be this far in after a seek
+}
+
+struct Event {
+    timing: Time,
+    event_type: EventType
+}
+
+enum EventType {
+    ChangeBpm(f64),
+    PlaySound(SoundX),
+    ChangeBga(Option<i32>),
+    ChangeSpeed(f64),
+    ChangeCover(LaneCover),
+    Input(bms_loader::Key, bool),
+    EndMusic
+}
+
+// judging, scoring and the chart timeline without a window, driven by play time and input
+pub struct GameSession {
+    notes_by_key: HashMap<bms_loader::Key, Vec<Note>>,
+    judge_index_by_key: HashMap<bms_loader::Key, usize>,
+    events: Vec<Event>,
+    event_index: usize,
+    pushed_key_set: HashSet<bms_loader::Key>,
+    holding: HashMap<bms_loader::Key, Time>, // the end of the long note each lane is holding
+    judgerank: JudgeRank,
+    judge_count: HashMap<Judge, u32>,
+    last_judge: Option<(Judge, Time)>,
+    combo: u32,
+    max_combo: u32,
+    misses: Vec<Time>,
+    gauge: Gauge,
+    bpm: f64,
+    total_notes: u32, // judges in the chart, a long note has one for its end too
+    finished: bool,
+    sample_lengths: HashMap<SoundX, Time>,
+    outputs: Vec<SessionOutput>,
+}
+
+impl GameSession {
+    pub fn new(bms: &Bms) -> GameSession {
+        let mut notes_by_key = HashMap::new();
+        for key in bms_loader::Key::visible_keys() {
+            notes_by_key.insert(key, vec![]);
+        }
+
+        let mut events = vec![];
+        let mut total_notes = 0;
+        for sound in &bms.sounds {
+            if let Some(notes) = notes_by_key.get_mut(&sound.key) {
+                total_notes += if sound.ln_end.is_some() { 2 } else { 1 };
+                notes.push(Note { timing: sound.timing, wav_id: sound.wav_id, ln_end: sound.ln_end });
+            } else if sound.key == bms_loader::Key::BACK_CHORUS {
+                events.push(Event { timing: sound.timing, event_type: EventType::PlaySound(sound.wav_id) });
+            }
+        }
+        for notes in notes_by_key.values_mut() {
+            notes.sort_by(|a, b| a.timing.partial_cmp(&b.timing).unwrap());
+        }
+
+        let mut bga_ends: f64 = 0.0;
+        for image in &bms.bga {
+            events.push(Event { timing: image.timing, event_type: EventType::ChangeBga(Some(image.texture_id)) });
+            if bga_ends < image.timing {
+                bga_ends = image.timing;
+            }
+        }
+        events.push(Event { timing: bga_ends + 0.5, event_type: EventType::ChangeBga(None) });
+
+        for bpm in bms.bpms.iter() {
+            events.push(Event { timing: bpm.timing, event_type: EventType::ChangeBpm(bpm.bpm) });
+        }
+        events.sort_by(|a, b| a.timing.partial_cmp(&b.timing).unwrap());
+        let chart_end = bms.sounds.iter().fold(0.0, |end: f64, sound| f64::max(end, sound.ln_end.unwrap_or(sound.timing)));
+        let end_timing = 1.0 + f64::max(chart_end, events.last().map_or(0.0, |event| event.timing));
+        events.push(Event { timing: end_timing, event_type: EventType::EndMusic });
+
+        let judge_index_by_key = notes_by_key.keys().map(|key| (*key, 0usize)).collect();
+        let total = bms.total.unwrap_or(default_total(total_notes));
+        let sample_lengths = bms.samples.iter().filter_map(|(wav_id, sample)| sample.length.map(|length| (*wav_id, length))).collect();
+        GameSession {
+            notes_by_key: notes_by_key,
+            judge_index_by_key: judge_index_by_key,
+            events: events,
+            event_index: 0,
+            pushed_key_set: HashSet::new(),
+            holding: HashMap::new(),
+            judgerank: IIDX_JUDGERANK,
+            judge_count: HashMap::new(),
+            last_judge: None,
+            combo: 0,
+            max_combo: 0,
+            misses: vec![],
+            gauge: Gauge::new(total, total_notes),
+            bpm: DEFAULT_BPM,
+            total_notes: total_notes,
+            finished: false,
+            sample_lengths: sample_lengths,
+            outputs: vec![],
+        }
+    }
+
+    pub fn notes(&self, key: bms_loader::Key) -> &[Note] {
+        self.notes_by_key.get(&key).map_or(&[], |notes| &notes[..])
+    }
+
+    // judges the notes from start up to end make, two for a long note
+    pub fn judges_between(&self, start: Time, end: Time) -> u32 {
+        self.notes_by_key.values()
+            .flat_map(|notes| notes.iter())
+            .filter(|note| start <= note.timing && note.timing < end)
+            .map(|note| if note.ln_end.is_some() { 2 } else { 1 })
+            .sum()
+    }
+
+    // notes of the lane that are already judged, they come first in notes()
+    pub fn judged_count(&self, key: bms_loader::Key) -> usize {
+        *self.judge_index_by_key.get(&key).unwrap_or(&0)
+    }
+
+    fn schedule(&mut self, timing: Time, event_type: EventType) {
+        self.events.push(Event { timing: timing, event_type: event_type });
+        let index = self.event_index;
+        self.events[index..].sort_by(|a, b| a.timing.partial_cmp(&b.timing).unwrap());
+    }
+
+    // inputs from a replay or autoplay, judged when play time reaches them
+    pub fn schedule_input(&mut self, timing: Time, key: bms_loader::Key, pressed: bool) {
+        self.schedule(timing, EventType::Input(key, pressed));
+    }
+
+    pub fn schedule_speed(&mut self, timing: Time, value: f64) {
+        self.schedule(timing, EventType::ChangeSpeed(value));
+    }
+
+    pub fn schedule_cover(&mut self, timing: Time, cover: LaneCover) {
+        self.schedule(timing, EventType::ChangeCover(cover));
+    }
+
+    // judge a lane press happened at play time pt
+    pub fn press(&mut self, note_key: bms_loader::Key, pt: Time) {
+        self.pushed_key_set.insert(note_key);
+
+        let mut judged = None;
+        let mut sound = None;
+        let mut ln_end = None;
+        if let Some(index) = self.judge_index_by_key.get_mut(&note_key) {
+            let notes = &self.notes_by_key[&note_key];
+            while let Some(note) = notes.get(*index) {
+                if pt <= note.timing + 2.0 {
+                    if let Some(judge) = self.judgerank.get_judge(f64::abs(note.timing - pt)) {
+                        judged = Some(judge);
+                        if judge.consume_note(pt < note.timing) {
+                            *index += 1;
+                            ln_end = note.ln_end;
+                        }
+                    }
+                    sound = Some(note.wav_id);
+                    break;
+                }
+                *index += 1;
+            }
+        }
+
+        if let Some(wav_id) = sound {
+            self.outputs.push(SessionOutput::PlaySound(wav_id));
+        }
+        if let Some(judge) = judged {
+            self.judge(note_key, judge, pt);
+        }
+        if let Some(end) = ln_end {
+            self.holding.insert(note_key, end);
+        }
+    }
+
+    // a long note let go of is judged by how close to its end it was, too early is a POOR
+    pub fn release(&mut self, note_key: bms_loader::Key, pt: Time) {
+        self.pushed_key_set.remove(&note_key);
+        if let Some(end) = self.holding.remove(&note_key) {
+            let judge = match self.judgerank.get_judge(end - pt) {
+                Some(Judge::POOR) | None => Judge::POOR,
+                Some(judge) => judge,
+            };
+            self.judge(note_key, judge, pt);
+        }
+    }
+
+    fn judge(&mut self, note_key: bms_loader::Key, judge: Judge, pt: Time) {
+        if Judge::combo_lasts(judge) {
+            self.combo += 1;
+        } else {
+            self.combo = 0;
+        }
+        if self.combo > self.max_combo {
+            self.max_combo = self.combo;
+        }
+        if !Judge::combo_lasts(judge) {
+            self.misses.push(pt);
+        }
+        *self.judge_count.entry(judge).or_insert(0) += 1;
+        self.gauge.update(judge);
+        self.last_judge = Some((judge, pt));
+        self.outputs.push(SessionOutput::Judged(note_key, judge, pt));
+    }
+
+    // runs the timeline up to play time pt and misses the notes that went past the judge line
+    pub fn update(&mut self, pt: Time) {
+        while let Some(timing) = self.events.get(self.event_index).map(|event| event.timing) {
+            if timing > pt {
+                break;
+            }
+            let index = self.event_index;
+            self.event_index += 1;
+
+            let mut input = None;
+            match self.events[index].event_type {
+                EventType::ChangeBpm(bpm) => {
+                    self.bpm = f64::max(bpm, 1.0);
+                    self.outputs.push(SessionOutput::ChangeBpm(self.bpm));
+                }
+                EventType::PlaySound(wav_id) => {
+                    self.outputs.push(SessionOutput::PlaySound(wav_id));
+                }
+                EventType::ChangeBga(id) => {
+                    self.outputs.push(SessionOutput::ChangeBga(id));
+                }
+                EventType::ChangeSpeed(value) => {
+                    self.outputs.push(SessionOutput::ChangeSpeed(value, timing));
+                }
+                EventType::ChangeCover(cover) => {
+                    self.outputs.push(SessionOutput::ChangeCover(cover, timing));
+                }
+                EventType::Input(key, pressed) => {
+                    input = Some((key, pressed));
+                }
+                EventType::EndMusic => {
+                    self.finished = true;
+                }
+            }
+
+            // judged at the scheduled timing, not at the time this update happens to run
+            match input {
+                Some((key, true)) => self.press(key, timing),
+                Some((key, false)) => self.release(key, timing),
+                None => (),
+            }
+        }
+
+        // long notes held to their end
+        let mut held: Vec<(bms_loader::Key, Time)> = self.holding.iter().filter(|&(_, end)| *end <= pt).map(|(key, end)| (*key, *end)).collect();
+        held.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        for (note_key, end) in held {
+            self.holding.remove(&note_key);
+            self.judge(note_key, Judge::PGREAT, end);
+        }
+
+        self.miss_late(pt);
+    }
+
+    // notes before end still waiting to be hit are missed, as though play had gone on until their window closed
+    pub fn miss_before(&mut self, end: Time) {
+        let pt = end + self.judgerank.bad;
+        self.miss_late(pt);
+    }
+
+    // notes whose window has closed by pt
+    fn miss_late(&mut self, pt: Time) {
+        let mut missed = vec![];
+        for (note_key, index) in self.judge_index_by_key.iter_mut() {
+            let notes = &self.notes_by_key[note_key];
+            while let Some(note) = notes.get(*index) {
+                if pt > note.timing + self.judgerank.bad {
+                    missed.push(*note_key);
+                    // the end of a long note never pressed is missed with it
+                    if note.ln_end.is_some() {
+                        missed.push(*note_key);
+                    }
+                    *index += 1;
+                } else {
+                    break;
+                }
+            }
+        }
+        for note_key in missed {
+            self.judge(note_key, Judge::MISSED, pt);
+        }
+    }
+
+    // BGM samples started before pt that are still sounding, with how far in they are
+    pub fn sounding_at(&self, pt: Time) -> Vec<(SoundX, Time)> {
+        let mut sounding = vec![];
+        for event in &self.events {
+            if event.timing >= pt {
+                break;
+            }
+            if let EventType::PlaySound(wav_id) = event.event_type {
+                if let Some(length) = self.sample_lengths.get(&wav_id) {
+                    if event.timing + length > pt {
+                        sounding.push((wav_id, pt - event.timing));
+                    }
+                }
+            }
+        }
+        sounding
+    }
+
+    // moves the timeline to play time pt. notes before pt are skipped without a judge,
+    // the latest bpm, bga, speed and cover before pt are output again along with the BGM still sounding
+    pub fn seek(&mut self, pt: Time) {
+        let mut bga = None;
+        let mut speed = None;
+        let mut cover = None;
+        self.bpm = DEFAULT_BPM;
+        let mut index = 0;
+        while let Some(event) = self.events.get(index) {
+            if event.timing >= pt {
+                break;
+            }
+            match event.event_type {
+                EventType::ChangeBpm(bpm) => self.bpm = f64::max(bpm, 1.0),
+                EventType::ChangeBga(id) => bga = id,
+                EventType::ChangeSpeed(value) => speed = Some(value),
+                EventType::ChangeCover(cover_at) => cover = Some(cover_at),
+                _ => (),
+            }
+            index += 1;
+        }
+        self.event_index = index;
+
+        self.outputs.push(SessionOutput::ChangeBpm(self.bpm));
+        self.outputs.push(SessionOutput::ChangeBga(bga));
+        if let Some(value) = speed {
+            self.outputs.push(SessionOutput::ChangeSpeed(value, pt));
+        }
+        if let Some(cover) = cover {
+            self.outputs.push(SessionOutput::ChangeCover(cover, pt));
+        }
+        for (wav_id, offset) in self.sounding_at(pt) {
+            self.outputs.push(SessionOutput::ResumeSound(wav_id, offset));
+        }
+
+        for (note_key, index) in self.judge_index_by_key.iter_mut() {
+            *index = self.notes_by_key[note_key].iter().take_while(|note| note.timing < pt).count();
+        }
+        self.pushed_key_set.clear();
+        self.holding.clear();
+        // notes seeked back over are judged again, so what they were judged before is dropped
+        self.reset_result();
+        self.finished = false;
+    }
+
+    // starts counting judges, combo and gauge over, on every seek and so every practice loop
+    pub fn reset_result(&mut self) {
+        self.judge_count.clear();
+        self.last_judge = None;
+        self.combo = 0;
+        self.max_combo = 0;
+        self.misses.clear();
+        self.gauge.reset();
+    }
+
+    pub fn drain_outputs(&mut self) -> Vec<SessionOutput> {
+        self.outputs.drain(..).collect()
+    }
+
+    pub fn pushed_keys(&self) -> &HashSet<bms_loader::Key> {
+        &self.pushed_key_set
+    }
+
+    pub fn last_judge(&self) -> Option<(Judge, Time)> {
+        self.last_judge
+    }
+
+    pub fn combo(&self) -> u32 {
+        self.combo
+    }
+
+    pub fn gauge(&self) -> &Gauge {
+        &self.gauge
+    }
+
+    pub fn bpm(&self) -> f64 {
+        self.bpm
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn result(&self) -> PlayResult {
+        PlayResult {
+            judge_count: self.judge_count.clone(),
+            max_combo: self.max_combo,
+            total_notes: self.total_notes,
+            gauge: self.gauge.value(),
+            finished: self.finished,
+            misses: self.misses.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+pub fn test_bms(sounds: Vec<bms_loader::Sound>, total: Option<f64>) -> Bms {
+    Bms {
+        sounds: sounds,
+        mines: vec![],
+        bars: vec![],
+        bpms: vec![bms_loader::BpmChange { timing: 0.0, bpm: 150.0 }],
+        stops: vec![],
+        bga_changes: vec![],
+        total: total,
+        wav_files: vec![],
+        bmp_files: vec![],
+        bga: vec![],
+        images: vec![],
+        samples: HashMap::new(),
+    }
+}
+
+#[cfg(test)]
+pub fn test_sound(key: bms_loader::Key, timing: Time, id: u32) -> bms_loader::Sound {
+    bms_loader::Sound { key: key, timing: timing, wav_id: SoundX { id: id }, ln_end: None }
+}
+
+#[test]
+fn game_session_judge_test() {
+    use bms_loader::Key;
+    let bms = test_bms(vec![
+        test_sound(Key::P1_KEY1, 1.0, 1),
+        test_sound(Key::P1_KEY1, 2.0, 2),
+        test_sound(Key::P1_SCRATCH, 3.0, 3),
+        test_sound(Key::BACK_CHORUS, 0.5, 4),
+    ], Some(300.0));
+    let mut session = GameSession::new(&bms);
+    assert_eq!(3, session.result().total_notes);
+
+    session.update(0.6);
+    assert_eq!(vec![SessionOutput::ChangeBpm(150.0), SessionOutput::PlaySound(SoundX { id: 4 }), SessionOutput::ChangeBga(None)], session.drain_outputs());
+
+    session.press(Key::P1_KEY1, 1.01);
+    assert_eq!(vec![SessionOutput::PlaySound(SoundX { id: 1 }), SessionOutput::Judged(Key::P1_KEY1, Judge::PGREAT, 1.01)], session.drain_outputs());
+    assert_eq!(1, session.judged_count(Key::P1_KEY1));
+    assert_eq!(GAUGE_MAX, session.gauge().value());
+
+    // a press too early for the next note only plays its sound
+    session.release(Key::P1_KEY1, 1.1);
+    session.press(Key::P1_KEY1, 1.5);
+    assert_eq!(vec![SessionOutput::PlaySound(SoundX { id: 2 })], session.drain_outputs());
+
+    session.press(Key::P1_KEY1, 2.05);
+    assert_eq!(vec![SessionOutput::PlaySound(SoundX { id: 2 }), SessionOutput::Judged(Key::P1_KEY1, Judge::GOOD, 2.05)], session.drain_outputs());
+    assert_eq!(2, session.combo());
+
+    session.update(3.5);
+    assert_eq!(vec![SessionOutput::Judged(Key::P1_SCRATCH, Judge::MISSED, 3.5)], session.drain_outputs());
+    assert_eq!(0, session.combo());
+    assert!(!session.is_finished());
+
+    session.update(10.0);
+    assert!(session.is_finished());
+    let result = session.result();
+    assert_eq!(2, result.ex_score());
+    assert_eq!(1, result.bp());
+    assert_eq!(vec![3.5], result.misses);
+    assert_eq!(2, result.max_combo);
+    assert_eq!(Lamp::CLEAR, result.lamp());
+
+    // POORs break the combo and the full combo with it
+    let bms = test_bms(vec![
+        test_sound(Key::P1_KEY1, 1.0, 1),
+        test_sound(Key::P1_KEY1, 2.0, 1),
+    ], Some(300.0));
+    let mut session = GameSession::new(&bms);
+
+    // too early to be a BAD, the note is left to be hit
+    session.press(Key::P1_KEY1, 0.82);
+    assert_eq!(Some((Judge::POOR, 0.82)), session.last_judge());
+    assert_eq!(0, session.judged_count(Key::P1_KEY1));
+    assert_eq!(0, session.combo());
+    session.press(Key::P1_KEY1, 0.9);
+    assert_eq!(Some((Judge::GOOD, 0.9)), session.last_judge());
+    assert_eq!(1, session.combo());
+
+    // too late, the POOR takes the note and breaks the combo
+    session.press(Key::P1_KEY1, 2.18);
+    assert_eq!(Some((Judge::POOR, 2.18)), session.last_judge());
+    assert_eq!(2, session.judged_count(Key::P1_KEY1));
+    assert_eq!(0, session.combo());
+
+    session.update(10.0);
+    let result = session.result();
+    assert_eq!(1, result.max_combo);
+    assert_eq!(2, result.count(Judge::POOR));
+    assert_eq!(0, result.count(Judge::MISSED));
+    assert_eq!(vec![0.82, 2.18], result.misses);
+    assert_eq!(Lamp::CLEAR, result.lamp());
+}
+
+#[test]
+fn game_session_long_note_test() {
+    use bms_loader::Key;
+    let long_note = |timing: Time, end: Time| {
+        let mut sound = test_sound(Key::P1_KEY1, timing, 1);
+        sound.ln_end = Some(end);
+        sound
+    };
+    let bms = test_bms(vec![long_note(1.0, 2.0), long_note(3.0, 4.0), long_note(5.0, 6.0)], None);
+    let mut session = GameSession::new(&bms);
+    assert_eq!(6, session.result().total_notes);
+    assert_eq!(4, session.judges_between(0.0, 5.0));
+
+    // held through to the end
+    session.press(Key::P1_KEY1, 1.0);
+    session.update(2.0);
+    assert_eq!(Some((Judge::PGREAT, 2.0)), session.last_judge());
+    assert_eq!(2, session.combo());
+    session.release(Key::P1_KEY1, 2.1);
+    assert_eq!(2, session.combo());
+
+    // let go half way
+    session.press(Key::P1_KEY1, 3.0);
+    session.release(Key::P1_KEY1, 3.5);
+    assert_eq!(Some((Judge::POOR, 3.5)), session.last_judge());
+    assert_eq!(0, session.combo());
+
+    // never pressed, both ends are missed
+    session.update(10.0);
+    let result = session.result();
+    assert_eq!(3, result.count(Judge::PGREAT));
+    assert_eq!(1, result.count(Judge::POOR));
+    assert_eq!(2, result.count(Judge::MISSED));
+    assert_eq!(6, result.ex_score());
+}
+
+#[test]
+fn game_session_schedule_test() {
+    use bms_loader::Key;
+    let bms = test_bms(vec![test_sound(Key::P1_KEY2, 1.0, 1)], None);
+    let mut session = GameSession::new(&bms);
+    session.schedule_input(1.0, Key::P1_KEY2, true);
+    session.schedule_input(1.08, Key::P1_KEY2, false);
+
+    session.update(1.0);
+    assert!(session.pushed_keys().contains(&Key::P1_KEY2));
+    assert_eq!(Some((Judge::PGREAT, 1.0)), session.last_judge());
+    session.update(1.1);
+    assert!(session.pushed_keys().is_empty());
+
+    session.update(10.0);
+    assert_eq!(Lamp::MAX, session.result().lamp());
+}
+
+#[test]
+fn game_session_seek_test() {
+    use bms_loader::Key;
+    let mut bms = test_bms(vec![
+        test_sound(Key::BACK_CHORUS, 0.0, 1),
+        test_sound(Key::BACK_CHORUS, 1.0, 2),
+        test_sound(Key::P1_KEY1, 1.0, 3),
+        test_sound(Key::P1_KEY1, 3.0, 3),
+    ], None);
+    bms.samples.insert(SoundX { id: 1 }, bms_loader::Sample { path: Default::default(), length: Some(10.0) });
+    bms.samples.insert(SoundX { id: 2 }, bms_loader::Sample { path: Default::default(), length: Some(0.5) });
+    let mut session = GameSession::new(&bms);
+
+    session.seek(2.5);
+    assert_eq!(vec![SessionOutput::ChangeBpm(150.0), SessionOutput::ChangeBga(None), SessionOutput::ResumeSound(SoundX { id: 1 }, 2.5)], session.drain_outputs());
+    assert_eq!(1, session.judged_count(Key::P1_KEY1));
+
+    // nothing before the seek is played or missed
+    session.update(2.6);
+    assert!(session.drain_outputs().is_empty());
+    session.press(Key::P1_KEY1, 3.0);
+    assert_eq!(Some((Judge::PGREAT, 3.0)), session.last_judge());
+    session.drain_outputs();
+
+    session.seek(0.0);
+    assert_eq!(0, session.judged_count(Key::P1_KEY1));
+    session.update(0.0);
+    assert_eq!(vec![
+        SessionOutput::ChangeBpm(DEFAULT_BPM),
+        SessionOutput::ChangeBga(None),
+        SessionOutput::PlaySound(SoundX { id: 1 }),
+        SessionOutput::ChangeBpm(150.0),
+    ], session.drain_outputs());
+}
+
+#[test]
+fn game_session_seek_back_test() {
+    use bms_loader::Key;
+    let bms = test_bms(vec![
+        test_sound(Key::P1_KEY1, 1.0, 1),
+        test_sound(Key::P1_KEY2, 2.0, 1),
+    ], None);
+    let mut session = GameSession::new(&bms);
+    session.press(Key::P1_KEY1, 1.0);
+    session.press(Key::P1_KEY2, 2.0);
+    session.update(2.5);
+    assert_eq!(4, session.result().ex_score());
+
+    // both notes are judged again after seeking back over them, but only counted once
+    session.seek(0.5);
+    assert_eq!(0, session.combo());
+    assert_eq!(0, session.result().ex_score());
+    session.press(Key::P1_KEY1, 1.0);
+    session.update(3.0);
+    let result = session.result();
+    assert_eq!(2, result.ex_score());
+    assert_eq!(1, result.bp());
+    assert_eq!(vec![3.0], result.misses);
+    assert_eq!(1, result.max_combo);
+}
+
+#[test]
+fn gauge_test() {
+    let mut gauge = Gauge::new(200.0, 100);
+    gauge.update(Judge::GREAT);
+    gauge.update(Judge::GOOD);
+    assert_eq!(GAUGE_START + 3.0, gauge.value());
+    for _ in 0..10 {
+        gauge.update(Judge::MISSED);
+    }
+    assert_eq!(GAUGE_MIN, gauge.value());
+    assert!(!gauge.is_clear());
+    assert_eq!(160.0 + 100.0 * 0.16, default_total(100));
+}
@@ -0,0 +1,159 @@
+use bms_loader::BpmChange;
+
+type Time = f64;
+
+const SPEED_STEP: f64 = 0.1;
+const MIN_SPEED: f64 = 0.1;
+const GREEN_NUMBER_STEP: f64 = 10.0;
+const MIN_GREEN_NUMBER: f64 = 50.0;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HiSpeedMode {
+    NORMAL,   // raw multiplier, visual speed follows the bpm
+    FLOATING, // green number fixed for the base bpm, still follows bpm changes
+    CONSTANT, // green number fixed for every note, bpm changes are not shown
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BaseBpm {
+    MAIN,
+    MAX,
+    MIN,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct HiSpeed {
+    pub mode: HiSpeedMode,
+    pub base: BaseBpm,
+    pub value: f64, // multiplier for NORMAL, green number (visible ms) otherwise
+}
+
+impl HiSpeedMode {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            HiSpeedMode::NORMAL => "NORMAL",
+            HiSpeedMode::FLOATING => "FLOATING",
+            HiSpeedMode::CONSTANT => "CONSTANT",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<HiSpeedMode> {
+        match name {
+            "NORMAL" => Some(HiSpeedMode::NORMAL),
+            "FLOATING" => Some(HiSpeedMode::FLOATING),
+            "CONSTANT" => Some(HiSpeedMode::CONSTANT),
+            _ => None,
+        }
+    }
+}
+
+impl BaseBpm {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            BaseBpm::MAIN => "MAIN",
+            BaseBpm::MAX => "MAX",
+            BaseBpm::MIN => "MIN",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<BaseBpm> {
+        match name {
+            "MAIN" => Some(BaseBpm::MAIN),
+            "MAX" => Some(BaseBpm::MAX),
+            "MIN" => Some(BaseBpm::MIN),
+            _ => None,
+        }
+    }
+}
+
+impl HiSpeed {
+    pub fn normal(speed: f64) -> HiSpeed {
+        HiSpeed { mode: HiSpeedMode::NORMAL, base: BaseBpm::MAIN, value: speed }
+    }
+
+    pub fn green(mode: HiSpeedMode, base: BaseBpm, green_number: f64) -> HiSpeed {
+        HiSpeed { mode: mode, base: base, value: green_number }
+    }
+
+    // pixels per (second * bpm), which is what calc_position returns
+    pub fn multiplier(&self, base_bpm: f64, visible_height: f64) -> f64 {
+        match self.mode {
+            HiSpeedMode::NORMAL => self.value,
+            _ => visible_height * 1000.0 / (base_bpm * self.value),
+        }
+    }
+
+    // milliseconds a note at the base bpm stays visible
+    pub fn green_number(&self, base_bpm: f64, visible_height: f64) -> f64 {
+        match self.mode {
+            HiSpeedMode::NORMAL => visible_height * 1000.0 / (base_bpm * self.value),
+            _ => self.value,
+        }
+    }
+
+    pub fn faster(&self) -> f64 {
+        match self.mode {
+            HiSpeedMode::NORMAL => self.value + SPEED_STEP,
+            _ => f64::max(self.value - GREEN_NUMBER_STEP, MIN_GREEN_NUMBER),
+        }
+    }
+
+    pub fn slower(&self) -> f64 {
+        match self.mode {
+            HiSpeedMode::NORMAL => f64::max(self.value - SPEED_STEP, MIN_SPEED),
+            _ => self.value + GREEN_NUMBER_STEP,
+        }
+    }
+}
+
+// main bpm is the one the chart spends the longest time in.
+// bpms can come with stops in them as bpm 0, time spent stopped doesn't count
+pub fn base_bpm(bpms: &Vec<BpmChange>, end: Time, base: BaseBpm) -> f64 {
+    let changes: Vec<&BpmChange> = bpms.iter().filter(|change| change.bpm > 0.0).collect();
+    if changes.is_empty() {
+        return 130.0;
+    }
+
+    match base {
+        BaseBpm::MAX => changes.iter().fold(0.0, |max, change| f64::max(max, change.bpm)),
+        BaseBpm::MIN => changes.iter().fold(changes[0].bpm, |min, change| f64::min(min, change.bpm)),
+        BaseBpm::MAIN => {
+            let mut durations: Vec<(f64, Time)> = vec![];
+            for (i, change) in bpms.iter().enumerate() {
+                if change.bpm <= 0.0 {
+                    continue;
+                }
+                let next = bpms.get(i + 1).map_or(end, |next| f64::min(next.timing, end));
+                let duration = f64::max(next - change.timing, 0.0);
+                match durations.iter().position(|&(bpm, _)| bpm == change.bpm) {
+                    Some(index) => durations[index].1 += duration,
+                    None => durations.push((change.bpm, duration)),
+                }
+            }
+            durations.iter().fold(durations[0], |main, &entry| if entry.1 > main.1 { entry } else { main }).0
+        }
+    }
+}
+
+#[test]
+fn hispeed_test() {
+    let bpms = vec![
+        BpmChange { timing: 0.0, bpm: 150.0 },
+        BpmChange { timing: 10.0, bpm: 300.0 },
+        BpmChange { timing: 15.0, bpm: 75.0 },
+        BpmChange { timing: 20.0, bpm: 150.0 },
+    ];
+    assert_eq!(150.0, base_bpm(&bpms, 60.0, BaseBpm::MAIN));
+    assert_eq!(300.0, base_bpm(&bpms, 60.0, BaseBpm::MAX));
+    assert_eq!(75.0, base_bpm(&bpms, 60.0, BaseBpm::MIN));
+    let two = vec![BpmChange { timing: 0.0, bpm: 150.0 }, BpmChange { timing: 1.0, bpm: 300.0 }];
+    assert_eq!(300.0, base_bpm(&two, 60.0, BaseBpm::MAIN));
+    // stopped for most of the 300 part, so more time scrolls at 150
+    let stopped = vec![two[0].clone(), two[1].clone(), BpmChange { timing: 1.5, bpm: 0.0 }, BpmChange { timing: 60.0, bpm: 300.0 }];
+    assert_eq!(150.0, base_bpm(&stopped, 60.0, BaseBpm::MAIN));
+
+    // 600px at 150bpm with a multiplier of 2 scrolls through in 2 seconds
+    let hispeed = HiSpeed::green(HiSpeedMode::FLOATING, BaseBpm::MAIN, 2000.0);
+    assert!((hispeed.multiplier(150.0, 600.0) - 2.0).abs() < 1e-9);
+    assert!((HiSpeed::normal(2.0).green_number(150.0, 600.0) - 2000.0).abs() < 1e-9);
+}
@@ -0,0 +1,344 @@
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use piston::input::Key;
+use bms_loader;
+use key_config::{self, Control, KeyBindings};
+
+type Time = f64;
+
+// anything a lane or a control can be bound to
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
+pub enum InputSource {
+    Keyboard(Key),
+    Button(u32, u8),    // joystick index, button
+    AxisPlus(u32, u8),  // joystick index, axis turned towards +
+    AxisMinus(u32, u8), // joystick index, axis turned towards -
+}
+
+impl InputSource {
+    // Z, Joy0.Button3, Joy0.Axis1+, Joy0.Axis1-
+    pub fn name(&self) -> String {
+        match *self {
+            InputSource::Keyboard(key) => key_config::key_name(key),
+            InputSource::Button(joystick, button) => format!("Joy{}.Button{}", joystick, button),
+            InputSource::AxisPlus(joystick, axis) => format!("Joy{}.Axis{}+", joystick, axis),
+            InputSource::AxisMinus(joystick, axis) => format!("Joy{}.Axis{}-", joystick, axis),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<InputSource> {
+        if !name.starts_with("Joy") {
+            return key_config::key_from_name(name).map(InputSource::Keyboard);
+        }
+        let mut parts = name["Joy".len()..].splitn(2, '.');
+        let joystick = match parts.next().and_then(|index| index.parse().ok()) {
+            Some(joystick) => joystick,
+            None => return None,
+        };
+        let input = parts.next().unwrap_or("");
+        if input.starts_with("Button") {
+            input["Button".len()..].parse().ok().map(|button| InputSource::Button(joystick, button))
+        } else if input.starts_with("Axis") && input.len() > "Axis".len() + 1 {
+            let (axis, sign) = input["Axis".len()..].split_at(input.len() - "Axis".len() - 1);
+            match (axis.parse().ok(), sign) {
+                (Some(axis), "+") => Some(InputSource::AxisPlus(joystick, axis)),
+                (Some(axis), "-") => Some(InputSource::AxisMinus(joystick, axis)),
+                _ => None,
+            }
+        } else {
+            None
+        }
+    }
+}
+
+// input as the window or the controllers deliver it
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RawInput {
+    Key(Key, bool),
+    Button(u32, u8, bool),
+    Axis(u32, u8, f64), // position in -1.0..1.0
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TimedInput {
+    pub input: RawInput,
+    pub time: f64, // reading of the play clock's source, the audio clock in play, when the input was taken
+}
+
+// inputs are stamped as the game loop takes them from the window or the pads, and judged later by their stamp.
+// neither glutin's window events nor SDL's polled joysticks say when they happened, so how long an
+// input sat before the loop came round is not in the stamp
+pub struct InputQueue {
+    sender: Sender<TimedInput>,
+    receiver: Receiver<TimedInput>,
+}
+
+impl InputQueue {
+    pub fn new() -> InputQueue {
+        let (sender, receiver) = mpsc::channel();
+        InputQueue { sender: sender, receiver: receiver }
+    }
+
+    pub fn push_at(&self, input: RawInput, time: f64) {
+        self.sender.send(TimedInput { input: input, time: time }).unwrap();
+    }
+
+    // everything that arrived so far, oldest first across all producers
+    pub fn drain(&self) -> Vec<TimedInput> {
+        let mut inputs: Vec<TimedInput> = self.receiver.try_iter().collect();
+        inputs.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+        inputs
+    }
+}
+
+// how long a press waited between being stamped and being judged
+pub struct LatencyStats {
+    samples: Vec<f64>,
+}
+
+impl LatencyStats {
+    pub fn new() -> LatencyStats {
+        LatencyStats { samples: vec![] }
+    }
+
+    pub fn push(&mut self, latency: f64) {
+        self.samples.push(latency);
+    }
+
+    pub fn last(&self) -> Option<f64> {
+        self.samples.last().map(|latency| *latency)
+    }
+
+    pub fn average(&self) -> Option<f64> {
+        if self.samples.is_empty() {
+            None
+        } else {
+            Some(self.samples.iter().sum::<f64>() / self.samples.len() as f64)
+        }
+    }
+
+    pub fn max(&self) -> Option<f64> {
+        self.samples.iter().fold(None, |max, latency| Some(f64::max(max.unwrap_or(*latency), *latency)))
+    }
+
+    pub fn summary(&self) -> String {
+        match (self.average(), self.max()) {
+            (Some(average), Some(max)) => format!("input to judge latency: {} presses, avg {:.2}ms, max {:.2}ms", self.samples.len(), average * 1000.0, max * 1000.0),
+            _ => "input to judge latency: no presses".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum InputAction {
+    Press(bms_loader::Key),
+    Release(bms_loader::Key),
+    Control(Control),
+}
+
+// a turntable reports its rotation as an axis position, so it is pressed while it moves
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TurntableSettings {
+    pub dead_zone: f64, // movement smaller than this is ignored
+    pub timeout: Time,  // released when it has not moved for this long
+}
+
+struct Turntable {
+    position: f64,
+    direction: Option<InputSource>,
+    last_moved: Time,
+}
+
+// turns raw input into lane presses and controls through the bindings
+pub struct InputMapper {
+    bindings: KeyBindings,
+    held: HashSet<InputSource>,
+    turntables: HashMap<(u32, u8), Turntable>,
+}
+
+impl InputMapper {
+    pub fn new(bindings: KeyBindings) -> InputMapper {
+        InputMapper { bindings: bindings, held: HashSet::new(), turntables: HashMap::new() }
+    }
+
+    pub fn feed(&mut self, input: RawInput, now: Time) -> Vec<InputAction> {
+        match input {
+            RawInput::Key(key, true) => self.source_down(InputSource::Keyboard(key)),
+            RawInput::Key(key, false) => self.source_up(InputSource::Keyboard(key)),
+            RawInput::Button(joystick, button, true) => self.source_down(InputSource::Button(joystick, button)),
+            RawInput::Button(joystick, button, false) => self.source_up(InputSource::Button(joystick, button)),
+            RawInput::Axis(joystick, axis, position) => self.axis_moved(joystick, axis, position, now),
+        }
+    }
+
+    // releases turntables that stopped moving, call every frame
+    pub fn tick(&mut self, now: Time) -> Vec<InputAction> {
+        let timeout = self.bindings.turntable.timeout;
+        let stopped: Vec<InputSource> = self.turntables.values_mut().filter_map(|turntable| {
+            if now - turntable.last_moved > timeout {
+                turntable.direction.take()
+            } else {
+                None
+            }
+        }).collect();
+
+        let mut actions = vec![];
+        for source in stopped {
+            actions.extend(self.source_up(source));
+        }
+        actions
+    }
+
+    fn axis_moved(&mut self, joystick: u32, axis: u8, position: f64, now: Time) -> Vec<InputAction> {
+        let dead_zone = self.bindings.turntable.dead_zone;
+        let (previous, next) = {
+            let turntable = self.turntables.entry((joystick, axis)).or_insert(Turntable { position: position, direction: None, last_moved: now });
+            let mut delta = position - turntable.position;
+            // the position wraps around once per rotation
+            if delta > 1.0 {
+                delta -= 2.0;
+            } else if delta < -1.0 {
+                delta += 2.0;
+            }
+            if delta.abs() <= dead_zone {
+                return vec![];
+            }
+            turntable.position = position;
+            turntable.last_moved = now;
+
+            let direction = if delta > 0.0 { InputSource::AxisPlus(joystick, axis) } else { InputSource::AxisMinus(joystick, axis) };
+            if turntable.direction == Some(direction) {
+                return vec![];
+            }
+            (mem::replace(&mut turntable.direction, Some(direction)), direction)
+        };
+
+        let mut actions = vec![];
+        if let Some(previous) = previous {
+            actions.extend(self.source_up(previous));
+        }
+        actions.extend(self.source_down(next));
+        actions
+    }
+
+    fn source_down(&mut self, source: InputSource) -> Vec<InputAction> {
+        // the OS repeats held keys
+        if !self.held.insert(source) {
+            return vec![];
+        }
+        if let Some(control) = self.bindings.controls.get(&source) {
+            return vec![InputAction::Control(*control)];
+        }
+        self.bindings.lanes.get(&source).map(|lane| InputAction::Press(*lane)).into_iter().collect()
+    }
+
+    fn source_up(&mut self, source: InputSource) -> Vec<InputAction> {
+        if !self.held.remove(&source) {
+            return vec![];
+        }
+        let lane = match self.bindings.lanes.get(&source) {
+            Some(lane) => *lane,
+            None => return vec![],
+        };
+        // the lane stays down while another source bound to it is held
+        let bindings = &self.bindings;
+        if self.held.iter().any(|held| bindings.lanes.get(held) == Some(&lane)) {
+            vec![]
+        } else {
+            vec![InputAction::Release(lane)]
+        }
+    }
+}
+
+#[test]
+fn input_queue_test() {
+    let queue = InputQueue::new();
+    queue.push_at(RawInput::Key(Key::Z, true), 2.0);
+    queue.push_at(RawInput::Button(0, 1, true), 1.0);
+    queue.push_at(RawInput::Key(Key::Z, false), 3.0);
+
+    let times: Vec<f64> = queue.drain().iter().map(|input| input.time).collect();
+    assert_eq!(vec![1.0, 2.0, 3.0], times);
+    assert_eq!(0, queue.drain().len());
+
+    let mut latency = LatencyStats::new();
+    assert_eq!(None, latency.average());
+    latency.push(0.001);
+    latency.push(0.003);
+    assert_eq!(Some(0.002), latency.average());
+    assert_eq!(Some(0.003), latency.max());
+}
+
+#[cfg(test)]
+fn test_mapper() -> InputMapper {
+    let mut lanes = HashMap::new();
+    lanes.insert(InputSource::Keyboard(Key::Z), bms_loader::Key::P1_KEY1);
+    lanes.insert(InputSource::Keyboard(Key::J), bms_loader::Key::P1_KEY1);
+    lanes.insert(InputSource::Button(0, 0), bms_loader::Key::P1_KEY1);
+    lanes.insert(InputSource::AxisPlus(0, 0), bms_loader::Key::P1_SCRATCH);
+    lanes.insert(InputSource::AxisMinus(0, 0), bms_loader::Key::P1_SCRATCH);
+    let mut controls = HashMap::new();
+    controls.insert(InputSource::Keyboard(Key::Escape), Control::QUIT);
+    InputMapper::new(KeyBindings {
+        lanes: lanes,
+        controls: controls,
+        turntable: TurntableSettings { dead_zone: 0.01, timeout: 0.1 },
+    })
+}
+
+#[test]
+fn input_source_name_test() {
+    let sources = vec![
+        InputSource::Keyboard(Key::Semicolon),
+        InputSource::Button(1, 12),
+        InputSource::AxisPlus(0, 3),
+        InputSource::AxisMinus(2, 0),
+    ];
+    for source in sources {
+        assert_eq!(Some(source), InputSource::from_name(&source.name()));
+    }
+    assert_eq!(None, InputSource::from_name("Joy0.Axis1"));
+    assert_eq!(None, InputSource::from_name("JoyX.Button1"));
+}
+
+#[test]
+fn input_mapper_keys_test() {
+    let mut mapper = test_mapper();
+    let press = vec![InputAction::Press(bms_loader::Key::P1_KEY1)];
+    let release = vec![InputAction::Release(bms_loader::Key::P1_KEY1)];
+
+    assert_eq!(press, mapper.feed(RawInput::Key(Key::Z, true), 0.0));
+    // key repeat
+    assert_eq!(0, mapper.feed(RawInput::Key(Key::Z, true), 0.1).len());
+    // a second key on the same lane presses again but only the last release lets go
+    assert_eq!(press, mapper.feed(RawInput::Button(0, 0, true), 0.2));
+    assert_eq!(0, mapper.feed(RawInput::Key(Key::Z, false), 0.3).len());
+    assert_eq!(release, mapper.feed(RawInput::Button(0, 0, false), 0.4));
+
+    assert_eq!(vec![InputAction::Control(Control::QUIT)], mapper.feed(RawInput::Key(Key::Escape, true), 0.5));
+    assert_eq!(0, mapper.feed(RawInput::Key(Key::Q, true), 0.6).len());
+}
+
+#[test]
+fn input_mapper_turntable_test() {
+    let mut mapper = test_mapper();
+    let press = vec![InputAction::Press(bms_loader::Key::P1_SCRATCH)];
+    let release = vec![InputAction::Release(bms_loader::Key::P1_SCRATCH)];
+
+    // the first report only tells where the turntable is
+    assert_eq!(0, mapper.feed(RawInput::Axis(0, 0, 0.5), 0.0).len());
+    // inside the dead zone
+    assert_eq!(0, mapper.feed(RawInput::Axis(0, 0, 0.505), 0.01).len());
+    assert_eq!(press, mapper.feed(RawInput::Axis(0, 0, 0.6), 0.02));
+    assert_eq!(0, mapper.feed(RawInput::Axis(0, 0, 0.7), 0.05).len());
+    assert_eq!(0, mapper.tick(0.1).len());
+    // turning back releases and presses again
+    assert_eq!(vec![release[0], press[0]], mapper.feed(RawInput::Axis(0, 0, 0.6), 0.12));
+    assert_eq!(release, mapper.tick(0.3));
+    assert_eq!(0, mapper.tick(0.4).len());
+    // wrapping from 1.0 to -1.0 keeps the direction
+    assert_eq!(press, mapper.feed(RawInput::Axis(0, 0, 0.95), 0.5));
+    assert_eq!(0, mapper.feed(RawInput::Axis(0, 0, -0.95), 0.52).len());
+}
@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::io;
+
+use piston::input::Key;
+use bms_loader::{self, PlayMode};
+use config::Config;
+use input::{InputSource, TurntableSettings};
+
+const KEY_CONFIG_FILE: &'static str = "keyconfig.cfg";
+const DEFAULT_DEAD_ZONE: f64 = 0.005;
+const DEFAULT_TURNTABLE_TIMEOUT: f64 = 0.1;
+
+// in-game controls that are not lanes
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
+pub enum Control {
+    SPEED_UP,
+    SPEED_DOWN,
+    SUDDEN_PLUS,
+    SUDDEN_MINUS,
+    HIDDEN_PLUS,
+    HIDDEN_MINUS,
+    LIFT_PLUS,
+    LIFT_MINUS,
+    PAUSE,
+    SEEK_FORWARD, // to the next bar line
+    SEEK_BACKWARD, // to the start of this measure, or the previous one right after a bar line
+    RETRY,         // with the same lane option
+    RETRY_REROLL,  // with a new seed for RANDOM and the like
+    QUIT,
+}
+
+impl Control {
+    pub fn all() -> Vec<Control> {
+        vec![Control::SPEED_UP, Control::SPEED_DOWN, Control::SUDDEN_PLUS, Control::SUDDEN_MINUS,
+             Control::HIDDEN_PLUS, Control::HIDDEN_MINUS, Control::LIFT_PLUS, Control::LIFT_MINUS,
+             Control::PAUSE, Control::SEEK_FORWARD, Control::SEEK_BACKWARD, Control::RETRY, Control::RETRY_REROLL, Control::QUIT]
+    }
+
+    fn default_keys(&self) -> Vec<InputSource> {
+        match *self {
+            Control::SPEED_UP => vec![Key::Up],
+            Control::SPEED_DOWN => vec![Key::Down],
+            Control::SUDDEN_PLUS => vec![Key::Right],
+            Control::SUDDEN_MINUS => vec![Key::Left],
+            Control::HIDDEN_PLUS => vec![Key::PageUp],
+            Control::HIDDEN_MINUS => vec![Key::PageDown],
+            Control::LIFT_PLUS => vec![Key::Home],
+            Control::LIFT_MINUS => vec![Key::End],
+            Control::PAUSE => vec![Key::P],
+            Control::SEEK_FORWARD => vec![Key::RightBracket],
+            Control::SEEK_BACKWARD => vec![Key::LeftBracket],
+            Control::RETRY => vec![Key::F5],
+            Control::RETRY_REROLL => vec![Key::F6],
+            Control::QUIT => vec![Key::Escape],
+        }.into_iter().map(InputSource::Keyboard).collect()
+    }
+}
+
+// keys that can be written in the config file, by their Debug name
+const NAMED_KEYS: &'static [Key] = &[
+    Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I, Key::J, Key::K, Key::L, Key::M,
+    Key::N, Key::O, Key::P, Key::Q, Key::R, Key::S, Key::T, Key::U, Key::V, Key::W, Key::X, Key::Y, Key::Z,
+    Key::D0, Key::D1, Key::D2, Key::D3, Key::D4, Key::D5, Key::D6, Key::D7, Key::D8, Key::D9,
+    Key::NumPad0, Key::NumPad1, Key::NumPad2, Key::NumPad3, Key::NumPad4,
+    Key::NumPad5, Key::NumPad6, Key::NumPad7, Key::NumPad8, Key::NumPad9,
+    Key::NumPadPlus, Key::NumPadMinus, Key::NumPadMultiply, Key::NumPadDivide, Key::NumPadPeriod, Key::NumPadEnter,
+    Key::Semicolon, Key::Comma, Key::Period, Key::Slash, Key::Backslash, Key::Quote, Key::Backquote,
+    Key::Minus, Key::Equals, Key::LeftBracket, Key::RightBracket,
+    Key::Space, Key::Return, Key::Tab, Key::Backspace, Key::Escape, Key::Insert, Key::Delete, Key::CapsLock,
+    Key::Up, Key::Down, Key::Left, Key::Right, Key::PageUp, Key::PageDown, Key::Home, Key::End,
+    Key::LShift, Key::RShift, Key::LCtrl, Key::RCtrl, Key::LAlt, Key::RAlt,
+    Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6, Key::F7, Key::F8, Key::F9, Key::F10, Key::F11, Key::F12,
+];
+
+pub fn key_name(key: Key) -> String {
+    format!("{:?}", key)
+}
+
+pub fn key_from_name(name: &str) -> Option<Key> {
+    NAMED_KEYS.iter().find(|key| key_name(**key) == name).map(|key| *key)
+}
+
+fn default_lane_keys(mode: PlayMode, lane: bms_loader::Key) -> Vec<InputSource> {
+    let keys = default_keyboard_keys(mode, lane).into_iter().map(InputSource::Keyboard);
+    keys.chain(default_controller_inputs(mode, lane)).collect()
+}
+
+// the usual USB controller layout: keys 1-7 on buttons 0-6, turntable on the first axis
+fn default_controller_inputs(mode: PlayMode, lane: bms_loader::Key) -> Vec<InputSource> {
+    use bms_loader::Key as Lane;
+    match (mode, lane) {
+        (PlayMode::BEAT_5K, Lane::P1_SCRATCH) | (PlayMode::BEAT_7K, Lane::P1_SCRATCH) => {
+            vec![InputSource::AxisPlus(0, 0), InputSource::AxisMinus(0, 0)]
+        }
+        (PlayMode::BEAT_5K, _) | (PlayMode::BEAT_7K, _) if (lane as u8) >= 1 && (lane as u8) <= 7 => {
+            vec![InputSource::Button(0, lane as u8 - 1)]
+        }
+        _ => vec![],
+    }
+}
+
+fn default_keyboard_keys(mode: PlayMode, lane: bms_loader::Key) -> Vec<Key> {
+    use bms_loader::Key as Lane;
+    match (mode, lane) {
+        (PlayMode::POPN_9K, Lane::P1_KEY1) => vec![Key::A],
+        (PlayMode::POPN_9K, Lane::P1_KEY2) => vec![Key::S],
+        (PlayMode::POPN_9K, Lane::P1_KEY3) => vec![Key::D],
+        (PlayMode::POPN_9K, Lane::P1_KEY4) => vec![Key::F],
+        (PlayMode::POPN_9K, Lane::P1_KEY5) => vec![Key::Space],
+        (PlayMode::POPN_9K, Lane::P2_KEY2) => vec![Key::J],
+        (PlayMode::POPN_9K, Lane::P2_KEY3) => vec![Key::K],
+        (PlayMode::POPN_9K, Lane::P2_KEY4) => vec![Key::L],
+        (PlayMode::POPN_9K, Lane::P2_KEY5) => vec![Key::Semicolon],
+
+        // both sides on one keyboard leaves no room for the J/K/L/; alternatives
+        (PlayMode::BEAT_14K, Lane::P1_SCRATCH) => vec![Key::LShift],
+        (PlayMode::BEAT_14K, Lane::P1_KEY1) => vec![Key::Z],
+        (PlayMode::BEAT_14K, Lane::P1_KEY2) => vec![Key::S],
+        (PlayMode::BEAT_14K, Lane::P1_KEY3) => vec![Key::X],
+        (PlayMode::BEAT_14K, Lane::P1_KEY4) => vec![Key::D],
+        (PlayMode::BEAT_14K, Lane::P1_KEY5) => vec![Key::C],
+        (PlayMode::BEAT_14K, Lane::P1_KEY6) => vec![Key::F],
+        (PlayMode::BEAT_14K, Lane::P1_KEY7) => vec![Key::V],
+        (PlayMode::BEAT_14K, Lane::P2_KEY1) => vec![Key::M],
+        (PlayMode::BEAT_14K, Lane::P2_KEY2) => vec![Key::K],
+        (PlayMode::BEAT_14K, Lane::P2_KEY3) => vec![Key::Comma],
+        (PlayMode::BEAT_14K, Lane::P2_KEY4) => vec![Key::L],
+        (PlayMode::BEAT_14K, Lane::P2_KEY5) => vec![Key::Period],
+        (PlayMode::BEAT_14K, Lane::P2_KEY6) => vec![Key::Semicolon],
+        (PlayMode::BEAT_14K, Lane::P2_KEY7) => vec![Key::Slash],
+        (PlayMode::BEAT_14K, Lane::P2_SCRATCH) => vec![Key::RShift],
+
+        (_, Lane::P1_SCRATCH) => vec![Key::A, Key::LShift],
+        (_, Lane::P1_KEY1) => vec![Key::Z, Key::J],
+        (_, Lane::P1_KEY2) => vec![Key::S],
+        (_, Lane::P1_KEY3) => vec![Key::X, Key::K],
+        (_, Lane::P1_KEY4) => vec![Key::D],
+        (_, Lane::P1_KEY5) => vec![Key::C, Key::L],
+        (_, Lane::P1_KEY6) => vec![Key::F],
+        (_, Lane::P1_KEY7) => vec![Key::V, Key::Semicolon],
+        _ => vec![],
+    }
+}
+
+// the bindings of one play mode, as the player looks them up
+pub struct KeyBindings {
+    pub lanes: HashMap<InputSource, bms_loader::Key>,
+    pub controls: HashMap<InputSource, Control>,
+    pub turntable: TurntableSettings,
+}
+
+// keys.<mode>.<lane> = Z, J, Joy0.Button0
+// control.<control> = Up
+// turntable.dead_zone = 0.005
+// turntable.timeout = 0.1
+pub struct KeyConfig {
+    config: Config,
+}
+
+impl KeyConfig {
+    pub fn load() -> KeyConfig {
+        KeyConfig { config: Config::open(KEY_CONFIG_FILE) }
+    }
+
+    fn parse_keys(value: &str) -> Vec<InputSource> {
+        value.split(',').map(|name| name.trim()).filter(|name| !name.is_empty()).filter_map(|name| {
+            let source = InputSource::from_name(name);
+            if source.is_none() {
+                println!("unknown key in key config: {}", name);
+            }
+            source
+        }).collect()
+    }
+
+    fn join_keys(keys: &Vec<InputSource>) -> String {
+        keys.iter().map(|key| key.name()).collect::<Vec<String>>().join(", ")
+    }
+
+    pub fn lane_keys(&self, mode: PlayMode, lane: bms_loader::Key) -> Vec<InputSource> {
+        match self.config.get(&format!("keys.{}.{:?}", mode.name(), lane)) {
+            Some(value) => KeyConfig::parse_keys(value),
+            None => default_lane_keys(mode, lane),
+        }
+    }
+
+    pub fn set_lane_keys(&mut self, mode: PlayMode, lane: bms_loader::Key, keys: &Vec<InputSource>) {
+        self.config.set(&format!("keys.{}.{:?}", mode.name(), lane), KeyConfig::join_keys(keys));
+    }
+
+    pub fn control_keys(&self, control: Control) -> Vec<InputSource> {
+        match self.config.get(&format!("control.{:?}", control)) {
+            Some(value) => KeyConfig::parse_keys(value),
+            None => control.default_keys(),
+        }
+    }
+
+    pub fn set_control_keys(&mut self, control: Control, keys: &Vec<InputSource>) {
+        self.config.set(&format!("control.{:?}", control), KeyConfig::join_keys(keys));
+    }
+
+    pub fn bindings(&self, mode: PlayMode) -> KeyBindings {
+        let mut controls = HashMap::new();
+        for control in Control::all() {
+            for key in self.control_keys(control) {
+                controls.insert(key, control);
+            }
+        }
+        // a key bound to a lane is never also a control
+        let mut lanes = HashMap::new();
+        for lane in mode.lanes() {
+            for key in self.lane_keys(mode, lane) {
+                controls.remove(&key);
+                lanes.insert(key, lane);
+            }
+        }
+        KeyBindings { lanes: lanes, controls: controls, turntable: self.turntable() }
+    }
+
+    pub fn turntable(&self) -> TurntableSettings {
+        TurntableSettings {
+            dead_zone: self.config.get_f64("turntable.dead_zone", DEFAULT_DEAD_ZONE),
+            timeout: self.config.get_f64("turntable.timeout", DEFAULT_TURNTABLE_TIMEOUT),
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        self.config.save()
+    }
+}
+
+#[test]
+fn key_name_test() {
+    for key in NAMED_KEYS {
+        assert_eq!(Some(*key), key_from_name(&key_name(*key)));
+    }
+    assert_eq!(vec![InputSource::Keyboard(Key::Z), InputSource::Button(0, 1)], KeyConfig::parse_keys("Z, Joy0.Button1,, NotAKey"));
+    assert_eq!("A, Joy0.Axis0+", KeyConfig::join_keys(&vec![InputSource::Keyboard(Key::A), InputSource::AxisPlus(0, 0)]));
+}
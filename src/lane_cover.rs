@@ -0,0 +1,90 @@
+use config::Config;
+
+// all values are in 1/1000 of the lane height, the same unit as the white number
+const MAX_COVERED: f64 = 900.0;
+const STEP: f64 = 10.0;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LaneCover {
+    pub sudden: f64, // SUDDEN+, covers the top of the lane
+    pub hidden: f64, // HIDDEN+, covers the lane right above the judge line
+    pub lift: f64,   // LIFT, raises the judge line
+}
+
+impl LaneCover {
+    pub fn none() -> LaneCover {
+        LaneCover { sudden: 0.0, hidden: 0.0, lift: 0.0 }
+    }
+
+    pub fn load(config: &Config) -> LaneCover {
+        LaneCover {
+            sudden: config.get_f64("cover.sudden", 0.0),
+            hidden: config.get_f64("cover.hidden", 0.0),
+            lift: config.get_f64("cover.lift", 0.0),
+        }.clamped()
+    }
+
+    pub fn store(&self, config: &mut Config) {
+        config.set("cover.sudden", self.sudden.to_string());
+        config.set("cover.hidden", self.hidden.to_string());
+        config.set("cover.lift", self.lift.to_string());
+    }
+
+    // keeps some of the lane visible no matter how the covers are stacked
+    pub fn clamped(&self) -> LaneCover {
+        let lift = f64::min(f64::max(self.lift, 0.0), MAX_COVERED);
+        let sudden = f64::min(f64::max(self.sudden, 0.0), MAX_COVERED - lift);
+        let hidden = f64::min(f64::max(self.hidden, 0.0), MAX_COVERED - lift - sudden);
+        LaneCover { sudden: sudden, hidden: hidden, lift: lift }
+    }
+
+    pub fn adjust_sudden(&self, steps: f64) -> LaneCover {
+        LaneCover { sudden: self.sudden + steps * STEP, ..*self }.clamped()
+    }
+
+    pub fn adjust_hidden(&self, steps: f64) -> LaneCover {
+        LaneCover { hidden: self.hidden + steps * STEP, ..*self }.clamped()
+    }
+
+    pub fn adjust_lift(&self, steps: f64) -> LaneCover {
+        LaneCover { lift: self.lift + steps * STEP, ..*self }.clamped()
+    }
+
+    pub fn judge_line_y(&self, lane_height: f64) -> f64 {
+        lane_height * (1000.0 - self.lift) / 1000.0
+    }
+
+    // bottom edge of SUDDEN+
+    pub fn sudden_y(&self, lane_height: f64) -> f64 {
+        lane_height * self.sudden / 1000.0
+    }
+
+    // top edge of HIDDEN+
+    pub fn hidden_y(&self, lane_height: f64) -> f64 {
+        self.judge_line_y(lane_height) - lane_height * self.hidden / 1000.0
+    }
+
+    // distance a note travels in sight; like in IIDX the green number ignores HIDDEN+
+    pub fn visible_height(&self, lane_height: f64) -> f64 {
+        self.judge_line_y(lane_height) - self.sudden_y(lane_height)
+    }
+
+    pub fn white_number(&self) -> f64 {
+        self.sudden
+    }
+}
+
+#[test]
+fn lane_cover_test() {
+    let cover = LaneCover { sudden: 250.0, hidden: 100.0, lift: 50.0 };
+    assert_eq!(950.0, cover.judge_line_y(1000.0));
+    assert_eq!(250.0, cover.sudden_y(1000.0));
+    assert_eq!(850.0, cover.hidden_y(1000.0));
+    assert_eq!(700.0, cover.visible_height(1000.0));
+
+    let cover = LaneCover::none().adjust_lift(80.0).adjust_sudden(80.0).adjust_hidden(80.0);
+    assert_eq!(800.0, cover.lift);
+    assert_eq!(100.0, cover.sudden);
+    assert_eq!(0.0, cover.hidden);
+    assert_eq!(LaneCover::none(), LaneCover::none().adjust_sudden(-3.0));
+}
@@ -0,0 +1,197 @@
+use bms_loader::{Key, PlayMode};
+
+// DP has to fit both sides in the window, so its lanes are narrower
+struct LaneWidths {
+    scratch: f64,
+    white: f64,
+    blue: f64,
+}
+
+const SINGLE_WIDTHS: LaneWidths = LaneWidths { scratch: 108.0, white: 60.0, blue: 50.0 };
+const DOUBLE_WIDTHS: LaneWidths = LaneWidths { scratch: 72.0, white: 40.0, blue: 34.0 };
+const POPN_WIDTH: f64 = 56.0;
+const DP_GAP: f64 = 40.0; // between the 1P and 2P lanes
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum LaneColor {
+    WHITE,
+    BLUE,
+    RED,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ScratchSide {
+    LEFT,
+    RIGHT,
+}
+
+// which side of the screen the lanes are on, DP uses both
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PlaySide {
+    P1,
+    P2,
+}
+
+impl ScratchSide {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ScratchSide::LEFT => "LEFT",
+            ScratchSide::RIGHT => "RIGHT",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ScratchSide> {
+        match name {
+            "LEFT" => Some(ScratchSide::LEFT),
+            "RIGHT" => Some(ScratchSide::RIGHT),
+            _ => None,
+        }
+    }
+
+    fn flipped(&self) -> ScratchSide {
+        match *self {
+            ScratchSide::LEFT => ScratchSide::RIGHT,
+            ScratchSide::RIGHT => ScratchSide::LEFT,
+        }
+    }
+}
+
+impl PlaySide {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            PlaySide::P1 => "1P",
+            PlaySide::P2 => "2P",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<PlaySide> {
+        match name {
+            "1P" => Some(PlaySide::P1),
+            "2P" => Some(PlaySide::P2),
+            _ => None,
+        }
+    }
+}
+
+// x is from the left edge of the lanes
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Lane {
+    pub key: Key,
+    pub x: f64,
+    pub width: f64,
+    pub color: LaneColor,
+}
+
+pub struct LaneLayout {
+    lanes: Vec<Lane>,
+    width: f64,
+    side: PlaySide,
+    double: bool,
+}
+
+// scratch then keys 1-7 alternating white and blue, or the other way round
+fn beat_lanes(keys: &[Key], scratch: Key, scratch_side: ScratchSide, widths: &LaneWidths) -> Vec<(Key, f64, LaneColor)> {
+    let mut lanes: Vec<(Key, f64, LaneColor)> = keys.iter().enumerate().map(|(i, key)| {
+        if i % 2 == 0 { (*key, widths.white, LaneColor::WHITE) } else { (*key, widths.blue, LaneColor::BLUE) }
+    }).collect();
+    match scratch_side {
+        ScratchSide::LEFT => lanes.insert(0, (scratch, widths.scratch, LaneColor::RED)),
+        ScratchSide::RIGHT => lanes.push((scratch, widths.scratch, LaneColor::RED)),
+    }
+    lanes
+}
+
+impl LaneLayout {
+    pub fn new(mode: PlayMode, scratch_side: ScratchSide, side: PlaySide) -> LaneLayout {
+        let p1_keys = [Key::P1_KEY1, Key::P1_KEY2, Key::P1_KEY3, Key::P1_KEY4, Key::P1_KEY5, Key::P1_KEY6, Key::P1_KEY7];
+        let p2_keys = [Key::P2_KEY1, Key::P2_KEY2, Key::P2_KEY3, Key::P2_KEY4, Key::P2_KEY5, Key::P2_KEY6, Key::P2_KEY7];
+        // (key, width, color) from left to right, None for a gap
+        let columns: Vec<Option<(Key, f64, LaneColor)>> = match mode {
+            PlayMode::BEAT_5K => beat_lanes(&p1_keys[..5], Key::P1_SCRATCH, scratch_side, &SINGLE_WIDTHS).into_iter().map(Some).collect(),
+            PlayMode::BEAT_7K => beat_lanes(&p1_keys, Key::P1_SCRATCH, scratch_side, &SINGLE_WIDTHS).into_iter().map(Some).collect(),
+            PlayMode::POPN_9K => {
+                let colors = [LaneColor::WHITE, LaneColor::BLUE, LaneColor::WHITE, LaneColor::BLUE, LaneColor::RED,
+                              LaneColor::BLUE, LaneColor::WHITE, LaneColor::BLUE, LaneColor::WHITE];
+                mode.lanes().into_iter().zip(colors.iter()).map(|(key, color)| Some((key, POPN_WIDTH, *color))).collect()
+            }
+            // the 2P side mirrors the 1P side, so the scratches are both outside or both inside
+            PlayMode::BEAT_14K => {
+                let mut columns: Vec<_> = beat_lanes(&p1_keys, Key::P1_SCRATCH, scratch_side, &DOUBLE_WIDTHS).into_iter().map(Some).collect();
+                columns.push(None);
+                columns.extend(beat_lanes(&p2_keys, Key::P2_SCRATCH, scratch_side.flipped(), &DOUBLE_WIDTHS).into_iter().map(Some));
+                columns
+            }
+        };
+
+        let mut lanes = vec![];
+        let mut x = 0.0;
+        for column in columns {
+            match column {
+                Some((key, width, color)) => {
+                    lanes.push(Lane { key: key, x: x, width: width, color: color });
+                    x += width;
+                }
+                None => x += DP_GAP,
+            }
+        }
+        LaneLayout { lanes: lanes, width: x, side: side, double: mode == PlayMode::BEAT_14K }
+    }
+
+    pub fn lanes(&self) -> &[Lane] {
+        &self.lanes
+    }
+
+    pub fn lane(&self, key: Key) -> Option<&Lane> {
+        self.lanes.iter().find(|lane| lane.key == key)
+    }
+
+    pub fn width(&self) -> f64 {
+        self.width
+    }
+
+    pub fn side(&self) -> PlaySide {
+        self.side
+    }
+
+    // both sides at once, skins place DP on its own
+    pub fn is_double(&self) -> bool {
+        self.double
+    }
+
+    // left edge of the lanes on a screen this wide, the 2P side is against the right edge
+    pub fn x(&self, screen_width: f64) -> f64 {
+        match self.side {
+            PlaySide::P1 => 0.0,
+            PlaySide::P2 => f64::max(screen_width - self.width, 0.0),
+        }
+    }
+}
+
+#[test]
+fn lane_layout_test() {
+    let layout = LaneLayout::new(PlayMode::BEAT_7K, ScratchSide::LEFT, PlaySide::P1);
+    assert_eq!(8, layout.lanes().len());
+    assert_eq!(108.0 + 60.0 * 4.0 + 50.0 * 3.0, layout.width());
+    assert_eq!(Some(108.0), layout.lane(Key::P1_KEY1).map(|lane| lane.x));
+    assert_eq!(Some(LaneColor::BLUE), layout.lane(Key::P1_KEY2).map(|lane| lane.color));
+    assert_eq!(0.0, layout.x(800.0));
+
+    let layout = LaneLayout::new(PlayMode::BEAT_5K, ScratchSide::RIGHT, PlaySide::P2);
+    assert_eq!(Key::P1_SCRATCH, layout.lanes().last().unwrap().key);
+    assert_eq!(Some(0.0), layout.lane(Key::P1_KEY1).map(|lane| lane.x));
+    assert_eq!(None, layout.lane(Key::P1_KEY6));
+    assert_eq!(800.0 - layout.width(), layout.x(800.0));
+
+    let layout = LaneLayout::new(PlayMode::BEAT_14K, ScratchSide::LEFT, PlaySide::P1);
+    assert_eq!(16, layout.lanes().len());
+    assert!(layout.is_double());
+    assert_eq!(Key::P1_SCRATCH, layout.lanes()[0].key);
+    assert_eq!(Key::P2_SCRATCH, layout.lanes()[15].key);
+    let p2_key1 = layout.lane(Key::P2_KEY1).unwrap();
+    assert_eq!(layout.width() / 2.0 + DP_GAP / 2.0, p2_key1.x);
+    assert!(layout.width() <= 800.0);
+
+    let layout = LaneLayout::new(PlayMode::POPN_9K, ScratchSide::RIGHT, PlaySide::P1);
+    assert_eq!(9, layout.lanes().len());
+    assert_eq!(Some(LaneColor::RED), layout.lane(Key::P1_KEY5).map(|lane| lane.color));
+}
@@ -0,0 +1,367 @@
+use std::collections::HashMap;
+
+use bms_loader::{Key, PlayMode, Sound};
+
+type Time = f64;
+
+// H-RANDOM avoids putting two notes closer than this in one lane
+const H_RANDOM_THRESHOLD: Time = 0.1;
+// ALL-SCRATCH only moves a note when the scratch lane is free for this long
+const ALL_SCRATCH_THRESHOLD: Time = 0.2;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LaneOption {
+    OFF,
+    MIRROR,
+    RANDOM,
+    R_RANDOM,
+    S_RANDOM,
+    H_RANDOM,
+    ALL_SCRATCH,
+}
+
+impl LaneOption {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            LaneOption::OFF => "OFF",
+            LaneOption::MIRROR => "MIRROR",
+            LaneOption::RANDOM => "RANDOM",
+            LaneOption::R_RANDOM => "R-RANDOM",
+            LaneOption::S_RANDOM => "S-RANDOM",
+            LaneOption::H_RANDOM => "H-RANDOM",
+            LaneOption::ALL_SCRATCH => "ALL-SCRATCH",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<LaneOption> {
+        match name {
+            "OFF" => Some(LaneOption::OFF),
+            "MIRROR" => Some(LaneOption::MIRROR),
+            "RANDOM" => Some(LaneOption::RANDOM),
+            "R-RANDOM" => Some(LaneOption::R_RANDOM),
+            "S-RANDOM" => Some(LaneOption::S_RANDOM),
+            "H-RANDOM" => Some(LaneOption::H_RANDOM),
+            "ALL-SCRATCH" => Some(LaneOption::ALL_SCRATCH),
+            _ => None,
+        }
+    }
+}
+
+// xorshift64*, so that a seed gives the same arrangement regardless of the rand crate version
+pub struct LaneRng {
+    state: u64,
+}
+
+impl LaneRng {
+    pub fn new(seed: u64) -> LaneRng {
+        LaneRng { state: if seed == 0 { 0x9E3779B97F4A7C15 } else { seed } }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+
+    // uniform in 0..n
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+// the key lanes of each side with its scratch, in the lane order of the play mode.
+// 9K buttons are one side across the 1P and 2P channels and have no scratch
+fn sides(sounds: &Vec<Sound>) -> Vec<(Vec<Key>, Option<Key>)> {
+    let mode = PlayMode::of(sounds);
+    let keys: Vec<Key> = mode.lanes().into_iter().filter(|key| *key != Key::P1_SCRATCH && *key != Key::P2_SCRATCH).collect();
+    match mode {
+        PlayMode::POPN_9K => vec![(keys, None)],
+        PlayMode::BEAT_14K => {
+            let (p1, p2) = keys.split_at(keys.len() / 2);
+            vec![(p1.to_vec(), Some(Key::P1_SCRATCH)), (p2.to_vec(), Some(Key::P2_SCRATCH))]
+        }
+        _ => vec![(keys, Some(Key::P1_SCRATCH))],
+    }
+}
+
+// each side is arranged on its own, notes never cross to the other side
+pub fn apply(option: LaneOption, seed: u64, sounds: &mut Vec<Sound>) {
+    let mut rng = LaneRng::new(seed);
+    for (lanes, scratch) in sides(sounds) {
+        apply_side(option, &mut rng, sounds, &lanes, scratch);
+    }
+}
+
+fn apply_side(option: LaneOption, rng: &mut LaneRng, sounds: &mut Vec<Sound>, lanes: &Vec<Key>, scratch: Option<Key>) {
+    match option {
+        LaneOption::OFF => (),
+        LaneOption::MIRROR => {
+            let mut mirrored = lanes.clone();
+            mirrored.reverse();
+            permute(sounds, lanes, &mirrored);
+        }
+        LaneOption::RANDOM => {
+            let mut shuffled = lanes.clone();
+            for i in (1..shuffled.len()).rev() {
+                let j = rng.below(i + 1);
+                shuffled.swap(i, j);
+            }
+            permute(sounds, lanes, &shuffled);
+        }
+        LaneOption::R_RANDOM => {
+            let shift = 1 + rng.below(lanes.len() - 1);
+            let mut rotated: Vec<Key> = (0..lanes.len()).map(|i| lanes[(i + shift) % lanes.len()]).collect();
+            if rng.below(2) == 1 {
+                rotated.reverse();
+            }
+            permute(sounds, lanes, &rotated);
+        }
+        LaneOption::S_RANDOM => scatter(sounds, lanes, rng, 0.0),
+        LaneOption::H_RANDOM => scatter(sounds, lanes, rng, H_RANDOM_THRESHOLD),
+        LaneOption::ALL_SCRATCH => if let Some(scratch) = scratch {
+            all_scratch(sounds, lanes, scratch);
+        },
+    }
+}
+
+fn permute(sounds: &mut Vec<Sound>, from: &Vec<Key>, to: &Vec<Key>) {
+    let mapping: HashMap<Key, Key> = from.iter().zip(to.iter()).map(|(a, b)| (*a, *b)).collect();
+    for sound in sounds.iter_mut() {
+        if let Some(key) = mapping.get(&sound.key) {
+            sound.key = *key;
+        }
+    }
+}
+
+// per-note random lane: chords stay on distinct lanes and nothing lands inside a long note
+// unless every lane is held at once
+fn scatter(sounds: &mut Vec<Sound>, lanes: &Vec<Key>, rng: &mut LaneRng, threshold: Time) {
+    let mut last_timing: HashMap<Key, Time> = HashMap::new();
+    let mut busy_until: HashMap<Key, Time> = HashMap::new();
+
+    let mut order: Vec<usize> = (0..sounds.len()).filter(|i| lanes.contains(&sounds[*i].key)).collect();
+    order.sort_by(|a, b| sounds[*a].timing.partial_cmp(&sounds[*b].timing).unwrap());
+
+    for i in order {
+        let timing = sounds[i].timing;
+        let free: Vec<Key> = lanes.iter().map(|lane| *lane).filter(|lane| {
+            busy_until.get(lane).map_or(true, |until| *until < timing) &&
+                last_timing.get(lane).map_or(true, |last| *last < timing)
+        }).collect();
+        let spaced: Vec<Key> = free.iter().map(|lane| *lane).filter(|lane| {
+            last_timing.get(lane).map_or(true, |last| timing - *last >= threshold)
+        }).collect();
+        // more notes at once than there are lanes to take them: sharing a lane with a note of the
+        // same chord is better than landing inside a long note, and failing that the one ending first
+        let unheld: Vec<Key> = lanes.iter().map(|lane| *lane).filter(|lane| busy_until.get(lane).map_or(true, |until| *until < timing)).collect();
+        let candidates = if !spaced.is_empty() {
+            spaced
+        } else if !free.is_empty() {
+            free
+        } else if !unheld.is_empty() {
+            unheld
+        } else {
+            // every lane is held, so each has a long note end
+            lanes.iter().map(|lane| *lane)
+                .fold(None, |first: Option<Key>, lane| match first {
+                    Some(first) if busy_until[&first] <= busy_until[&lane] => Some(first),
+                    _ => Some(lane),
+                })
+                .into_iter().collect()
+        };
+        if candidates.is_empty() {
+            continue;
+        }
+
+        let lane = candidates[rng.below(candidates.len())];
+        sounds[i].key = lane;
+        last_timing.insert(lane, timing);
+        if let Some(end) = sounds[i].ln_end {
+            let until = busy_until.get(&lane).map_or(end, |until| f64::max(*until, end));
+            busy_until.insert(lane, until);
+        }
+    }
+}
+
+// moves key notes onto the scratch lane whenever it has room for them
+fn all_scratch(sounds: &mut Vec<Sound>, lanes: &Vec<Key>, scratch: Key) {
+    let original_scratch: Vec<(Time, Time)> = sounds.iter()
+        .filter(|sound| sound.key == scratch)
+        .map(|sound| (sound.timing, sound.ln_end.unwrap_or(sound.timing)))
+        .collect();
+
+    let mut order: Vec<usize> = (0..sounds.len()).filter(|i| lanes.contains(&sounds[*i].key)).collect();
+    order.sort_by(|a, b| sounds[*a].timing.partial_cmp(&sounds[*b].timing).unwrap());
+
+    let mut moved: Vec<(Time, Time)> = vec![];
+    for i in order {
+        let start = sounds[i].timing;
+        let end = sounds[i].ln_end.unwrap_or(start);
+        let collides = |&(s, e): &(Time, Time)| s < end + ALL_SCRATCH_THRESHOLD && start < e + ALL_SCRATCH_THRESHOLD;
+        if !original_scratch.iter().any(&collides) && !moved.iter().any(&collides) {
+            sounds[i].key = scratch;
+            moved.push((start, end));
+        }
+    }
+}
+
+#[test]
+fn lane_option_test() {
+    use bms_loader::SoundX;
+
+    let note = |key, timing, ln_end| Sound { key: key, timing: timing, wav_id: SoundX { id: 1 }, ln_end: ln_end };
+    let chart = vec![
+        note(Key::P1_KEY1, 1.0, Some(3.0)),
+        note(Key::P1_KEY2, 1.0, None),
+        note(Key::P1_KEY3, 1.0, None),
+        note(Key::P1_KEY4, 2.0, None),
+        note(Key::P1_KEY5, 2.0, None),
+        note(Key::P1_KEY6, 2.0, None),
+        note(Key::P1_KEY7, 2.0, None),
+        note(Key::P1_SCRATCH, 4.0, None),
+        note(Key::BACK_CHORUS, 1.0, None),
+    ];
+
+    let mut mirrored = chart.clone();
+    apply(LaneOption::MIRROR, 0, &mut mirrored);
+    assert_eq!(Key::P1_KEY7, mirrored[0].key);
+    assert_eq!(Key::P1_KEY1, mirrored[6].key);
+    assert_eq!(Key::P1_SCRATCH, mirrored[7].key);
+    assert_eq!(Key::BACK_CHORUS, mirrored[8].key);
+
+    for seed in 1..50 {
+        let mut a = chart.clone();
+        let mut b = chart.clone();
+        apply(LaneOption::S_RANDOM, seed, &mut a);
+        apply(LaneOption::S_RANDOM, seed, &mut b);
+        assert!(a.iter().zip(b.iter()).all(|(x, y)| x.key == y.key));
+
+        // the long note keeps its lane to itself and chords never share a lane
+        let ln_lane = a[0].key;
+        assert_eq!(Some(3.0), a[0].ln_end);
+        assert!(a[1..7].iter().all(|sound| sound.key != ln_lane));
+        assert!(a[1].key != a[2].key);
+        assert!(a[3].key != a[4].key && a[4].key != a[5].key && a[3].key != a[5].key);
+    }
+
+    // every lane is held when the last note comes, it goes to the long note that ends first
+    let keys = vec![Key::P1_KEY1, Key::P1_KEY2, Key::P1_KEY3, Key::P1_KEY4, Key::P1_KEY5, Key::P1_KEY6, Key::P1_KEY7];
+    let mut held: Vec<Sound> = keys.iter().enumerate().map(|(i, key)| note(*key, 1.0, Some(if i == 2 { 2.5 } else { 3.0 }))).collect();
+    held.push(note(Key::P1_KEY1, 2.0, None));
+    // and one note too many for a chord shares a lane rather than go inside the long note
+    let mut crowded: Vec<Sound> = keys.iter().map(|key| note(*key, 1.0, None)).collect();
+    crowded[0].ln_end = Some(3.0);
+    crowded.push(note(Key::P1_KEY2, 1.0, None));
+    for seed in 1..20 {
+        let mut a = held.clone();
+        apply(LaneOption::S_RANDOM, seed, &mut a);
+        assert_eq!(a[2].key, a[7].key);
+
+        let mut b = crowded.clone();
+        apply(LaneOption::S_RANDOM, seed, &mut b);
+        assert!(b[1..].iter().all(|sound| sound.key != b[0].key));
+    }
+}
+
+#[test]
+fn lane_option_random_test() {
+    use bms_loader::SoundX;
+
+    let note = |key, timing| Sound { key: key, timing: timing, wav_id: SoundX { id: 1 }, ln_end: None };
+    let keys = vec![Key::P1_KEY1, Key::P1_KEY2, Key::P1_KEY3, Key::P1_KEY4, Key::P1_KEY5, Key::P1_KEY6, Key::P1_KEY7];
+    // a note a lane, so where each lane went can be read back
+    let mut chart: Vec<Sound> = keys.iter().enumerate().map(|(i, key)| note(*key, i as f64)).collect();
+    chart.push(note(Key::P1_SCRATCH, 10.0));
+    let position = |key: Key| keys.iter().position(|lane| *lane == key).unwrap();
+
+    let mut moved = false;
+    for seed in 1..20 {
+        let mut a = chart.clone();
+        let mut b = chart.clone();
+        apply(LaneOption::RANDOM, seed, &mut a);
+        apply(LaneOption::RANDOM, seed, &mut b);
+        assert!(a.iter().zip(b.iter()).all(|(x, y)| x.key == y.key));
+        // every lane still gets exactly one note and the scratch stays
+        assert!(keys.iter().all(|key| a.iter().filter(|sound| sound.key == *key).count() == 1));
+        assert_eq!(Key::P1_SCRATCH, a[7].key);
+        moved |= a.iter().zip(chart.iter()).any(|(x, y)| x.key != y.key);
+
+        // the lanes are rotated, maybe mirrored, but never left as they were
+        let mut rotated = chart.clone();
+        apply(LaneOption::R_RANDOM, seed, &mut rotated);
+        let step = (position(rotated[1].key) + 7 - position(rotated[0].key)) % 7;
+        assert!(step == 1 || step == 6);
+        assert!((1..7).all(|i| (position(rotated[i].key) + 7 - position(rotated[i - 1].key)) % 7 == step));
+        assert!(!(step == 1 && rotated[0].key == Key::P1_KEY1));
+        assert_eq!(Key::P1_SCRATCH, rotated[7].key);
+    }
+    assert!(moved);
+
+    // a fast stream on one lane is spread so no lane gets two notes within the threshold
+    let stream: Vec<Sound> = (0..40).map(|i| note(Key::P1_KEY1, i as f64 * 0.05)).collect();
+    for seed in 1..20 {
+        let mut spread = stream.clone();
+        apply(LaneOption::H_RANDOM, seed, &mut spread);
+        for key in &keys {
+            let timings: Vec<Time> = spread.iter().filter(|sound| sound.key == *key).map(|sound| sound.timing).collect();
+            assert!(timings.windows(2).all(|pair| pair[1] - pair[0] >= H_RANDOM_THRESHOLD - 1e-9));
+        }
+    }
+
+    // notes go to the scratch when it is free long enough around them
+    let mut scratched = vec![
+        note(Key::P1_KEY1, 1.0),
+        note(Key::P1_KEY2, 1.1),
+        note(Key::P1_SCRATCH, 2.0),
+        note(Key::P1_KEY3, 2.1),
+        note(Key::P1_KEY4, 3.0),
+        note(Key::P1_KEY6, 3.0),
+    ];
+    apply(LaneOption::ALL_SCRATCH, 1, &mut scratched);
+    assert_eq!(vec![Key::P1_SCRATCH, Key::P1_KEY2, Key::P1_SCRATCH, Key::P1_KEY3, Key::P1_SCRATCH, Key::P1_KEY6],
+               scratched.iter().map(|sound| sound.key).collect::<Vec<_>>());
+}
+
+#[test]
+fn lane_option_sides_test() {
+    use bms_loader::SoundX;
+
+    let note = |key, timing| Sound { key: key, timing: timing, wav_id: SoundX { id: 1 }, ln_end: None };
+    let keys_of = |sounds: &Vec<Sound>| sounds.iter().map(|sound| sound.key).collect::<Vec<_>>();
+    let double = vec![
+        note(Key::P1_KEY1, 1.0),
+        note(Key::P1_KEY7, 2.0),
+        note(Key::P2_KEY1, 3.0),
+        note(Key::P2_KEY7, 4.0),
+        note(Key::P1_SCRATCH, 5.0),
+        note(Key::P2_SCRATCH, 6.0),
+    ];
+
+    let mut mirrored = double.clone();
+    apply(LaneOption::MIRROR, 0, &mut mirrored);
+    assert_eq!(vec![Key::P1_KEY7, Key::P1_KEY1, Key::P2_KEY7, Key::P2_KEY1, Key::P1_SCRATCH, Key::P2_SCRATCH], keys_of(&mirrored));
+
+    // each side keeps its notes
+    let p1 = vec![Key::P1_KEY1, Key::P1_KEY2, Key::P1_KEY3, Key::P1_KEY4, Key::P1_KEY5, Key::P1_KEY6, Key::P1_KEY7];
+    let p2 = vec![Key::P2_KEY1, Key::P2_KEY2, Key::P2_KEY3, Key::P2_KEY4, Key::P2_KEY5, Key::P2_KEY6, Key::P2_KEY7];
+    for seed in 1..20 {
+        let mut random = double.clone();
+        apply(LaneOption::RANDOM, seed, &mut random);
+        assert!(p1.contains(&random[0].key) && p1.contains(&random[1].key));
+        assert!(p2.contains(&random[2].key) && p2.contains(&random[3].key));
+    }
+
+    let mut scratched = double.clone();
+    apply(LaneOption::ALL_SCRATCH, 0, &mut scratched);
+    assert_eq!(vec![Key::P1_SCRATCH, Key::P1_SCRATCH, Key::P2_SCRATCH, Key::P2_SCRATCH, Key::P1_SCRATCH, Key::P2_SCRATCH], keys_of(&scratched));
+
+    // 9K mirrors all nine buttons and has no scratch to move notes to
+    let nine = vec![note(Key::P1_KEY1, 1.0), note(Key::P2_KEY5, 2.0), note(Key::P1_KEY3, 3.0)];
+    let mut mirrored = nine.clone();
+    apply(LaneOption::MIRROR, 0, &mut mirrored);
+    assert_eq!(vec![Key::P2_KEY5, Key::P1_KEY1, Key::P2_KEY3], keys_of(&mirrored));
+    let mut scratched = nine.clone();
+    apply(LaneOption::ALL_SCRATCH, 0, &mut scratched);
+    assert_eq!(keys_of(&nine), keys_of(&scratched));
+}
@@ -0,0 +1,351 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use game_session::Judge;
+use lane_layout::LaneColor;
+use skin::{ImageId, Rect, Skin};
+
+// LR2 skins are made for a 640x480 screen
+const LR2_RESOLUTION: (f64, f64) = (640.0, 480.0);
+// LR2 fonts are bitmaps of its own, text is drawn with the bundled font instead
+const FONT: &'static str = "resource/font/rounded-mplus-1p-regular.ttf";
+
+// #NUMBER values the player has a text element for
+const NUM_EX_SCORE: i32 = 101;
+const NUM_GAUGE: i32 = 107;
+const NUM_BPM: i32 = 160;
+
+// the option LR2 sets for the key mode of the skin, 160 + the #INFORMATION type
+const OPTION_KEY_MODE: i32 = 160;
+// options that hold while playing here: BGA on, autoplay off, groove gauge, loading finished
+const PLAY_OPTIONS: [i32; 4] = [41, 32, 42, 81];
+
+// #SRC_* fields: index, image, x, y, w, h, div_x, div_y, cycle, timer, then command specific ones
+#[derive(Debug, Clone, PartialEq)]
+struct Src {
+    image: usize,
+    rect: Rect,
+    whole: bool, // w or h was -1
+    div_x: usize,
+    div_y: usize,
+    fields: Vec<i32>,
+}
+
+impl Src {
+    fn parse(fields: &[&str]) -> Src {
+        let numbers: Vec<i32> = fields.iter().map(|field| field.parse().unwrap_or(0)).collect();
+        let number = |i: usize| numbers.get(i).map_or(0, |n| *n);
+        Src {
+            image: number(2) as usize,
+            rect: Rect::new(number(3) as f64, number(4) as f64, number(5) as f64, number(6) as f64),
+            whole: number(5) < 0 || number(6) < 0,
+            div_x: number(7).max(1) as usize,
+            div_y: number(8).max(1) as usize,
+            fields: numbers,
+        }
+    }
+
+    // animation frames and digits are cells of the source, left to right then down
+    fn frame(&self, n: usize) -> Option<Rect> {
+        if self.whole {
+            return None;
+        }
+        let w = self.rect.w / self.div_x as f64;
+        let h = self.rect.h / self.div_y as f64;
+        Some(Rect::new(self.rect.x + (n % self.div_x) as f64 * w, self.rect.y + (n / self.div_x) as f64 * h, w, h))
+    }
+
+    fn frames(&self) -> usize {
+        self.div_x * self.div_y
+    }
+}
+
+// #DST_* fields: index, time, x, y, w, h, acc, a, r, g, b, blend, filter, angle, center, loop, timer, op1, op2, op3
+fn dst_rect(fields: &[&str]) -> Rect {
+    let number = |i: usize| fields.get(i).and_then(|field| field.parse::<f64>().ok()).unwrap_or(0.0);
+    let (mut x, mut y, mut w, mut h) = (number(3), number(4), number(5), number(6));
+    // a negative size flips the image, only the placement is kept
+    if w < 0.0 {
+        x += w;
+        w = -w;
+    }
+    if h < 0.0 {
+        y += h;
+        h = -h;
+    }
+    Rect::new(x, y, w, h)
+}
+
+fn union(a: Rect, b: Rect) -> Rect {
+    let x = f64::min(a.x, b.x);
+    let y = f64::min(a.y, b.y);
+    Rect::new(x, y, f64::max(a.x + a.w, b.x + b.w) - x, f64::max(a.y + a.h, b.y + b.h) - y)
+}
+
+// what a skin is read into before it becomes a Skin, keyed by command without #SRC_ / #DST_ and index
+struct Lr2Skin {
+    images: Vec<PathBuf>,
+    srcs: HashMap<(String, i32), Src>,
+    dsts: HashMap<(String, i32), Rect>, // the first #DST line that applies, animations are not played
+}
+
+// which #IF blocks and #DST lines apply, a negative option means it is off
+struct Options {
+    on: HashSet<i32>,
+}
+
+impl Options {
+    fn holds(&self, options: &[i32]) -> bool {
+        options.iter().all(|option| match *option {
+            0 => true,
+            option if option < 0 => !self.on.contains(&-option),
+            option => self.on.contains(&option),
+        })
+    }
+}
+
+// LR2 paths start from its own folder, LR2files\Theme\..., and may pick a file with a wildcard
+fn resolve_path(path: &str, dir: &Path) -> PathBuf {
+    let path = path.replace('\\', "/");
+    let mut resolved = dir.join(&path);
+    if path.starts_with("LR2files/") {
+        if let Some(root) = dir.ancestors().find(|ancestor| ancestor.join("LR2files").is_dir()) {
+            resolved = root.join(&path);
+        }
+    }
+    match resolved.file_name().and_then(|name| name.to_str()).map(|name| name.to_string()) {
+        Some(ref name) if name.contains('*') => {
+            let mut parts = name.splitn(2, '*');
+            let (prefix, suffix) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+            let parent = resolved.parent().map(|parent| parent.to_path_buf()).unwrap_or(PathBuf::new());
+            let mut matches: Vec<PathBuf> = fs::read_dir(&parent).map(|entries| {
+                entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).filter(|candidate| {
+                    candidate.file_name().and_then(|name| name.to_str()).map_or(false, |name| name.starts_with(prefix) && name.ends_with(suffix))
+                }).collect()
+            }).unwrap_or(vec![]);
+            // the first in name order, LR2 would let the player choose
+            matches.sort();
+            matches.into_iter().next().unwrap_or(resolved)
+        }
+        _ => resolved,
+    }
+}
+
+fn parse(contents: &str, dir: &Path) -> Lr2Skin {
+    let mut skin = Lr2Skin { images: vec![], srcs: HashMap::new(), dsts: HashMap::new() };
+    let mut options = Options { on: PLAY_OPTIONS.iter().map(|option| *option).collect() };
+    // (this block applies, a block of this #IF chain already applied)
+    let mut blocks: Vec<(bool, bool)> = vec![];
+    let numbers = |fields: &[&str]| -> Vec<i32> { fields.iter().skip(1).filter_map(|field| field.parse().ok()).collect() };
+
+    for line in contents.lines() {
+        let fields: Vec<&str> = line.split(',').map(|field| field.trim()).collect();
+        let command = fields[0].to_uppercase();
+        if !command.starts_with('#') {
+            continue;
+        }
+        let active = blocks.iter().all(|&(applies, _)| applies);
+        match command.as_str() {
+            "#IF" => {
+                let applies = active && options.holds(&numbers(&fields));
+                blocks.push((applies, applies));
+                continue;
+            }
+            "#ELSEIF" | "#ELSE" => {
+                if let Some((_, taken)) = blocks.pop() {
+                    let outer = blocks.iter().all(|&(applies, _)| applies);
+                    let applies = outer && !taken && (command == "#ELSE" || options.holds(&numbers(&fields)));
+                    blocks.push((applies, taken || applies));
+                }
+                continue;
+            }
+            "#ENDIF" => {
+                blocks.pop();
+                continue;
+            }
+            _ if !active => continue,
+            _ => (),
+        }
+
+        match command.as_str() {
+            "#INFORMATION" => {
+                if let Some(key_mode) = fields.get(1).and_then(|field| field.parse::<i32>().ok()) {
+                    options.on.insert(OPTION_KEY_MODE + key_mode);
+                }
+            }
+            // #CUSTOMOPTION,name,first option,labels..., LR2 starts with the first label chosen
+            "#CUSTOMOPTION" => {
+                if let Some(option) = fields.get(2).and_then(|field| field.parse::<i32>().ok()) {
+                    options.on.insert(option);
+                }
+            }
+            "#IMAGE" => {
+                skin.images.push(resolve_path(fields.get(1).unwrap_or(&""), dir));
+            }
+            _ if command.starts_with("#SRC_") => {
+                let index = fields.get(1).and_then(|field| field.parse().ok()).unwrap_or(0);
+                skin.srcs.insert((command["#SRC_".len()..].to_string(), index), Src::parse(&fields));
+            }
+            _ if command.starts_with("#DST_") => {
+                let index = fields.get(1).and_then(|field| field.parse().ok()).unwrap_or(0);
+                let conditions: Vec<i32> = (18..21).filter_map(|i| fields.get(i).and_then(|field| field.parse().ok())).collect();
+                if options.holds(&conditions) {
+                    skin.dsts.entry((command["#DST_".len()..].to_string(), index)).or_insert(dst_rect(&fields));
+                }
+            }
+            _ => (),
+        }
+    }
+    skin
+}
+
+impl Lr2Skin {
+    fn src(&self, command: &str, index: i32) -> Option<&Src> {
+        self.srcs.get(&(command.to_string(), index))
+    }
+
+    fn dst(&self, command: &str, index: i32) -> Option<Rect> {
+        self.dsts.get(&(command.to_string(), index)).map(|rect| *rect)
+    }
+
+    // a frame of a #SRC as a skin image, None for LR2's own images like the stage file
+    fn image(&self, skin: &mut Skin, src: &Src, frame: usize) -> Option<ImageId> {
+        self.images.get(src.image).map(|path| skin.add_image(path.clone(), src.frame(frame)))
+    }
+
+    fn into_skin(self) -> Skin {
+        let mut skin = Skin::new(LR2_RESOLUTION, PathBuf::from(FONT));
+
+        // scratch, then the white and blue keys, 10 and up for the 2P side
+        for &(index, color) in &[(0, LaneColor::RED), (1, LaneColor::WHITE), (2, LaneColor::BLUE)] {
+            let src = self.src("NOTE", index).or_else(|| self.src("NOTE", index + 10)).cloned();
+            if let Some(id) = src.and_then(|src| self.image(&mut skin, &src, 0)) {
+                skin.notes.insert(color, id);
+            }
+        }
+        if let Some(id) = self.src("LINE", 0).cloned().and_then(|src| self.image(&mut skin, &src, 0)) {
+            skin.bar = Some(id);
+        }
+        // the lanes run from the top of the #DST_NOTE rects to the judge line at their bottom
+        let lanes = (0..8).chain(10..18).filter_map(|index| self.dst("NOTE", index)).fold(None, |lanes: Option<Rect>, rect| {
+            Some(lanes.map_or(rect, |lanes| union(lanes, rect)))
+        });
+        skin.set_element("lanes", lanes);
+
+        // 4 is the POOR of a note let through, 5 the empty POOR of a press with no note
+        let judges = [Judge::PGREAT, Judge::GREAT, Judge::GOOD, Judge::BAD, Judge::MISSED, Judge::POOR];
+        for (index, judge) in judges.iter().enumerate() {
+            if let Some(id) = self.src("NOWJUDGE_1P", index as i32).cloned().and_then(|src| self.image(&mut skin, &src, 0)) {
+                skin.judges.insert(*judge, id);
+            }
+        }
+        if let (Some(missed), None) = (skin.judges.get(&Judge::MISSED).cloned(), skin.judges.get(&Judge::POOR).cloned()) {
+            skin.judges.insert(Judge::POOR, missed);
+        }
+        skin.set_element("judge", self.dst("NOWJUDGE_1P", 0));
+        // the combo goes next to the judge, so only its digits are used
+        if let Some(src) = self.src("NOWCOMBO_1P", 0).cloned() {
+            if src.frames() >= 10 {
+                let digits = (0..10).filter_map(|digit| self.image(&mut skin, &src, digit)).collect();
+                skin.digits = digits;
+            }
+        }
+
+        skin.set_element("bga", self.dst("BGA", 0));
+        skin.set_element("gauge", self.dst("GROOVEGAUGE", 0));
+        // #SRC_NUMBER has the value it shows at field 11, the player draws it as text
+        let mut numbers: Vec<(i32, &Src)> = self.srcs.iter().filter(|&(key, _)| key.0 == "NUMBER").map(|(key, src)| (key.1, src)).collect();
+        numbers.sort_by_key(|&(index, _)| index);
+        for (index, src) in numbers {
+            let name = match src.fields.get(11).map(|num| *num) {
+                Some(NUM_EX_SCORE) => "score",
+                Some(NUM_BPM) => "bpm",
+                Some(NUM_GAUGE) if self.dst("GROOVEGAUGE", 0).is_none() => "gauge",
+                _ => continue,
+            };
+            if let Some(rect) = self.dst("NUMBER", index) {
+                skin.set_element(name, Some(rect));
+            }
+        }
+
+        // LR2 has no place for these, so they go in the top left corner
+        skin.set_element("latency", Some(Rect::new(8.0, 4.0, 320.0, 12.0)));
+        skin.set_element("practice", Some(Rect::new(8.0, 20.0, 320.0, 12.0)));
+        skin
+    }
+}
+
+// LR2 skins are Shift_JIS, so anything but the ASCII commands and paths may come out garbled
+pub fn load(path: &Path) -> Skin {
+    let mut bytes = vec![];
+    if let Err(e) = File::open(path).and_then(|mut file| file.read_to_end(&mut bytes)) {
+        println!("failed to read {}: {}", path.to_str().unwrap_or(""), e);
+    }
+    from_csv(&String::from_utf8_lossy(&bytes), path.parent().unwrap_or(Path::new(".")))
+}
+
+pub fn from_csv(contents: &str, dir: &Path) -> Skin {
+    parse(contents, dir).into_skin()
+}
+
+#[test]
+fn lr2_skin_test() {
+    let skin = from_csv("
+        // comments and unknown commands are skipped
+        #INFORMATION,0,test skin,someone
+        #CUSTOMOPTION,JUDGE,900,A,B
+        #IMAGE,parts\\notes.png
+        #IMAGE,parts\\judge.png
+        #IF,901
+        #IMAGE,parts\\judge_b.png
+        #ELSE
+        #IMAGE,parts\\number.png
+        #ENDIF
+        #SRC_NOTE,0,0,0,0,60,10,1,1,0,0
+        #SRC_NOTE,1,0,60,0,40,10,1,1,0,0
+        #SRC_NOTE,2,0,100,0,30,10,1,1,0,0
+        #DST_NOTE,0,0,20,0,60,400,0,255,255,255,255,0,0,0,0,0,0,0,0,0
+        #DST_NOTE,1,0,80,0,40,400,0,255,255,255,255,0,0,0,0,0,0,0,0,0
+        #DST_NOTE,7,0,300,0,40,400,0,255,255,255,255,0,0,0,0,0,0,0,0,0
+        #SRC_NOWJUDGE_1P,0,1,0,0,200,120,1,4,100,0
+        #SRC_NOWJUDGE_1P,3,1,0,120,200,30,1,1,0,0
+        #SRC_NOWJUDGE_1P,4,1,0,150,200,30,1,1,0,0
+        #DST_NOWJUDGE_1P,0,0,60,250,200,30,0,255,255,255,255,0,0,0,0,0,46,0,0,0
+        #DST_NOWJUDGE_1P,0,100,60,240,200,30,0,255,255,255,255,0,0,0,0,0,46,0,0,0
+        #SRC_NOWCOMBO_1P,0,2,0,0,240,24,10,1,0,0,0,0
+        #SRC_NUMBER,0,2,0,0,240,24,10,1,0,0,101,0,4
+        #DST_NUMBER,0,0,400,420,100,12,0,255,255,255,255,0,0,0,0,0,0,-41,0,0
+        #DST_NUMBER,0,0,400,440,100,12,0,255,255,255,255,0,0,0,0,0,0,0,0,0
+        #SRC_NUMBER,1,2,0,0,240,24,10,1,0,0,160,0,3
+        #DST_NUMBER,1,0,400,460,100,12,0,255,255,255,255,0,0,0,0,0,0,161,0,0
+        #DST_BGA,0,0,360,296,256,-256,0,255,255,255,255,0,0,0,0,0,0,0,0,0
+    ", Path::new("skins/lr2"));
+
+    assert_eq!((640.0, 480.0), skin.resolution);
+    assert_eq!(vec![PathBuf::from("skins/lr2/parts/notes.png"), PathBuf::from("skins/lr2/parts/judge.png"),
+                    PathBuf::from("skins/lr2/parts/number.png")], skin.files);
+    let blue = &skin.images[skin.notes[&LaneColor::BLUE]];
+    assert_eq!((0, Some(Rect::new(100.0, 0.0, 30.0, 10.0))), (blue.file, blue.src));
+
+    use lane_layout::PlaySide;
+    let element = |name: &str| skin.element(name, PlaySide::P1, false);
+    assert_eq!(Some(Rect::new(20.0, 0.0, 320.0, 400.0)), element("lanes"));
+    // the first frame of an animated judge, the first #DST line places it
+    assert_eq!(Some(Rect::new(0.0, 0.0, 200.0, 30.0)), skin.images[skin.judges[&Judge::PGREAT]].src);
+    assert!(skin.judges.contains_key(&Judge::BAD));
+    assert!(!skin.judges.contains_key(&Judge::GREAT));
+    // the skin has no empty POOR, so it shows the missed note's
+    assert_eq!(Some(Rect::new(0.0, 150.0, 200.0, 30.0)), skin.images[skin.judges[&Judge::MISSED]].src);
+    assert_eq!(skin.judges[&Judge::MISSED], skin.judges[&Judge::POOR]);
+    assert_eq!(Some(Rect::new(60.0, 250.0, 200.0, 30.0)), element("judge"));
+
+    assert_eq!(10, skin.digits.len());
+    assert_eq!(Some(Rect::new(24.0 * 9.0, 0.0, 24.0, 24.0)), skin.images[skin.digits[9]].src);
+    // the score's first #DST needs BGA off, bpm only shows in 5K skins
+    assert_eq!(Some(Rect::new(400.0, 440.0, 100.0, 12.0)), element("score"));
+    assert_eq!(None, element("bpm"));
+    assert_eq!(Some(Rect::new(360.0, 40.0, 256.0, 256.0)), element("bga"));
+    assert_eq!(None, element("gauge"));
+}
@@ -11,6 +11,7 @@ extern crate time;
 extern crate image;
 extern crate ffmpeg;
 extern crate walkdir;
+extern crate md5;
 
 use piston::event_loop::*;
 use piston::input::*;
@@ -29,19 +30,253 @@ use std::env;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 mod bms_parser;
 mod bms_player;
 mod bms_loader;
+mod bms_assets;
+mod game_session;
+mod score_db;
+mod user_data;
+mod replay;
+mod lane_option;
+mod hispeed;
+mod config;
+mod lane_cover;
+mod key_config;
+mod input;
+mod controller;
+mod clock;
+mod sample_info;
+mod partial_sound;
+mod practice;
+mod lane_layout;
+mod skin;
+mod lr2_skin;
+mod frame;
+mod play_view;
+mod software_render;
+mod chart_preview;
+mod chart_analysis;
+mod chart_stats;
+mod bms_lint;
 
 use bms_parser::BmsParser;
-use bms_player::TextureLabel;
+use bms_player::Retry;
+use game_session::{Judge, PlayResult};
+use score_db::{Lamp, ScoreDb};
+use replay::Replay;
+use lane_option::LaneOption;
+use hispeed::{HiSpeed, HiSpeedMode, BaseBpm};
+use config::Config;
+use lane_cover::LaneCover;
+use key_config::{Control, KeyBindings, KeyConfig};
+use input::{InputSource, RawInput};
+use controller::Controllers;
+use clock::{AudioClock, Clock, ManualClock, WallClock};
+use practice::Practice;
+use lane_layout::{LaneLayout, PlaySide, ScratchSide};
+use skin::{Rect, Skin};
+use frame::{DrawCommand, Frame};
+use chart_analysis::ChartAnalysis;
+use chart_stats::ChartStats;
+use software_render::SoftwareRenderer;
 
 use walkdir::{DirEntry, WalkDir, WalkDirIterator};
 
+const DEFAULT_GREEN_NUMBER: f64 = 500.0;
+const SETTINGS_FILE: &'static str = "settings.cfg";
+
+pub struct PlayOptions {
+    replay: Option<Replay>,
+    autoplay: HashSet<bms_loader::Key>,
+    lane_option: LaneOption,
+    seed: Option<u64>,
+    hispeed: HiSpeed,
+    lane_cover: LaneCover,
+    scratch_side: ScratchSide,
+    play_side: PlaySide,
+    latency_report: bool,
+    start: Option<StartAt>,
+    practice: Option<Practice>,
+    skin: Option<PathBuf>, // the one in settings.cfg otherwise
+    dump_frames: Option<Vec<f64>>, // play times to render to PNG instead of playing
+    dump_dir: PathBuf,
+    preview: Option<PathBuf>, // write the whole chart to this PNG instead of playing
+}
+
+// where play starts, for practicing a part of the chart
+#[derive(Copy, Clone)]
+pub enum StartAt {
+    Time(f64),
+    Measure(usize),
+}
+
+impl PlayOptions {
+    fn new() -> PlayOptions {
+        let settings = Config::open(SETTINGS_FILE);
+        PlayOptions {
+            replay: None,
+            autoplay: HashSet::new(),
+            lane_option: LaneOption::OFF,
+            seed: None,
+            hispeed: HiSpeed::normal(1.0),
+            lane_cover: LaneCover::load(&settings),
+            scratch_side: settings.get("layout.scratch").and_then(ScratchSide::from_name).unwrap_or(ScratchSide::LEFT),
+            play_side: settings.get("layout.side").and_then(PlaySide::from_name).unwrap_or(PlaySide::P1),
+            latency_report: false,
+            start: None,
+            practice: None,
+            skin: None,
+            dump_frames: None,
+            dump_dir: PathBuf::from("."),
+            preview: None,
+        }
+    }
+}
+
+// all, scratch (both sides in DP), or a comma separated lane list like P1_SCRATCH,P1_KEY1
+fn parse_autoplay(arg: &str) -> Result<HashSet<bms_loader::Key>, String> {
+    match arg {
+        "all" => Ok(bms_loader::Key::visible_keys()),
+        "scratch" => Ok(vec![bms_loader::Key::P1_SCRATCH, bms_loader::Key::P2_SCRATCH].into_iter().collect()),
+        _ => arg.split(',').map(|name| bms_loader::Key::from_name(name).ok_or(format!("unknown lane {} for --autoplay", name))).collect(),
+    }
+}
+
+// rust-bms [chart] [--replay file] [--autoplay all|scratch|lanes] [--option MIRROR|RANDOM|...] [--seed n]
+//          [--hispeed NORMAL|FLOATING|CONSTANT] [--green ms] [--base-bpm MAIN|MAX|MIN]
+//          [--sudden n] [--hidden n] [--lift n] [--latency] [--start seconds] [--start-measure n]
+//          [--practice start-end] [--rate r] [--scratch LEFT|RIGHT] [--side 1P|2P] [--skin file]
+//          [--dump-frames seconds,seconds,...] [--dump-dir dir] [--preview file.png]
+// rust-bms info [--json] chart...
+// rust-bms lint chart...
+fn parse_args() -> (Option<String>, PlayOptions) {
+    let mut path = None;
+    let mut options = PlayOptions::new();
+    let mut green = None;
+    let mut practice_range = None;
+    let mut rate = 1.0;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--replay" => {
+                let replay_path = args.next().expect("--replay needs a replay file");
+                options.replay = Some(Replay::load(&replay_path).expect("failed to load replay"));
+            }
+            "--autoplay" => {
+                match parse_autoplay(&args.next().expect("--autoplay needs all, scratch or a lane list")) {
+                    Ok(lanes) => options.autoplay = lanes,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        eprintln!("usage: rust-bms chart --autoplay all|scratch|P1_SCRATCH,P1_KEY1,...");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--option" => {
+                let name = args.next().expect("--option needs a lane option");
+                options.lane_option = LaneOption::from_name(&name).expect("unknown lane option");
+            }
+            "--seed" => {
+                options.seed = Some(args.next().and_then(|seed| seed.parse().ok()).expect("--seed needs a number"));
+            }
+            "--hispeed" => {
+                let name = args.next().expect("--hispeed needs a mode");
+                options.hispeed.mode = HiSpeedMode::from_name(&name).expect("unknown hi-speed mode");
+            }
+            "--green" => {
+                green = Some(args.next().and_then(|value| value.parse().ok()).expect("--green needs milliseconds"));
+            }
+            "--sudden" | "--hidden" | "--lift" => {
+                let value: f64 = args.next().and_then(|value| value.parse().ok()).expect("lane covers are given in 1/1000 of the lane");
+                match arg.as_str() {
+                    "--sudden" => options.lane_cover.sudden = value,
+                    "--hidden" => options.lane_cover.hidden = value,
+                    _ => options.lane_cover.lift = value,
+                }
+                options.lane_cover = options.lane_cover.clamped();
+            }
+            "--latency" => {
+                options.latency_report = true;
+            }
+            "--start" => {
+                options.start = Some(StartAt::Time(args.next().and_then(|value| value.parse().ok()).expect("--start needs seconds")));
+            }
+            "--start-measure" => {
+                options.start = Some(StartAt::Measure(args.next().and_then(|value| value.parse().ok()).expect("--start-measure needs a measure number")));
+            }
+            "--practice" => {
+                practice_range = Some(args.next().expect("--practice needs a measure range like 8-12"));
+            }
+            "--rate" => {
+                rate = args.next().and_then(|value| value.parse().ok()).expect("--rate needs a playback rate like 0.75");
+            }
+            "--scratch" => {
+                let name = args.next().expect("--scratch needs LEFT or RIGHT");
+                options.scratch_side = ScratchSide::from_name(&name).expect("unknown scratch side");
+            }
+            "--side" => {
+                let name = args.next().expect("--side needs 1P or 2P");
+                options.play_side = PlaySide::from_name(&name).expect("unknown play side");
+            }
+            "--skin" => {
+                options.skin = Some(PathBuf::from(args.next().expect("--skin needs a skin file")));
+            }
+            "--dump-frames" => {
+                let times = args.next().expect("--dump-frames needs play times like 10,20.5");
+                options.dump_frames = Some(times.split(',').map(|pt| pt.parse().expect("--dump-frames needs play times in seconds")).collect());
+            }
+            "--dump-dir" => {
+                options.dump_dir = PathBuf::from(args.next().expect("--dump-dir needs a directory"));
+            }
+            "--preview" => {
+                options.preview = Some(PathBuf::from(args.next().expect("--preview needs a PNG file to write")));
+            }
+            "--base-bpm" => {
+                let name = args.next().expect("--base-bpm needs MAIN, MAX or MIN");
+                options.hispeed.base = BaseBpm::from_name(&name).expect("unknown base bpm");
+            }
+            _ => {
+                path = Some(arg);
+            }
+        }
+    }
+
+    // a green number alone means floating hi-speed
+    if let Some(green) = green {
+        if options.hispeed.mode == HiSpeedMode::NORMAL {
+            options.hispeed.mode = HiSpeedMode::FLOATING;
+        }
+        options.hispeed.value = green;
+    } else if options.hispeed.mode != HiSpeedMode::NORMAL {
+        options.hispeed.value = DEFAULT_GREEN_NUMBER;
+    }
+    if let Some(range) = practice_range {
+        options.practice = Some(Practice::parse(&range, rate).expect("--practice needs a measure range like 8-12"));
+    }
+    (path, options)
+}
+
 fn main() {
-    println!("Start main() at {}", time::precise_time_s());
+    if env::args().nth(1).map_or(false, |arg| arg == "info") {
+        info(env::args().skip(2).collect());
+        return;
+    }
+    if env::args().nth(1).map_or(false, |arg| arg == "lint") {
+        lint(env::args().skip(2).collect());
+        return;
+    }
+    let (path, options) = parse_args();
+    if let (&Some(ref path), &Some(ref times)) = (&path, &options.dump_frames) {
+        dump_frames(path, &options, times);
+        return;
+    }
+    if let (&Some(ref path), &Some(ref preview)) = (&path, &options.preview) {
+        write_preview(path, &options, preview);
+        return;
+    }
     let opengl = OpenGL::V3_2;
     music::start::<bms_loader::MusicX, bms_loader::SoundX, _>(|| {
         mixer::allocate_channels(256);
@@ -54,15 +289,16 @@ fn main() {
             .build()
             .unwrap();
         let mut gl = GlGraphics::new(opengl);
+        let mut score_db = ScoreDb::open_default();
 
-        match env::args().nth(1) {
-            Some(path) => play_bms(&mut window, &mut gl, path),
-            None => music_selection(&mut window, &mut gl)
+        match path {
+            Some(path) => play_bms(&mut window, &mut gl, &mut score_db, path, &options),
+            None => music_selection(&mut window, &mut gl, &mut score_db)
         }
     });
 }
 
-fn music_selection(mut window: &mut Window, mut gl: &mut GlGraphics) {
+fn music_selection(mut window: &mut Window, mut gl: &mut GlGraphics, score_db: &mut ScoreDb) {
     show_loading(&mut window, &mut gl);
 
     let ref mut glyphs = GlyphCache::new("resource/font/rounded-mplus-1p-regular.ttf")
@@ -82,37 +318,57 @@ fn music_selection(mut window: &mut Window, mut gl: &mut GlGraphics) {
 
     assert!(bms_paths.len() > 0, "place bms files under following directory: {}", bms_base.to_str().unwrap());
 
-    let path_title: Vec<(PathBuf, String)> = bms_paths.into_iter().map(|path_buf| {
+    let path_title: Vec<(PathBuf, String, String)> = bms_paths.into_iter().map(|path_buf| {
         let bms_script = bms_parser::BmsFileParser{path: path_buf.to_str().unwrap().to_string()}.parse();
         println!("{}", bms_script.headers()["TITLE"]);
-        (path_buf, bms_script.headers()["TITLE"].clone())
+        let hash = score_db::chart_hash(&path_buf).unwrap_or(String::new());
+        (path_buf, bms_script.headers()["TITLE"].clone(), hash)
     }).collect();
 
     const BG_COLOR: [f32; 4] = [0.3, 0.3, 0.3, 1.0];
     const GREEN: [f32; 4] = [0.0, 1.0, 0.0, 1.0];
     const FONT_SIZE: u32 = 50;
+    const GRAPH_WIDTH: f64 = 300.0;
+    const GRAPH_HEIGHT: f64 = 80.0;
 
     let mut cur = 0;
+    // loaded as the cursor reaches them, the whole library would take too long
+    let mut analyses: HashMap<usize, Option<ChartAnalysis>> = HashMap::new();
     let mut events = Events::new(EventSettings::new());
     while let Some(e) = events.next(window) {
         if let Some(r) = e.render_args() {
+            use bms_loader::BmsLoader;
+            let path = path_title[cur].0.to_str().unwrap();
+            let analysis = analyses.entry(cur).or_insert_with(|| match bms_loader::BmsFileLoader::chart_only(path).load() {
+                Ok((bms, _)) => Some(ChartAnalysis::new(&bms)),
+                Err(e) => {
+                    println!("failed to load {}: {}", path, e);
+                    None
+                }
+            });
+            let mut graph = Frame::new(r.width as f64, r.height as f64);
+            if let Some(ref analysis) = *analysis {
+                analysis.graph(&mut graph, Rect::new(r.width as f64 - GRAPH_WIDTH - 10.0, r.height as f64 - GRAPH_HEIGHT - 10.0, GRAPH_WIDTH, GRAPH_HEIGHT), &[]);
+            }
+
+            let score_db = &*score_db;
             gl.draw(r.viewport(), |c, gl| {
                 clear(BG_COLOR, gl);
                 let w = r.width as f64;
                 let h = r.height as f64;
 
-                let (ref path, ref title) = path_title[cur];
                 rectangle(GREEN, rectangle::rectangle_by_corners(0.0, 0.0, w, FONT_SIZE as f64), c.transform.trans(0.0, h / 2.0 - FONT_SIZE as f64 * 0.9), gl);
-                Text::new(FONT_SIZE).draw(&title, glyphs, &DrawState::new_alpha(), c.transform.trans(0.0, h / 2.0), gl);
+                draw_entry(&path_title[cur], score_db, glyphs, w, h / 2.0, FONT_SIZE, &c, gl);
 
                 let display_num = 5;
                 for i in 1..display_num + 1 {
-                    let (ref path, ref title) = path_title[(cur + i) % path_title.len()];
-                    Text::new(FONT_SIZE).draw(&title, glyphs, &DrawState::new_alpha(), c.transform.trans(0.0, h / 2.0 - i as f64 * FONT_SIZE as f64), gl);
+                    let entry = &path_title[(cur + i) % path_title.len()];
+                    draw_entry(entry, score_db, glyphs, w, h / 2.0 - i as f64 * FONT_SIZE as f64, FONT_SIZE, &c, gl);
 
-                    let (ref path, ref title) = path_title[(cur + path_title.len() * 100 - i) % path_title.len()];
-                    Text::new(FONT_SIZE).draw(&title, glyphs, &DrawState::new_alpha(), c.transform.trans(0.0, h / 2.0 + i as f64 * FONT_SIZE as f64), gl);
+                    let entry = &path_title[(cur + path_title.len() * 100 - i) % path_title.len()];
+                    draw_entry(entry, score_db, glyphs, w, h / 2.0 + i as f64 * FONT_SIZE as f64, FONT_SIZE, &c, gl);
                 }
+                draw_fills(&graph, &c, gl);
 
             });
         }
@@ -127,7 +383,10 @@ fn music_selection(mut window: &mut Window, mut gl: &mut GlGraphics) {
                     cur %= path_title.len();
                 }
                 Key::Return => {
-                    play_bms(&mut window, &mut gl, path_title[cur].0.to_str().unwrap().to_string());
+                    play_bms(&mut window, &mut gl, score_db, path_title[cur].0.to_str().unwrap().to_string(), &PlayOptions::new());
+                }
+                Key::F1 => {
+                    key_config_screen(&mut window, &mut gl, glyphs);
                 }
                 Key::Escape => {
                     break;
@@ -140,6 +399,160 @@ fn music_selection(mut window: &mut Window, mut gl: &mut GlGraphics) {
     }
 }
 
+// Left/Right: play mode, Up/Down: lane, Return: rebind, Tab: add a key, Delete: unbind, Escape: save and leave
+// while waiting for a key, controller buttons and turntable spins are captured too
+fn key_config_screen(window: &mut Window, gl: &mut GlGraphics, glyphs: &mut GlyphCache) {
+    const BG_COLOR: [f32; 4] = [0.3, 0.3, 0.3, 1.0];
+    const GREEN: [f32; 4] = [0.0, 1.0, 0.0, 1.0];
+    const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+    const FONT_SIZE: u32 = 24;
+
+    let mut key_config = KeyConfig::load();
+    let modes = bms_loader::PlayMode::all();
+    let mut mode_index = 1;
+    let mut cur = 0;
+    // Some(true) waits for a key to add to the lane, Some(false) for one to replace its keys
+    let mut capturing: Option<bool> = None;
+    let mut controllers = Controllers::open();
+    let mut axis_positions = HashMap::new();
+
+    let mut events = Events::new(EventSettings::new());
+    while let Some(e) = events.next(window) {
+        let mode = modes[mode_index];
+        let lanes = mode.lanes();
+
+        if let Some(r) = e.render_args() {
+            let key_config = &key_config;
+            gl.draw(r.viewport(), |c, gl| {
+                clear(BG_COLOR, gl);
+                let w = r.width as f64;
+                let line_height = FONT_SIZE as f64 * 1.4;
+
+                let title = format!("KEY CONFIG  < {} >", mode.name());
+                Text::new_color(WHITE, FONT_SIZE).draw(&title, glyphs, &DrawState::new_alpha(), c.transform.trans(20.0, line_height), gl);
+
+                for (i, lane) in lanes.iter().enumerate() {
+                    let y = line_height * (i as f64 + 3.0);
+                    if i == cur {
+                        rectangle(GREEN, rectangle::rectangle_by_corners(0.0, 0.0, w, line_height), c.transform.trans(0.0, y - FONT_SIZE as f64), gl);
+                    }
+                    let keys = if i == cur && capturing.is_some() {
+                        "press a key...".to_string()
+                    } else {
+                        key_config.lane_keys(mode, *lane).iter().map(|key| key.name()).collect::<Vec<String>>().join(", ")
+                    };
+                    Text::new_color(WHITE, FONT_SIZE).draw(&format!("{:?}", lane), glyphs, &DrawState::new_alpha(), c.transform.trans(20.0, y), gl);
+                    Text::new_color(WHITE, FONT_SIZE).draw(&keys, glyphs, &DrawState::new_alpha(), c.transform.trans(w / 2.0, y), gl);
+                }
+            });
+        }
+
+        let mut captured = None;
+        for input in controllers.poll() {
+            match input {
+                RawInput::Button(joystick, button, true) => captured = Some(InputSource::Button(joystick, button)),
+                RawInput::Axis(joystick, axis, position) => {
+                    let previous = axis_positions.insert((joystick, axis), position).unwrap_or(position);
+                    if position - previous > 0.05 {
+                        captured = Some(InputSource::AxisPlus(joystick, axis));
+                    } else if previous - position > 0.05 {
+                        captured = Some(InputSource::AxisMinus(joystick, axis));
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        if let Some(Button::Keyboard(key)) = e.press_args() {
+            if capturing.is_some() {
+                match key {
+                    Key::Escape => capturing = None,
+                    _ => captured = Some(InputSource::Keyboard(key)),
+                }
+            } else {
+                match key {
+                    Key::Left => {
+                        mode_index = (mode_index + modes.len() - 1) % modes.len();
+                        cur = 0;
+                    }
+                    Key::Right => {
+                        mode_index = (mode_index + 1) % modes.len();
+                        cur = 0;
+                    }
+                    Key::Up => {
+                        cur = (cur + lanes.len() - 1) % lanes.len();
+                    }
+                    Key::Down => {
+                        cur = (cur + 1) % lanes.len();
+                    }
+                    Key::Return => {
+                        capturing = Some(false);
+                    }
+                    Key::Tab => {
+                        capturing = Some(true);
+                    }
+                    Key::Delete => {
+                        key_config.set_lane_keys(mode, lanes[cur], &vec![]);
+                    }
+                    Key::Escape => {
+                        break;
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        if let (Some(add), Some(source)) = (capturing, captured) {
+            capturing = None;
+            let lane = lanes[cur];
+            let mut keys = if add { key_config.lane_keys(mode, lane) } else { vec![] };
+            if !keys.contains(&source) {
+                keys.push(source);
+            }
+            key_config.set_lane_keys(mode, lane, &keys);
+        }
+    }
+
+    if let Err(e) = key_config.save() {
+        println!("failed to save key config: {}", e);
+    }
+}
+
+fn lamp_color(lamp: Lamp) -> [f32; 4] {
+    match lamp {
+        Lamp::NO_PLAY => [0.2, 0.2, 0.2, 1.0],
+        Lamp::FAILED => [0.6, 0.0, 0.0, 1.0],
+        Lamp::CLEAR => [0.0, 0.6, 1.0, 1.0],
+        Lamp::FULL_COMBO => [1.0, 0.6, 0.0, 1.0],
+        Lamp::PERFECT => [1.0, 1.0, 0.4, 1.0],
+        Lamp::MAX => [1.0, 1.0, 1.0, 1.0],
+    }
+}
+
+fn draw_entry(entry: &(PathBuf, String, String), score_db: &ScoreDb, glyphs: &mut GlyphCache, w: f64, y: f64, font_size: u32, c: &Context, gl: &mut GlGraphics) {
+    const LAMP_WIDTH: f64 = 16.0;
+    let (_, ref title, ref hash) = *entry;
+    let record = score_db.get(hash);
+
+    let lamp = record.map_or(Lamp::NO_PLAY, |r| r.lamp);
+    rectangle(lamp_color(lamp), rectangle::rectangle_by_corners(0.0, 0.0, LAMP_WIDTH, font_size as f64 * 0.8), c.transform.trans(0.0, y - font_size as f64 * 0.8), gl);
+    Text::new(font_size).draw(title, glyphs, &DrawState::new_alpha(), c.transform.trans(LAMP_WIDTH * 1.5, y), gl);
+
+    if let Some(record) = record {
+        let score = format!("EX {}  BP {}", record.ex_score, record.min_bp.map_or("-".to_string(), |bp| bp.to_string()));
+        Text::new(font_size / 2).draw(&score, glyphs, &DrawState::new_alpha(), c.transform.trans(w - font_size as f64 * 4.0, y), gl);
+    }
+}
+
+// the fills of a frame, for screens drawn without the skin textures
+fn draw_fills(frame: &Frame, c: &Context, gl: &mut GlGraphics) {
+    for command in &frame.commands {
+        if let DrawCommand::Fill { color, rect } = *command {
+            rectangle(color, [rect.x, rect.y, rect.w, rect.h], c.transform, gl);
+        }
+    }
+}
+
 fn is_bms(entry: &DirEntry) -> bool {
     entry.file_name()
         .to_str()
@@ -170,39 +583,367 @@ fn show_loading(mut window: &mut Window, mut gl: &mut GlGraphics) {
     }
 }
 
-fn play_bms(mut window: &mut Window, mut gl: &mut GlGraphics, script_path: String) {
+// what the loader left out, on the loading screen until a key is pressed or a few seconds pass
+fn show_load_report(window: &mut Window, gl: &mut GlGraphics, glyphs: &mut GlyphCache, report: &bms_loader::LoadReport) {
+    const BG_COLOR: [f32; 4] = [0.3, 0.3, 0.3, 1.0];
+    const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+    const FONT_SIZE: u32 = 16;
+    const MAX_LINES: usize = 20;
+    const SHOW_SECONDS: f64 = 3.0;
+
+    let mut lines = vec![report.summary(), String::new()];
+    let problems = report.problems();
+    lines.extend(problems.iter().take(MAX_LINES).cloned());
+    if problems.len() > MAX_LINES {
+        lines.push(format!("and {} more", problems.len() - MAX_LINES));
+    }
+
+    let until = time::precise_time_s() + SHOW_SECONDS;
+    let mut events = Events::new(EventSettings::new());
+    while let Some(e) = events.next(window) {
+        if let Some(r) = e.render_args() {
+            gl.draw(r.viewport(), |c, gl| {
+                clear(BG_COLOR, gl);
+                let line_height = FONT_SIZE as f64 * 1.4;
+                for (i, line) in lines.iter().enumerate() {
+                    Text::new_color(WHITE, FONT_SIZE).draw(line, glyphs, &DrawState::new_alpha(), c.transform.trans(20.0, line_height * (i as f64 + 2.0)), gl);
+                }
+            });
+        }
+        if e.press_args().is_some() || time::precise_time_s() > until {
+            break;
+        }
+    }
+}
+
+// play follows the samples the mixer has played, or the wall clock when it can't be asked
+fn audio_clock() -> Box<Clock> {
+    match AudioClock::new() {
+        Ok(clock) => Box::new(clock),
+        Err(e) => {
+            println!("the audio clock is not available, using the wall clock: {}", e);
+            Box::new(WallClock::new())
+        }
+    }
+}
+
+fn play_bms(mut window: &mut Window, mut gl: &mut GlGraphics, score_db: &mut ScoreDb, script_path: String, options: &PlayOptions) {
     show_loading(&mut window, &mut gl);
 
-    let mut textures_map = HashMap::new();
-    textures_map.insert(TextureLabel::BACKGROUND, Texture::from_path(Path::new("resource/background.png")).unwrap());
-    textures_map.insert(TextureLabel::LANE_BG, Texture::from_path(Path::new("resource/lane_bg.png")).unwrap());
-    textures_map.insert(TextureLabel::NOTE_BLUE, Texture::from_path(Path::new("resource/note_blue.png")).unwrap());
-    textures_map.insert(TextureLabel::NOTE_RED, Texture::from_path(Path::new("resource/note_red.png")).unwrap());
-    textures_map.insert(TextureLabel::NOTE_WHITE, Texture::from_path(Path::new("resource/note_white.png")).unwrap());
-    textures_map.insert(TextureLabel::JUDGE_PERFECT, Texture::from_path(Path::new("resource/judge_perfect.png")).unwrap());
-    textures_map.insert(TextureLabel::JUDGE_GREAT, Texture::from_path(Path::new("resource/judge_great.png")).unwrap());
-    textures_map.insert(TextureLabel::JUDGE_GOOD, Texture::from_path(Path::new("resource/judge_good.png")).unwrap());
-    textures_map.insert(TextureLabel::JUDGE_BAD, Texture::from_path(Path::new("resource/judge_bad.png")).unwrap());
-    textures_map.insert(TextureLabel::JUDGE_POOR, Texture::from_path(Path::new("resource/judge_poor.png")).unwrap());
-    textures_map.insert(TextureLabel::RED_BEAM, Texture::from_path(Path::new("resource/redbeam.png")).unwrap());
-    textures_map.insert(TextureLabel::WHITE_BEAM, Texture::from_path(Path::new("resource/whitebeam.png")).unwrap());
-    textures_map.insert(TextureLabel::BLUE_BEAM, Texture::from_path(Path::new("resource/bluebeam.png")).unwrap());
-
-    let mut digits = Vec::new();
-    for i in 0..10 {
-        digits.push(Texture::from_path(Path::new(&format!("resource/{}.png", i))).unwrap());
+    let mut skin = match options.skin {
+        Some(ref path) => Skin::load(path),
+        None => Skin::load_default(&Config::open(SETTINGS_FILE)),
+    };
+    let skin_textures: Vec<Texture> = skin.files.iter()
+        .map(|file| Texture::from_path(file).expect(&format!("failed to load skin image {}", file.to_str().unwrap_or(""))))
+        .collect();
+    for (file, texture) in skin_textures.iter().enumerate() {
+        let (w, h) = texture.get_size();
+        skin.set_file_size(file, w as f64, h as f64);
     }
+    let mut glyphs = GlyphCache::new(&skin.font).expect("Could not load font");
 
     let loader = bms_loader::BmsFileLoader::new(&script_path);
 
     use bms_loader::BmsLoader;
-    println!("Start loading at {}", time::precise_time_s());
+    let (lane_option, mut seed) = match options.replay {
+        Some(ref replay) => (replay.lane_option, replay.seed),
+        None => (options.lane_option, options.seed.unwrap_or_else(rand::random)),
+    };
+    let mut bms = match loader.load() {
+        Ok((bms, report)) => {
+            for problem in report.problems() {
+                println!("{}", problem);
+            }
+            println!("{}", report.summary());
+            if !report.is_clean() {
+                show_load_report(&mut window, &mut gl, &mut glyphs, &report);
+            }
+            bms
+        }
+        Err(e) => {
+            println!("failed to load {}: {}", script_path, e);
+            return;
+        }
+    };
+    let mut textures = bms_player::PresetTextures {
+        textures: skin_textures,
+        bga: bms.images.drain(..).map(|image| Texture::from_image(&image, &TextureSettings::new())).collect(),
+        glyphs: glyphs,
+    };
+    // lane options shuffle a copy so a retry can roll them again
+    let chart_sounds = bms.sounds.clone();
+    let play_mode = bms.play_mode();
+    let analysis = ChartAnalysis::new(&bms);
+
+    let hash = match score_db::chart_hash(&script_path) {
+        Ok(hash) => Some(hash),
+        Err(e) => {
+            println!("failed to hash {}: {}", script_path, e);
+            None
+        }
+    };
+
+    let mut hispeed = options.hispeed;
+    let mut lane_cover = options.lane_cover;
+    loop {
+        bms.sounds = chart_sounds.clone();
+        lane_option::apply(lane_option, seed, &mut bms.sounds);
+        let key_bindings = KeyConfig::load().bindings(play_mode);
+
+        let mut bms_player = bms_player::BmsPlayer::new(
+            skin.clone(),
+            &bms,
+            audio_clock(),
+            hispeed,
+            lane_cover,
+            key_bindings,
+            LaneLayout::new(play_mode, options.scratch_side, options.play_side)
+        );
+
+        if let Some(ref replay) = options.replay {
+            if hash.as_ref() != Some(&replay.chart_hash) {
+                println!("warning: replay was recorded on a different chart");
+            }
+            bms_player.load_replay(replay);
+        } else {
+            bms_player.set_autoplay(&options.autoplay);
+        }
+        if options.latency_report {
+            bms_player.enable_latency_report();
+        }
+        match options.start {
+            Some(StartAt::Time(pt)) => bms_player.seek(pt),
+            Some(StartAt::Measure(measure)) => bms_player.seek_measure(measure),
+            None => (),
+        }
+        if let Some(practice) = options.practice {
+            bms_player.set_practice(practice);
+        }
+
+        let result = bms_player.run(&mut window, &mut gl, &mut textures);
+
+        // covers and speed adjusted during play are kept for the next one
+        if !bms_player.is_replaying() {
+            hispeed = bms_player.hispeed();
+            lane_cover = bms_player.lane_cover();
+            let mut settings = Config::open(SETTINGS_FILE);
+            lane_cover.store(&mut settings);
+            if let Err(e) = settings.save() {
+                println!("failed to save settings: {}", e);
+            }
+        }
+
+        // replays and assisted plays are watched, not scored, and neither is a play with parts skipped
+        let scored = !(bms_player.is_replaying() || bms_player.is_autoplay() || bms_player.has_seeked());
+        if let (true, Some(hash)) = (scored, hash.clone()) {
+            let now = time::get_time().sec;
+            score_db.update(&hash, &result, now);
+            if let Err(e) = score_db.save() {
+                println!("failed to save score: {}", e);
+            }
+
+            let mut replay = bms_player.recorded_replay().clone();
+            replay.chart_hash = hash;
+            replay.lane_option = lane_option;
+            replay.seed = seed;
+            if let Err(e) = replay.save(replay.default_path(now)) {
+                println!("failed to save replay: {}", e);
+            }
+        }
+
+        let retry = match bms_player.retry() {
+            Some(retry) => retry,
+            None => match result_screen(&mut window, &mut gl, &mut textures.glyphs, &result, &analysis, &KeyConfig::load().bindings(play_mode)) {
+                Some(retry) => retry,
+                None => return,
+            },
+        };
+        // a replay always plays the lanes it was recorded with
+        if retry == Retry::REROLL && options.replay.is_none() {
+            seed = rand::random();
+        }
+    }
+}
+
+// stats of each chart on stdout, as text or a JSON object per line
+fn info(args: Vec<String>) {
+    use bms_loader::BmsLoader;
+    let json = args.iter().any(|arg| arg == "--json");
+    let paths: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+    if paths.is_empty() {
+        eprintln!("usage: rust-bms info [--json] chart...");
+        return;
+    }
+    for (i, path) in paths.iter().enumerate() {
+        let stats = match bms_loader::BmsFileLoader::chart_only(path).load() {
+            Ok((bms, _)) => ChartStats::new(&bms),
+            Err(e) => {
+                eprintln!("failed to load {}: {}", path, e);
+                continue;
+            }
+        };
+        if json {
+            println!("{}", stats.json(path));
+        } else {
+            if i > 0 {
+                println!();
+            }
+            println!("{}\n{}", path, stats.text());
+        }
+    }
+}
+
+// authoring mistakes of each chart, then the channels the player ignores.
+// exits with 1 when any chart has one
+fn lint(paths: Vec<String>) {
+    use bms_loader::BmsLoader;
+    if paths.is_empty() {
+        eprintln!("usage: rust-bms lint chart...");
+        return;
+    }
+    let mut found = false;
+    for path in &paths {
+        let script = match bms_parser::BmsFileParser { path: path.to_string() }.read() {
+            Ok(script) => script,
+            Err(e) => {
+                println!("{}: failed to read: {}", path, e);
+                found = true;
+                continue;
+            }
+        };
+        let lints = bms_lint::lint(&script, Path::new(path).parent());
+        for lint in &lints {
+            println!("{}: {}", path, lint);
+        }
+        // the rest of the report is what the lints above already cover
+        let unsupported = bms_loader::BmsFileLoader::chart_only(path).load().map(|(_, report)| report.unsupported_channels).unwrap_or(vec![]);
+        for channel in &unsupported {
+            println!("{}: channel {} is not played", path, channel);
+        }
+        found |= !lints.is_empty() || !unsupported.is_empty();
+    }
+    if found {
+        std::process::exit(1);
+    }
+}
+
+// every measure of the chart in one image, lanes laid out like they would be played
+fn write_preview(script_path: &str, options: &PlayOptions, preview: &Path) {
+    use bms_loader::BmsLoader;
+    let bms = match bms_loader::BmsFileLoader::chart_only(script_path).load() {
+        Ok((bms, _)) => bms,
+        Err(e) => {
+            println!("failed to load {}: {}", script_path, e);
+            return;
+        }
+    };
+    let layout = LaneLayout::new(bms.play_mode(), options.scratch_side, PlaySide::P1);
+    match chart_preview::render(&bms, &layout).into_image().save(preview) {
+        Ok(_) => println!("wrote {}", preview.to_str().unwrap_or("")),
+        Err(e) => println!("failed to save {}: {}", preview.to_str().unwrap_or(""), e),
+    }
+}
+
+const DUMP_WIDTH: u32 = 800;
+const DUMP_HEIGHT: u32 = 600;
+
+// renders the play screen at each time to frame_<time>.png, with no window or mixer.
+// autoplay and replays are followed, so judges and combos show up like they would in play
+fn dump_frames(script_path: &str, options: &PlayOptions, times: &[f64]) {
+    let mut skin = match options.skin {
+        Some(ref path) => Skin::load(path),
+        None => Skin::load_default(&Config::open(SETTINGS_FILE)),
+    };
+
+    use bms_loader::BmsLoader;
+    let mut bms = match bms_loader::BmsFileLoader::without_sound(script_path).load() {
+        Ok((bms, _)) => bms,
+        Err(e) => {
+            println!("failed to load {}: {}", script_path, e);
+            return;
+        }
+    };
+    let renderer = SoftwareRenderer::new(&mut skin, bms.images.drain(..).collect());
+    let (lane_option, seed) = match options.replay {
+        Some(ref replay) => (replay.lane_option, replay.seed),
+        None => (options.lane_option, options.seed.unwrap_or_else(rand::random)),
+    };
+    lane_option::apply(lane_option, seed, &mut bms.sounds);
+    let play_mode = bms.play_mode();
+
     let mut bms_player = bms_player::BmsPlayer::new(
-        bms_player::PresetTextures {lane_components: textures_map, digits: digits},
-        loader.load(),
-        0.0,
-        1.0
+        skin,
+        &bms,
+        Box::new(ManualClock::new()),
+        options.hispeed,
+        options.lane_cover,
+        KeyConfig::load().bindings(play_mode),
+        LaneLayout::new(play_mode, options.scratch_side, options.play_side)
     );
+    match options.replay {
+        Some(ref replay) => bms_player.load_replay(replay),
+        None => bms_player.set_autoplay(&options.autoplay),
+    }
 
-    bms_player.run(&mut window, &mut gl);
-}
\ No newline at end of file
+    // the session only moves forward
+    let mut times = times.to_vec();
+    times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    for pt in times {
+        let frame = bms_player.frame_at(pt, DUMP_WIDTH as f64, DUMP_HEIGHT as f64);
+        let path = options.dump_dir.join(format!("frame_{}.png", pt));
+        match renderer.render(&frame, bms_player.skin()).into_image().save(&path) {
+            Ok(_) => println!("wrote {}", path.to_str().unwrap_or("")),
+            Err(e) => println!("failed to save {}: {}", path.to_str().unwrap_or(""), e),
+        }
+    }
+}
+
+// the judges of the play just finished, with where the combo broke over the chart's graph.
+// the retry keys play again, Return or Escape leaves
+fn result_screen(window: &mut Window, gl: &mut GlGraphics, glyphs: &mut GlyphCache, result: &PlayResult, analysis: &ChartAnalysis, key_bindings: &KeyBindings) -> Option<Retry> {
+    const BG_COLOR: [f32; 4] = [0.3, 0.3, 0.3, 1.0];
+    const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+    const FONT_SIZE: u32 = 28;
+
+    let lamp = result.lamp();
+    let mut lines = vec![];
+    for judge in &[Judge::PGREAT, Judge::GREAT, Judge::GOOD, Judge::BAD, Judge::POOR, Judge::MISSED] {
+        lines.push(format!("{:?}  {}", judge, result.count(*judge)));
+    }
+    lines.push(String::new());
+    lines.push(format!("EX SCORE  {} / {}", result.ex_score(), result.total_notes * 2));
+    lines.push(format!("MISS COUNT  {}", result.bp()));
+    lines.push(format!("MAX COMBO  {}", result.max_combo));
+    lines.push(format!("GAUGE  {:.0}%", result.gauge));
+
+    let mut events = Events::new(EventSettings::new());
+    while let Some(e) = events.next(window) {
+        if let Some(r) = e.render_args() {
+            let mut graph = Frame::new(r.width as f64, r.height as f64);
+            analysis.graph(&mut graph, Rect::new(r.width as f64 / 2.0, FONT_SIZE as f64 * 4.0, r.width as f64 / 2.0 - 40.0, 120.0), &result.misses);
+            gl.draw(r.viewport(), |c, gl| {
+                clear(BG_COLOR, gl);
+                let line_height = FONT_SIZE as f64 * 1.4;
+
+                Text::new_color(lamp_color(lamp), FONT_SIZE * 2).draw(&format!("{:?}", lamp), glyphs, &DrawState::new_alpha(), c.transform.trans(40.0, line_height * 2.0), gl);
+                for (i, line) in lines.iter().enumerate() {
+                    Text::new_color(WHITE, FONT_SIZE).draw(line, glyphs, &DrawState::new_alpha(), c.transform.trans(40.0, line_height * (i as f64 + 4.0)), gl);
+                }
+                draw_fills(&graph, &c, gl);
+            });
+        }
+
+        if let Some(Button::Keyboard(key)) = e.press_args() {
+            match key_bindings.controls.get(&InputSource::Keyboard(key)) {
+                Some(&Control::RETRY) => return Some(Retry::SAME_OPTION),
+                Some(&Control::RETRY_REROLL) => return Some(Retry::REROLL),
+                _ => (),
+            }
+            if key == Key::Return || key == Key::Escape {
+                break;
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,48 @@
+use sdl2::mixer::{self, Channel, Chunk};
+use std::path::Path;
+
+type Time = f64;
+
+// a sample started part way through, for BGM that should already be sounding after a seek or pause.
+// the mixer only plays chunks from their start, so the decoded buffer is moved forward while it plays
+// and put back before SDL_mixer frees it
+pub struct PartialSound {
+    chunk: Chunk,
+    abuf: *mut u8,
+    alen: u32,
+}
+
+impl PartialSound {
+    pub fn play(path: &Path, offset: Time) -> Result<PartialSound, String> {
+        let (frequency, format, channels) = mixer::query_spec()?;
+        // the chunk is decoded to the device format, the low byte of which is the bits per sample
+        let frame_size = (format & 0xff) as u32 / 8 * channels as u32;
+        let chunk = Chunk::from_file(path)?;
+        unsafe {
+            let raw = chunk.raw;
+            let abuf = (*raw).abuf;
+            let alen = (*raw).alen;
+            let skip = u32::min((offset * frequency as f64) as u32 * frame_size, alen);
+            (*raw).abuf = abuf.offset(skip as isize);
+            (*raw).alen = alen - skip;
+
+            let sound = PartialSound { chunk: chunk, abuf: abuf, alen: alen };
+            Channel::all().play(&sound.chunk, 0)?;
+            Ok(sound)
+        }
+    }
+}
+
+impl Drop for PartialSound {
+    fn drop(&mut self) {
+        unsafe {
+            (*self.chunk.raw).abuf = self.abuf;
+            (*self.chunk.raw).alen = self.alen;
+        }
+    }
+}
+
+// stops every sound the mixer is playing, keysounds from piston-music included
+pub fn halt_all() {
+    Channel::all().halt();
+}
@@ -0,0 +1,432 @@
+use std::collections::HashMap;
+
+use bms_loader::{self, Bms, BpmChange, Key, Stop};
+use frame::{Frame, TextureId};
+use game_session::{GameSession, Judge};
+use hispeed::{self, HiSpeed, HiSpeedMode};
+use lane_cover::LaneCover;
+use lane_layout::LaneLayout;
+use skin::{ImageId, Rect, Skin};
+
+type Time = f64;
+
+const NOTES_HEIGHT: f64 = 10.0;
+const BAR_HEIGHT: f64 = 1.0;
+const OFFSET: f64 = 2.5;
+const JUDGE_DISPLAY_TIME: f64 = 1.0;
+
+const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+pub fn calc_position(t: Time, bpms: &Vec<BpmChange>) -> f64 {
+    let mut y = 0f64;
+    let mut p_bpm = 130f64;
+    let mut p_timing = 0f64;
+    for bpm in bpms {
+        let d = if bpm.timing < t {
+            bpm.timing - p_timing
+        } else if t - p_timing > 0f64 {
+            t - p_timing
+        } else {
+            0f64
+        };
+        if d < 0f64 {
+            break;
+        }
+        y += d * p_bpm;
+        p_bpm = bpm.bpm;
+        p_timing = bpm.timing;
+    }
+    y += if t - p_timing > 0f64 {
+        t - p_timing
+    } else {
+        0f64
+    } * p_bpm;
+
+    y
+}
+
+pub fn f64_eq(a: f64, b: f64) -> bool {
+    f64::abs(a - b) < 1e-9
+}
+
+#[test]
+pub fn test_calc_position() {
+    let bpms = vec![
+        BpmChange { timing: 0f64, bpm: 100f64 },
+        BpmChange { timing: 10f64, bpm: 200f64 },
+        BpmChange { timing: 20f64, bpm: 400f64 },
+    ];
+
+    assert!(f64_eq(0f64, calc_position(0f64, &bpms)));
+    assert!(f64_eq(500f64, calc_position(5f64, &bpms)));
+    assert!(f64_eq(1000f64, calc_position(10f64, &bpms)));
+    assert!(f64_eq(2000f64, calc_position(15f64, &bpms)));
+    assert!(f64_eq(3000f64, calc_position(20f64, &bpms)));
+    assert!(f64_eq(5000f64, calc_position(25f64, &bpms)));
+    assert!(f64_eq(7000f64, calc_position(30f64, &bpms)));
+
+    // a 2 second stop at 5 holds the position until 7, then the chart goes on at 100
+    let stopped = bms_loader::scroll_bpms(&bpms, &vec![Stop { timing: 5f64, duration: 2f64 }]);
+    assert!(f64_eq(500f64, calc_position(6f64, &stopped)));
+    assert!(f64_eq(500f64, calc_position(7f64, &stopped)));
+    assert!(f64_eq(800f64, calc_position(10f64, &stopped)));
+    assert!(f64_eq(1800f64, calc_position(15f64, &stopped)));
+}
+
+// what the player shows besides the chart itself
+pub struct Overlay {
+    pub hispeed: HiSpeed,
+    pub lane_cover: LaneCover,
+    pub show_hispeed: bool,
+    pub bga: Option<i32>,
+    pub paused: bool,
+    pub latency: Option<String>,
+    pub practice: Option<String>,
+}
+
+#[derive(Clone)]
+struct Draw {
+    pub timing: Time,
+    pub y: f64,
+    pub x: f64,
+    pub width: f64,
+    pub height: f64,
+    pub image: Option<ImageId>,
+    pub ln_end: Option<Time>,
+    pub ln_end_y: Option<f64>,
+}
+
+// in lane coordinates, x from the left of the lanes and y from their top
+struct DrawInfo {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+    pub image: Option<ImageId>,
+}
+
+impl DrawInfo {
+    fn long_note_body(draw: &Draw, top: f64, bottom: f64) -> DrawInfo {
+        let height = f64::max(bottom - top, 0.0);
+        DrawInfo { x: draw.x + draw.width * 0.15, y: top + height / 2.0, width: draw.width * 0.7, height: height, image: draw.image }
+    }
+}
+
+// lays out the play screen at a moment as a frame, for whichever backend draws it
+pub struct PlayView {
+    skin: Skin,
+    layout: LaneLayout,
+    objects_by_key: HashMap<Key, Vec<Draw>>, // same order as the session notes, x from the left of the lanes
+    obj_index_by_key: HashMap<Key, usize>,
+    bpms: Vec<BpmChange>,
+    stops: Vec<Stop>,
+    scroll_bpms: Vec<BpmChange>, // bpms the notes scroll by, flat in CONSTANT hi-speed, 0 through stops
+    base_bpm: f64,
+    chart_end: Time,
+}
+
+impl PlayView {
+    pub fn new(skin: Skin, bms: &Bms, session: &GameSession, layout: LaneLayout, hispeed: HiSpeed) -> PlayView {
+        // note positions are filled in by set_hispeed
+        let mut objects_by_key = HashMap::new();
+        for key in Key::visible_keys() {
+            let objects = match layout.lane(key) {
+                Some(lane) => session.notes(key).iter().map(|note| {
+                    Draw {
+                        timing: note.timing, x: lane.x + OFFSET, y: 0.0, width: lane.width - OFFSET * 2.0, height: NOTES_HEIGHT,
+                        image: skin.notes.get(&lane.color).map(|id| *id), ln_end: note.ln_end, ln_end_y: None,
+                    }
+                }).collect(),
+                None => vec![],
+            };
+            objects_by_key.insert(key, objects);
+        }
+        objects_by_key.insert(Key::BACK_CHORUS, bms.bars.iter().map(|bar| {
+            Draw { timing: *bar, x: 0.0, y: 0.0, width: layout.width(), height: BAR_HEIGHT, image: skin.bar, ln_end: None, ln_end_y: None }
+        }).collect());
+        let obj_index_by_key = objects_by_key.keys().map(|key| (*key, 0usize)).collect();
+
+        let mut view = PlayView {
+            skin: skin,
+            layout: layout,
+            objects_by_key: objects_by_key,
+            obj_index_by_key: obj_index_by_key,
+            bpms: bms.bpms.clone(),
+            stops: bms.stops.clone(),
+            scroll_bpms: vec![],
+            base_bpm: 130f64,
+            chart_end: bms.end(),
+        };
+        view.set_hispeed(hispeed);
+        view
+    }
+
+    pub fn skin(&self) -> &Skin {
+        &self.skin
+    }
+
+    // CONSTANT hi-speed scrolls every note at the base bpm, so positions depend on the mode
+    pub fn set_hispeed(&mut self, hispeed: HiSpeed) {
+        self.base_bpm = hispeed::base_bpm(&bms_loader::scroll_bpms(&self.bpms, &self.stops), self.chart_end, hispeed.base);
+        let bpms = match hispeed.mode {
+            HiSpeedMode::CONSTANT => vec![BpmChange { timing: 0.0, bpm: self.base_bpm }],
+            _ => self.bpms.clone(),
+        };
+        self.scroll_bpms = bms_loader::scroll_bpms(&bpms, &self.stops);
+        let scroll_bpms = &self.scroll_bpms;
+        for objects in self.objects_by_key.values_mut() {
+            for draw in objects.iter_mut() {
+                draw.y = calc_position(draw.timing, scroll_bpms);
+                draw.ln_end_y = draw.ln_end.map(|end| calc_position(end, scroll_bpms));
+            }
+        }
+    }
+
+    // after a seek, looks for what is on screen from the first object again
+    pub fn rewind(&mut self) {
+        for index in self.obj_index_by_key.values_mut() {
+            *index = 0;
+        }
+    }
+
+    pub fn frame(&mut self, pt: Time, session: &GameSession, overlay: &Overlay, width: f64, height: f64) -> Frame {
+        let y_offset = calc_position(pt, &self.scroll_bpms);
+        let skin = &self.skin;
+        let (sx, sy) = skin.scale(width, height);
+        let side = self.layout.side();
+        let double = self.layout.is_double();
+        let element = |name: &str| skin.element(name, side, double).map(|rect| rect.scaled(sx, sy));
+        // without a lanes element the lanes take the whole height on their side of the window
+        let lanes_rect = element("lanes").unwrap_or(Rect::new(self.layout.x(width), 0.0, self.layout.width(), height));
+
+        // lane x and widths are stretched to the lanes element, heights are in window pixels
+        let lane_scale = lanes_rect.w / self.layout.width();
+        let lane_width = self.layout.width();
+        let lane_height = lanes_rect.h;
+        let lane_rect = |x: f64, y: f64, w: f64, h: f64| Rect::new(lanes_rect.x + x * lane_scale, lanes_rect.y + y, w * lane_scale, h);
+        let cover = overlay.lane_cover;
+        let judge_y = cover.judge_line_y(lane_height);
+        let sudden_y = cover.sudden_y(lane_height);
+        let hidden_y = cover.hidden_y(lane_height);
+        let speed = overlay.hispeed.multiplier(self.base_bpm, cover.visible_height(lane_height));
+
+        // drawable objects
+        let mut drawings = vec![];
+        for (key, objects) in &self.objects_by_key {
+            let start = *self.obj_index_by_key.get(key).unwrap();
+            let judge_consumed = session.judged_count(*key);
+
+            let mut next_start = start;
+            for (index, draw) in objects.iter().enumerate().skip(start) {
+                let y = (draw.y - y_offset) * speed;
+                let y = judge_y - y;
+                let tail_y = draw.ln_end_y.map(|end_y| judge_y - (end_y - y_offset) * speed);
+                // only what is between SUDDEN+ and HIDDEN+ is drawn
+                let visible = |y: f64| sudden_y <= y && y <= hidden_y;
+
+                if tail_y.unwrap_or(y) > lane_height {
+                    next_start += 1;
+                } else if index >= judge_consumed {
+                    if visible(y) {
+                        drawings.push(DrawInfo { x: draw.x, y: y - NOTES_HEIGHT, width: draw.width, height: draw.height, image: draw.image });
+                    }
+                    if let Some(tail_y) = tail_y {
+                        drawings.push(DrawInfo::long_note_body(draw, f64::max(tail_y, sudden_y) - NOTES_HEIGHT, f64::min(y, hidden_y) - NOTES_HEIGHT));
+                        if visible(tail_y) {
+                            drawings.push(DrawInfo { x: draw.x, y: tail_y - NOTES_HEIGHT, width: draw.width, height: draw.height, image: draw.image });
+                        }
+                    }
+                } else if let Some(tail_y) = tail_y {
+                    // judged long note, the rest of the body still runs into the judge line
+                    drawings.push(DrawInfo::long_note_body(draw, f64::max(tail_y, sudden_y) - NOTES_HEIGHT, hidden_y - NOTES_HEIGHT));
+                }
+                if y < sudden_y {
+                    break;
+                }
+            }
+            *self.obj_index_by_key.get_mut(key).unwrap() = next_start;
+        }
+        // the map has no order, this keeps frames the same from run to run
+        drawings.sort_by(|a, b| (a.x, a.y).partial_cmp(&(b.x, b.y)).unwrap());
+
+        let mut frame = Frame::new(width, height);
+
+        // back ground
+        if let Some(id) = skin.background {
+            frame.image(TextureId::Skin(id), Rect::new(0.0, 0.0, width, height));
+        }
+
+        // lanes
+        if let Some(id) = skin.lane_bg {
+            frame.image(TextureId::Skin(id), lane_rect(0.0, 0.0, lane_width, lane_height));
+        }
+
+        // beams
+        let mut beams: Vec<_> = session.pushed_keys().iter().filter_map(|key| self.layout.lane(*key)).map(|lane| *lane).collect();
+        beams.sort_by(|a, b| a.x.partial_cmp(&b.x).unwrap());
+        for lane in &beams {
+            if let Some(id) = skin.beams.get(&lane.color) {
+                frame.image(TextureId::Skin(*id), lane_rect(lane.x + OFFSET, 0.0, lane.width - OFFSET * 2.0, judge_y - 14f64));
+            }
+        }
+
+        // notes and bars
+        for draw in &drawings {
+            if let Some(id) = draw.image {
+                frame.image(TextureId::Skin(id), lane_rect(draw.x, draw.y - draw.height / 2.0, draw.width, draw.height));
+            }
+        }
+
+        // lane covers
+        const COVER_COLOR: [f32; 4] = [0.1, 0.1, 0.1, 1.0];
+        frame.fill(COVER_COLOR, lane_rect(0.0, 0.0, lane_width, sudden_y));
+        frame.fill(COVER_COLOR, lane_rect(0.0, hidden_y, lane_width, judge_y - hidden_y));
+        frame.fill(COVER_COLOR, lane_rect(0.0, judge_y, lane_width, lane_height - judge_y));
+
+        // bga
+        if let (Some(id), Some(rect)) = (overlay.bga, element("bga")) {
+            frame.image(TextureId::Bga(id as usize), rect);
+        }
+
+        // judge and combo, as high as the judge element and moving up with LIFT
+        let judge = match session.last_judge() {
+            Some((judge, judged_at)) if pt <= judged_at + JUDGE_DISPLAY_TIME => skin.judges.get(&judge).map(|id| (judge, *id)),
+            _ => None,
+        };
+        if let (Some((judge, id)), Some(rect)) = (judge, element("judge")) {
+            let h = rect.h;
+            let mut images = vec![(id, skin.image_width(id, h))];
+            let combo = match judge {
+                Judge::PGREAT | Judge::GREAT | Judge::GOOD => session.combo(),
+                _ => 0,
+            };
+            // using the width of '0' for all digits to be monospaced...
+            if let (true, Some(zero)) = (combo > 0, skin.digits.first()) {
+                let dw = skin.image_width(*zero, h);
+                for digit in combo.to_string().bytes().map(|digit| (digit - b'0') as usize) {
+                    if let Some(id) = skin.digits.get(digit) {
+                        images.push((*id, dw));
+                    }
+                }
+            }
+
+            let total_w = images.iter().fold(0.0, |w, &(_, iw)| w + iw);
+            let mut x = rect.x + (rect.w - total_w) / 2.0;
+            let y = rect.y + judge_y - lane_height;
+            for (id, w) in images {
+                frame.image(TextureId::Skin(id), Rect::new(x, y, w, h));
+                x += w;
+            }
+        }
+
+        // hi-speed while it is being adjusted
+        if overlay.show_hispeed {
+            const FONT_SIZE: u32 = 20;
+            let text = format!("WHITE {:.0}  GREEN {:.0}  HI-SPEED {:.2}", cover.white_number(),
+                               overlay.hispeed.green_number(self.base_bpm, cover.visible_height(lane_height)), speed);
+            let y = lanes_rect.y + 0.85 * judge_y;
+            frame.fill([0.0, 0.0, 0.0, 0.7], Rect::new(lanes_rect.x, y - FONT_SIZE as f64 * 1.1, lanes_rect.w, FONT_SIZE as f64 * 1.5));
+            frame.text(&text, [0.3, 1.0, 0.3, 1.0], FONT_SIZE, lanes_rect.x + FONT_SIZE as f64 * 0.5, y);
+        }
+
+        if let Some(rect) = element("progress") {
+            let progress = if self.chart_end > 0.0 { f64::min(f64::max(pt / self.chart_end, 0.0), 1.0) } else { 0.0 };
+            frame.fill([0.2, 0.2, 0.2, 1.0], rect);
+            frame.fill([0.3, 1.0, 0.3, 1.0], Rect { w: rect.w * progress, ..rect });
+        }
+
+        let gauge_color = if session.gauge().is_clear() { [1.0, 0.3, 0.3, 1.0] } else { [0.3, 0.6, 1.0, 1.0] };
+        let texts = [
+            ("gauge", Some(format!("GROOVE GAUGE {:.0}%", session.gauge().value())), gauge_color),
+            ("bpm", Some(format!("BPM {:.0}", session.bpm())), WHITE),
+            ("score", Some(format!("EX SCORE {}", session.result().ex_score())), WHITE),
+            ("latency", overlay.latency.clone(), [1.0, 1.0, 0.3, 1.0]),
+            ("practice", overlay.practice.clone(), WHITE),
+        ];
+        for &(name, ref text, color) in &texts {
+            // text elements are as high as their font, drawn from the baseline at the bottom
+            if let (&Some(ref text), Some(rect)) = (text, element(name)) {
+                frame.text(text, color, rect.h as u32, rect.x, rect.y + rect.h);
+            }
+        }
+
+        if overlay.paused {
+            const FONT_SIZE: u32 = 40;
+            frame.fill([0.0, 0.0, 0.0, 0.6], Rect::new(0.0, 0.0, width, height));
+            frame.text("PAUSE", WHITE, FONT_SIZE, width / 2.0 - FONT_SIZE as f64 * 1.5, height / 2.0);
+        }
+        frame
+    }
+}
+
+#[test]
+fn play_view_snapshot_test() {
+    use config::Config;
+    use game_session::{test_bms, test_sound};
+    use lane_layout::{PlaySide, ScratchSide};
+    use std::path::Path;
+
+    let config = Config::from_contents("
+        image.note = note.png
+        image.judge = judge.png
+        image.digits = digits.png 0,0,20,30
+        image.digit_1 = digits.png 20,0,20,30
+        image.digit_2 = digits.png 40,0,20,30
+        note.WHITE = note
+        note.RED = note
+        judge.PGREAT = judge
+        digit.0 = digits
+        digit.1 = digit_1
+        digit.2 = digit_2
+        element.lanes = 0,0,200,600
+        element.judge = 0,400,200,30
+        element.bpm = 300,500,200,20
+    ");
+    let mut skin = Skin::from_config(&config, Path::new("skin"));
+    // judge.png
+    skin.set_file_size(1, 60.0, 10.0);
+
+    let mut bms = test_bms(vec![
+        test_sound(Key::P1_KEY1, 1.0, 1),
+        test_sound(Key::P1_SCRATCH, 1.5, 2),
+        test_sound(Key::P1_KEY1, 3.0, 3),
+    ], None);
+    bms.bars = vec![0.0, 1.6];
+    let mut session = GameSession::new(&bms);
+    let layout = LaneLayout::new(bms.play_mode(), ScratchSide::LEFT, PlaySide::P1);
+    // 200px of lane for 388px of 5K layout, one second of 150bpm scrolls 150px
+    let hispeed = HiSpeed::normal(1.0);
+    let mut view = PlayView::new(skin, &bms, &session, layout, hispeed);
+    let mut overlay = Overlay {
+        hispeed: hispeed, lane_cover: LaneCover::none(), show_hispeed: false, bga: None, paused: false, latency: None, practice: None,
+    };
+
+    session.update(0.0);
+    assert_eq!(vec![
+        "image skin 4 1,360 53x10 a1.00",
+        "image skin 4 57,135 28x10 a1.00",
+        "image skin 4 57,435 28x10 a1.00",
+        "fill 0.1,0.1,0.1,1.0 0,0 200x0",
+        "fill 0.1,0.1,0.1,1.0 0,600 200x0",
+        "fill 0.1,0.1,0.1,1.0 0,600 200x0",
+        "text \"BPM 150\" 20 300,520",
+    ].join("\n"), view.frame(0.0, &session, &overlay, 800.0, 600.0).snapshot());
+
+    // two notes hit on time, the combo is shown with the judge
+    session.press(Key::P1_KEY1, 1.0);
+    session.press(Key::P1_SCRATCH, 1.5);
+    session.update(1.5);
+    overlay.paused = true;
+    assert_eq!(vec![
+        "image skin 4 57,360 28x10 a1.00",
+        "fill 0.1,0.1,0.1,1.0 0,0 200x0",
+        "fill 0.1,0.1,0.1,1.0 0,600 200x0",
+        "fill 0.1,0.1,0.1,1.0 0,600 200x0",
+        "image skin 3 0,400 180x30 a1.00",
+        "image skin 1 180,400 20x30 a1.00",
+        "text \"BPM 150\" 20 300,520",
+        "fill 0.0,0.0,0.0,0.6 0,0 800x600",
+        "text \"PAUSE\" 40 340,300",
+    ].join("\n"), view.frame(1.5, &session, &overlay, 800.0, 600.0).snapshot());
+}
+
@@ -0,0 +1,169 @@
+use game_session::PlayResult;
+
+type Time = f64;
+
+const MIN_RATE: f64 = 0.25;
+const MAX_RATE: f64 = 1.0;
+
+// a range of measures to drill, measures are counted by bar line from 0
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Practice {
+    pub start_measure: usize,
+    pub end_measure: usize, // the loop goes back at the start of this measure
+    pub rate: f64,          // slower playback, keysounds still play at their own speed
+}
+
+impl Practice {
+    // "8-12" plays measures 8 to 11 and loops at the bar line of 12
+    pub fn parse(range: &str, rate: f64) -> Option<Practice> {
+        let mut bounds = range.splitn(2, '-').map(|bound| bound.trim().parse::<usize>());
+        match (bounds.next(), bounds.next()) {
+            (Some(Ok(start)), Some(Ok(end))) if start < end => Some(Practice {
+                start_measure: start,
+                end_measure: end,
+                rate: f64::min(f64::max(rate, MIN_RATE), MAX_RATE),
+            }),
+            _ => None,
+        }
+    }
+}
+
+// the loop over a practice range and how every pass through it went
+pub struct PracticeLoop {
+    practice: Practice,
+    start: Time,
+    end: Time,
+    notes: u32,
+    results: Vec<PlayResult>,
+}
+
+impl PracticeLoop {
+    // measures past the last bar line end at the end of the chart
+    pub fn new(practice: Practice, bars: &[Time], chart_end: Time) -> PracticeLoop {
+        let start = bars.get(practice.start_measure).map_or(0.0, |bar| *bar);
+        let end = bars.get(practice.end_measure).map_or(chart_end, |bar| *bar);
+        PracticeLoop { practice: practice, start: start, end: f64::max(end, start), notes: 0, results: vec![] }
+    }
+
+    pub fn start(&self) -> Time {
+        self.start
+    }
+
+    pub fn end(&self) -> Time {
+        self.end
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.practice.rate
+    }
+
+    // notes in the range, what every loop is scored against
+    pub fn set_notes(&mut self, notes: u32) {
+        self.notes = notes;
+    }
+
+    pub fn is_over(&self, pt: Time) -> bool {
+        pt >= self.end
+    }
+
+    pub fn finish_loop(&mut self, mut result: PlayResult) {
+        result.total_notes = self.notes;
+        result.finished = true;
+        self.results.push(result);
+    }
+
+    pub fn results(&self) -> &[PlayResult] {
+        &self.results
+    }
+
+    pub fn best_ex_score(&self) -> Option<u32> {
+        self.results.iter().map(|result| result.ex_score()).max()
+    }
+
+    pub fn status_text(&self) -> String {
+        let mut text = format!("PRACTICE {}-{}", self.practice.start_measure, self.practice.end_measure);
+        if self.practice.rate < 1.0 {
+            text.push_str(&format!(" x{:.2}", self.practice.rate));
+        }
+        text.push_str(&format!("  LOOP {}", self.results.len() + 1));
+        if let Some(last) = self.results.last() {
+            text.push_str(&format!("  LAST EX {} BP {} COMBO {}", last.ex_score(), last.bp(), last.max_combo));
+        }
+        if let Some(best) = self.best_ex_score() {
+            text.push_str(&format!("  BEST EX {}", best));
+        }
+        text
+    }
+
+    // one line per finished loop, for the console when practice ends
+    pub fn summary(&self) -> String {
+        let max_ex = self.notes * 2;
+        self.results.iter().enumerate().map(|(i, result)| {
+            let rate = if max_ex > 0 { result.ex_score() as f64 * 100.0 / max_ex as f64 } else { 0.0 };
+            format!("loop {}: EX {}/{} ({:.1}%) BP {} COMBO {}", i + 1, result.ex_score(), max_ex, rate, result.bp(), result.max_combo)
+        }).collect::<Vec<_>>().join("\n")
+    }
+}
+
+#[test]
+fn practice_loop_test() {
+    use std::collections::HashMap;
+    use game_session::Judge;
+
+    assert_eq!(None, Practice::parse("12-8", 1.0));
+    assert_eq!(None, Practice::parse("8", 1.0));
+    let practice = Practice::parse("1-3", 0.1).unwrap();
+    assert_eq!(MIN_RATE, practice.rate);
+
+    let mut practice_loop = PracticeLoop::new(practice, &[0.0, 2.0, 4.0, 6.0], 10.0);
+    assert_eq!((2.0, 6.0), (practice_loop.start(), practice_loop.end()));
+    assert!(!practice_loop.is_over(5.9));
+    assert!(practice_loop.is_over(6.0));
+    practice_loop.set_notes(4);
+
+    let result = |pgreat: u32, bad: u32| {
+        let mut judge_count = HashMap::new();
+        judge_count.insert(Judge::PGREAT, pgreat);
+        judge_count.insert(Judge::BAD, bad);
+        PlayResult { judge_count: judge_count, max_combo: pgreat, total_notes: 100, gauge: 50.0, finished: false, misses: vec![] }
+    };
+    practice_loop.finish_loop(result(2, 2));
+    practice_loop.finish_loop(result(3, 1));
+    assert_eq!(Some(6), practice_loop.best_ex_score());
+    assert_eq!(4, practice_loop.results()[0].total_notes);
+    assert_eq!("PRACTICE 1-3 x0.25  LOOP 3  LAST EX 6 BP 1 COMBO 3  BEST EX 6", practice_loop.status_text());
+    assert_eq!("loop 1: EX 4/8 (50.0%) BP 2 COMBO 2\nloop 2: EX 6/8 (75.0%) BP 1 COMBO 3", practice_loop.summary());
+
+    // past the last bar line
+    let practice_loop = PracticeLoop::new(Practice::parse("2-9", 1.0).unwrap(), &[0.0, 2.0, 4.0], 10.0);
+    assert_eq!((4.0, 10.0), (practice_loop.start(), practice_loop.end()));
+}
+
+#[test]
+fn practice_loop_end_test() {
+    use bms_loader::Key;
+    use game_session::{self, GameSession, Judge};
+
+    // the last note is too close to the end of the loop for its window to close before it
+    let bms = game_session::test_bms(vec![
+        game_session::test_sound(Key::P1_KEY1, 1.0, 1),
+        game_session::test_sound(Key::P1_KEY2, 1.95, 2),
+        game_session::test_sound(Key::P1_KEY1, 2.0, 3),
+    ], None);
+    let mut session = GameSession::new(&bms);
+    let mut practice_loop = PracticeLoop::new(Practice::parse("0-1", 1.0).unwrap(), &[0.0, 2.0], 3.0);
+    practice_loop.set_notes(session.judges_between(practice_loop.start(), practice_loop.end()));
+
+    session.press(Key::P1_KEY1, 1.0);
+    session.update(2.01);
+    assert!(practice_loop.is_over(2.01));
+    session.miss_before(practice_loop.end());
+    practice_loop.finish_loop(session.result());
+
+    let result = &practice_loop.results()[0];
+    assert_eq!(2, result.total_notes);
+    assert_eq!(1, result.count(Judge::PGREAT));
+    assert_eq!(1, result.count(Judge::MISSED));
+    // the note on the bar line belongs to the next loop
+    assert_eq!(1, session.judged_count(Key::P1_KEY1));
+}
@@ -0,0 +1,154 @@
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io::{self, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use bms_loader::Key;
+use lane_option::LaneOption;
+use hispeed::{HiSpeed, HiSpeedMode, BaseBpm};
+use lane_cover::LaneCover;
+use user_data;
+
+type Time = f64;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ReplayEventType {
+    KeyDown(Key),
+    KeyUp(Key),
+    ChangeSpeed(f64), // new HiSpeed::value
+    ChangeCover(LaneCover),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ReplayEvent {
+    pub timing: Time, // relative to the start of the play, same clock as judgements
+    pub event_type: ReplayEventType,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Replay {
+    pub chart_hash: String,
+    pub hispeed: HiSpeed,
+    pub lane_cover: LaneCover,
+    pub autoplay: Vec<Key>,
+    pub lane_option: LaneOption,
+    pub seed: u64,
+    pub events: Vec<ReplayEvent>,
+}
+
+impl Replay {
+    pub fn new(chart_hash: &str, hispeed: HiSpeed, lane_cover: LaneCover) -> Replay {
+        Replay { chart_hash: chart_hash.to_string(), hispeed: hispeed, lane_cover: lane_cover, autoplay: vec![], lane_option: LaneOption::OFF, seed: 0, events: vec![] }
+    }
+
+    pub fn push(&mut self, timing: Time, event_type: ReplayEventType) {
+        self.events.push(ReplayEvent { timing: timing, event_type: event_type });
+    }
+
+    pub fn default_path(&self, played_at: i64) -> PathBuf {
+        let dir = user_data::data_dir().join("replays");
+        if let Err(e) = fs::create_dir_all(&dir) {
+            println!("failed to create replay directory: {}", e);
+        }
+        dir.join(format!("{}_{}.rpl", self.chart_hash, played_at))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        file.write_all(self.to_string().as_bytes())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Replay> {
+        let mut contents = String::new();
+        File::open(path)?.read_to_string(&mut contents)?;
+        Replay::parse(&contents).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    fn to_string(&self) -> String {
+        let mut s = String::new();
+        s.push_str(&format!("#HASH {}\n", self.chart_hash));
+        s.push_str(&format!("#HISPEED {} {} {:?}\n", self.hispeed.mode.name(), self.hispeed.base.name(), self.hispeed.value));
+        s.push_str(&format!("#COVER {:?} {:?} {:?}\n", self.lane_cover.sudden, self.lane_cover.hidden, self.lane_cover.lift));
+        s.push_str(&format!("#OPTION {} {}\n", self.lane_option.name(), self.seed));
+        if !self.autoplay.is_empty() {
+            let lanes: Vec<String> = self.autoplay.iter().map(|key| format!("{:?}", key)).collect();
+            s.push_str(&format!("#AUTOPLAY {}\n", lanes.join(",")));
+        }
+        for event in &self.events {
+            // {:?} on f64 keeps every digit, so timings survive the round trip exactly
+            let line = match event.event_type {
+                ReplayEventType::KeyDown(key) => format!("{:?} DOWN {:?}\n", event.timing, key),
+                ReplayEventType::KeyUp(key) => format!("{:?} UP {:?}\n", event.timing, key),
+                ReplayEventType::ChangeSpeed(speed) => format!("{:?} SPEED {:?}\n", event.timing, speed),
+                ReplayEventType::ChangeCover(cover) => format!("{:?} COVER {:?} {:?} {:?}\n", event.timing, cover.sudden, cover.hidden, cover.lift),
+            };
+            s.push_str(&line);
+        }
+        s
+    }
+
+    fn parse(contents: &str) -> Result<Replay, String> {
+        let mut replay = Replay::new("", HiSpeed::normal(1.0), LaneCover::none());
+        for (line_number, line) in contents.lines().enumerate() {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let error = || format!("line {}: broken replay entry: {}", line_number + 1, line);
+            match tokens.len() {
+                0 => continue,
+                2 if tokens[0] == "#HASH" => replay.chart_hash = tokens[1].to_string(),
+                2 if tokens[0] == "#SPEED" => replay.hispeed = HiSpeed::normal(tokens[1].parse().map_err(|_| error())?),
+                4 if tokens[0] == "#HISPEED" => {
+                    let mode = HiSpeedMode::from_name(tokens[1]).ok_or_else(&error)?;
+                    let base = BaseBpm::from_name(tokens[2]).ok_or_else(&error)?;
+                    replay.hispeed = HiSpeed { mode: mode, base: base, value: tokens[3].parse().map_err(|_| error())? };
+                }
+                4 if tokens[0] == "#COVER" => {
+                    replay.lane_cover = parse_cover(&tokens[1..]).ok_or_else(&error)?;
+                }
+                2 if tokens[0] == "#AUTOPLAY" => {
+                    for name in tokens[1].split(',') {
+                        replay.autoplay.push(Key::from_name(name).ok_or_else(&error)?);
+                    }
+                }
+                3 if tokens[0] == "#OPTION" => {
+                    replay.lane_option = LaneOption::from_name(tokens[1]).ok_or_else(&error)?;
+                    replay.seed = tokens[2].parse().map_err(|_| error())?;
+                }
+                3 => {
+                    let timing: Time = tokens[0].parse().map_err(|_| error())?;
+                    let event_type = match tokens[1] {
+                        "DOWN" => ReplayEventType::KeyDown(Key::from_name(tokens[2]).ok_or_else(&error)?),
+                        "UP" => ReplayEventType::KeyUp(Key::from_name(tokens[2]).ok_or_else(&error)?),
+                        "SPEED" => ReplayEventType::ChangeSpeed(tokens[2].parse().map_err(|_| error())?),
+                        _ => return Err(error()),
+                    };
+                    replay.push(timing, event_type);
+                }
+                5 if tokens[1] == "COVER" => {
+                    let timing: Time = tokens[0].parse().map_err(|_| error())?;
+                    replay.push(timing, ReplayEventType::ChangeCover(parse_cover(&tokens[2..]).ok_or_else(&error)?));
+                }
+                _ => return Err(error()),
+            }
+        }
+        Ok(replay)
+    }
+}
+
+fn parse_cover(tokens: &[&str]) -> Option<LaneCover> {
+    Some(LaneCover { sudden: tokens[0].parse().ok()?, hidden: tokens[1].parse().ok()?, lift: tokens[2].parse().ok()? })
+}
+
+#[test]
+fn replay_round_trip_test() {
+    let mut replay = Replay::new("0123abcd", HiSpeed::green(HiSpeedMode::FLOATING, BaseBpm::MAX, 310.0), LaneCover { sudden: 120.0, hidden: 0.0, lift: 50.0 });
+    replay.autoplay = vec![Key::P1_SCRATCH];
+    replay.lane_option = LaneOption::S_RANDOM;
+    replay.seed = 18446744073709551557;
+    replay.push(0.1 + 0.2, ReplayEventType::KeyDown(Key::P1_SCRATCH));
+    replay.push(0.4, ReplayEventType::ChangeSpeed(1.6));
+    replay.push(0.5, ReplayEventType::ChangeCover(LaneCover { sudden: 130.0, hidden: 0.0, lift: 50.0 }));
+    replay.push(1.0 / 3.0, ReplayEventType::KeyUp(Key::P1_KEY7));
+
+    assert_eq!(Ok(replay.clone()), Replay::parse(&replay.to_string()));
+    assert!(Replay::parse("0.5 DOWN NOT_A_KEY").is_err());
+}
@@ -0,0 +1,149 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{self, SeekFrom};
+use std::path::Path;
+
+type Time = f64;
+
+// the last ogg page is normally a few KB, this leaves plenty of room
+const OGG_TAIL_SIZE: u64 = 65536;
+
+// playing time of a WAV or OGG Vorbis file, read from its headers without decoding
+pub fn sample_length(path: &Path) -> Option<Time> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return None,
+    };
+    let mut magic = [0u8; 4];
+    if file.read_exact(&mut magic).is_err() || file.seek(SeekFrom::Start(0)).is_err() {
+        return None;
+    }
+    let length = match &magic {
+        b"RIFF" => wav_length(&mut file),
+        b"OggS" => ogg_length(&mut file),
+        _ => return None,
+    };
+    length.unwrap_or_else(|e| {
+        println!("failed to read the length of {}: {}", path.to_str().unwrap_or(""), e);
+        None
+    })
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(le_u32(&bytes))
+}
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24
+}
+
+// RIFF chunks until the data chunk, its size over the byte rate from the fmt chunk
+fn wav_length<R: Read + Seek>(reader: &mut R) -> io::Result<Option<Time>> {
+    let mut header = [0u8; 12];
+    reader.read_exact(&mut header)?;
+    if &header[8..12] != b"WAVE" {
+        return Ok(None);
+    }
+
+    let mut byte_rate = None;
+    loop {
+        let mut id = [0u8; 4];
+        if reader.read_exact(&mut id).is_err() {
+            return Ok(None);
+        }
+        let size = read_u32(reader)?;
+        match &id {
+            b"fmt " => {
+                let mut fmt = vec![0u8; size as usize];
+                reader.read_exact(&mut fmt)?;
+                if fmt.len() >= 12 {
+                    byte_rate = Some(le_u32(&fmt[8..12]));
+                }
+            }
+            b"data" => {
+                return Ok(match byte_rate {
+                    Some(rate) if rate > 0 => Some(size as f64 / rate as f64),
+                    _ => None,
+                });
+            }
+            _ => {
+                // chunks are padded to even sizes
+                reader.seek(SeekFrom::Current((size + size % 2) as i64))?;
+            }
+        }
+    }
+}
+
+fn find_last(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if haystack.len() < needle.len() {
+        return None;
+    }
+    (0..haystack.len() - needle.len() + 1).rev().find(|&i| &haystack[i..i + needle.len()] == needle)
+}
+
+// granule position of the last page over the sample rate from the identification header
+fn ogg_length<R: Read + Seek>(reader: &mut R) -> io::Result<Option<Time>> {
+    let mut head = vec![0u8; 4096];
+    let read = reader.read(&mut head)?;
+    head.truncate(read);
+    let rate = match find_last(&head, b"\x01vorbis") {
+        Some(index) if index + 16 <= head.len() => le_u32(&head[index + 12..index + 16]),
+        _ => return Ok(None),
+    };
+
+    let end = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(end.saturating_sub(OGG_TAIL_SIZE)))?;
+    let mut tail = vec![];
+    reader.read_to_end(&mut tail)?;
+    let granule = match find_last(&tail, b"OggS") {
+        Some(index) if index + 14 <= tail.len() => {
+            le_u32(&tail[index + 6..index + 10]) as u64 | (le_u32(&tail[index + 10..index + 14]) as u64) << 32
+        }
+        _ => return Ok(None),
+    };
+
+    if rate == 0 {
+        Ok(None)
+    } else {
+        Ok(Some(granule as f64 / rate as f64))
+    }
+}
+
+#[test]
+fn wav_length_test() {
+    fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+        bytes.extend_from_slice(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]);
+    }
+    // 16bit stereo 44.1kHz, one odd sized chunk before data
+    let mut wav = b"RIFF\0\0\0\0WAVE".to_vec();
+    wav.extend_from_slice(b"fmt ");
+    push_u32(&mut wav, 16);
+    wav.extend_from_slice(&[1, 0, 2, 0]);
+    push_u32(&mut wav, 44100);
+    push_u32(&mut wav, 44100 * 4);
+    wav.extend_from_slice(&[4, 0, 16, 0]);
+    wav.extend_from_slice(b"LIST");
+    push_u32(&mut wav, 3);
+    wav.extend_from_slice(&[0, 0, 0, 0]);
+    wav.extend_from_slice(b"data");
+    push_u32(&mut wav, 44100 * 4 * 3);
+
+    assert_eq!(Some(3.0), wav_length(&mut io::Cursor::new(wav)).unwrap());
+    assert_eq!(None, wav_length(&mut io::Cursor::new(b"RIFF\0\0\0\0AVI ".to_vec())).unwrap());
+}
+
+#[test]
+fn ogg_length_test() {
+    let mut ogg = b"OggS\0\x02".to_vec();
+    ogg.extend_from_slice(&[0; 22]);
+    ogg.extend_from_slice(b"\x01vorbis\0\0\0\0\x02");
+    ogg.extend_from_slice(&[0x44, 0xac, 0, 0]); // 44100
+    ogg.extend_from_slice(&[0; 100]);
+    ogg.extend_from_slice(b"OggS\0\x04");
+    ogg.extend_from_slice(&[0x88, 0x58, 0x01, 0, 0, 0, 0, 0]); // 88200
+    ogg.extend_from_slice(&[0; 20]);
+
+    assert_eq!(Some(2.0), ogg_length(&mut io::Cursor::new(ogg)).unwrap());
+}
@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io;
+use std::path::{Path, PathBuf};
+use md5;
+
+use game_session::PlayResult;
+use user_data;
+
+const DB_FILE: &'static str = "scores.tsv";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Lamp {
+    NO_PLAY,
+    FAILED,
+    CLEAR,
+    FULL_COMBO,
+    PERFECT,
+    MAX,
+}
+
+impl Lamp {
+    fn name(&self) -> &'static str {
+        match *self {
+            Lamp::NO_PLAY => "NO_PLAY",
+            Lamp::FAILED => "FAILED",
+            Lamp::CLEAR => "CLEAR",
+            Lamp::FULL_COMBO => "FULL_COMBO",
+            Lamp::PERFECT => "PERFECT",
+            Lamp::MAX => "MAX",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Lamp> {
+        match name {
+            "NO_PLAY" => Some(Lamp::NO_PLAY),
+            "FAILED" => Some(Lamp::FAILED),
+            "CLEAR" => Some(Lamp::CLEAR),
+            "FULL_COMBO" => Some(Lamp::FULL_COMBO),
+            "PERFECT" => Some(Lamp::PERFECT),
+            "MAX" => Some(Lamp::MAX),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScoreRecord {
+    pub ex_score: u32,
+    pub lamp: Lamp,
+    pub min_bp: Option<u32>,
+    pub max_combo: u32,
+    pub play_count: u32,
+    pub first_played: i64,
+    pub last_played: i64,
+}
+
+impl ScoreRecord {
+    fn new(now: i64) -> ScoreRecord {
+        ScoreRecord { ex_score: 0, lamp: Lamp::NO_PLAY, min_bp: None, max_combo: 0, play_count: 0, first_played: now, last_played: now }
+    }
+
+    fn to_line(&self, hash: &str) -> String {
+        let min_bp = match self.min_bp {
+            Some(bp) => bp.to_string(),
+            None => "-".to_string(),
+        };
+        format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", hash, self.ex_score, self.lamp.name(), min_bp, self.max_combo, self.play_count, self.first_played, self.last_played)
+    }
+
+    fn from_line(line: &str) -> Option<(String, ScoreRecord)> {
+        let tokens: Vec<&str> = line.split('\t').collect();
+        if tokens.len() != 8 {
+            return None;
+        }
+        let record = ScoreRecord {
+            ex_score: tokens[1].parse().ok()?,
+            lamp: Lamp::from_name(tokens[2])?,
+            min_bp: tokens[3].parse().ok(),
+            max_combo: tokens[4].parse().ok()?,
+            play_count: tokens[5].parse().ok()?,
+            first_played: tokens[6].parse().ok()?,
+            last_played: tokens[7].parse().ok()?,
+        };
+        Some((tokens[0].to_string(), record))
+    }
+}
+
+// md5 of the chart file, same as the hash used by other bms players
+pub fn chart_hash<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    let mut bytes = vec![];
+    File::open(path)?.read_to_end(&mut bytes)?;
+    Ok(format!("{:x}", md5::compute(&bytes)))
+}
+
+pub struct ScoreDb {
+    path: PathBuf,
+    records: HashMap<String, ScoreRecord>,
+}
+
+impl ScoreDb {
+    pub fn open_default() -> ScoreDb {
+        ScoreDb::open(user_data::data_dir().join(DB_FILE))
+    }
+
+    pub fn open(path: PathBuf) -> ScoreDb {
+        let mut records = HashMap::new();
+        let mut contents = String::new();
+        if let Ok(mut file) = File::open(&path) {
+            if let Err(e) = file.read_to_string(&mut contents) {
+                println!("failed to read score db: {}", e);
+            }
+        }
+        for line in contents.lines() {
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+            match ScoreRecord::from_line(line) {
+                Some((hash, record)) => { records.insert(hash, record); },
+                None => println!("skipping broken score db line: {}", line),
+            }
+        }
+        ScoreDb { path: path, records: records }
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&ScoreRecord> {
+        self.records.get(hash)
+    }
+
+    pub fn update(&mut self, hash: &str, result: &PlayResult, now: i64) {
+        let record = self.records.entry(hash.to_string()).or_insert_with(|| ScoreRecord::new(now));
+        let lamp = result.lamp();
+        if lamp > record.lamp {
+            record.lamp = lamp;
+        }
+        if result.ex_score() > record.ex_score {
+            record.ex_score = result.ex_score();
+        }
+        if result.max_combo > record.max_combo {
+            record.max_combo = result.max_combo;
+        }
+        // an aborted play has not seen every note, so its bp means nothing
+        if result.finished {
+            let bp = result.bp();
+            record.min_bp = Some(record.min_bp.map_or(bp, |min| if bp < min { bp } else { min }));
+        }
+        record.play_count += 1;
+        record.last_played = now;
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let mut hashes: Vec<&String> = self.records.keys().collect();
+        hashes.sort();
+
+        let tmp_path = self.path.with_extension("tmp");
+        {
+            let mut file = File::create(&tmp_path)?;
+            writeln!(file, "# hash\tex_score\tlamp\tmin_bp\tmax_combo\tplay_count\tfirst_played\tlast_played")?;
+            for hash in hashes {
+                writeln!(file, "{}", self.records[hash].to_line(hash))?;
+            }
+        }
+        fs::rename(&tmp_path, &self.path)
+    }
+}
+
+#[test]
+fn score_record_line_test() {
+    let record = ScoreRecord { ex_score: 1234, lamp: Lamp::FULL_COMBO, min_bp: Some(3), max_combo: 700, play_count: 5, first_played: 100, last_played: 200 };
+    let line = record.to_line("0123abcd");
+    assert_eq!(Some(("0123abcd".to_string(), record)), ScoreRecord::from_line(&line));
+
+    let unplayed = ScoreRecord::new(100);
+    assert_eq!(Some(("x".to_string(), unplayed.clone())), ScoreRecord::from_line(&unplayed.to_line("x")));
+    assert_eq!(None, ScoreRecord::from_line("broken"));
+}
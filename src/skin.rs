@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use config::Config;
+use game_session::Judge;
+use lane_layout::{LaneColor, PlaySide};
+use lr2_skin;
+
+pub type ImageId = usize;
+
+const DEFAULT_SKIN: &'static str = "resource/skin.cfg";
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Rect {
+    pub x: f64,
+    pub y: f64,
+    pub w: f64,
+    pub h: f64,
+}
+
+impl Rect {
+    pub fn new(x: f64, y: f64, w: f64, h: f64) -> Rect {
+        Rect { x: x, y: y, w: w, h: h }
+    }
+
+    // "x,y,w,h"
+    pub fn parse(value: &str) -> Option<Rect> {
+        let numbers: Vec<f64> = value.split(',').filter_map(|number| number.trim().parse().ok()).collect();
+        if numbers.len() == 4 {
+            Some(Rect::new(numbers[0], numbers[1], numbers[2], numbers[3]))
+        } else {
+            None
+        }
+    }
+
+    pub fn scaled(&self, sx: f64, sy: f64) -> Rect {
+        Rect::new(self.x * sx, self.y * sy, self.w * sx, self.h * sy)
+    }
+
+    // the same rect seen from the other side of a screen this wide
+    pub fn mirrored(&self, screen_width: f64) -> Rect {
+        Rect { x: screen_width - self.x - self.w, ..*self }
+    }
+}
+
+// a picture from one of the skin files, or the part of it given by src
+#[derive(Debug, Clone, PartialEq)]
+pub struct SkinImage {
+    pub file: usize,
+    pub src: Option<Rect>,
+}
+
+// what the play screen looks like, read from a `key = value` file:
+//   resolution = 800,600              the size every element is given in, scaled to the window
+//   font = font/some.ttf              paths are relative to the skin file
+//   image.<name> = file.png [x,y,w,h] optionally a part of the file
+//   note.WHITE = <image>              also BLUE and RED, the same for beam.
+//   judge.PGREAT = <image>            and the other judges
+//   digit.0 = <image>                 to digit.9
+//   background, lane_bg, bar = <image>
+//   element.<name> = x,y,w,h          lanes, judge, bga, progress, and the bpm, score, gauge, latency
+//                                     and practice texts, which use h as the font size
+//   element.<name>.2P, element.<name>.DP   placement on the 2P side or in DP, 2P mirrors the 1P one otherwise
+//   element.<name> = none             leaves the element out
+#[derive(Clone)]
+pub struct Skin {
+    pub resolution: (f64, f64),
+    pub files: Vec<PathBuf>,
+    file_sizes: Vec<(f64, f64)>, // known once the files are loaded
+    pub images: Vec<SkinImage>,
+    pub font: PathBuf,
+    pub background: Option<ImageId>,
+    pub lane_bg: Option<ImageId>,
+    pub bar: Option<ImageId>,
+    pub notes: HashMap<LaneColor, ImageId>,
+    pub beams: HashMap<LaneColor, ImageId>,
+    pub judges: HashMap<Judge, ImageId>,
+    pub digits: Vec<ImageId>,
+    elements: HashMap<String, Option<Rect>>,
+}
+
+fn judge_name(judge: Judge) -> String {
+    format!("{:?}", judge)
+}
+
+fn color_name(color: LaneColor) -> String {
+    format!("{:?}", color)
+}
+
+impl Skin {
+    // the skin in settings.cfg, the bundled one otherwise
+    pub fn load_default(settings: &Config) -> Skin {
+        Skin::load(Path::new(settings.get("skin").unwrap_or(DEFAULT_SKIN)))
+    }
+
+    // .lr2skin files are read as LR2 skins
+    pub fn load(path: &Path) -> Skin {
+        if path.extension().map_or(false, |extension| extension.eq_ignore_ascii_case("lr2skin")) {
+            return lr2_skin::load(path);
+        }
+        let config = Config::open_path(path.to_path_buf());
+        Skin::from_config(&config, path.parent().unwrap_or(Path::new(".")))
+    }
+
+    pub fn from_config(config: &Config, dir: &Path) -> Skin {
+        let resolution = config.get("resolution").and_then(|value| {
+            let size: Vec<f64> = value.split(',').filter_map(|number| number.trim().parse().ok()).collect();
+            if size.len() == 2 { Some((size[0], size[1])) } else { None }
+        }).unwrap_or((800.0, 600.0));
+
+        let font = dir.join(config.get("font").unwrap_or("font/rounded-mplus-1p-regular.ttf"));
+        let mut skin = Skin::new(resolution, font);
+        let mut image_ids = HashMap::new();
+        for key in config.keys_with_prefix("image.") {
+            let value = config.get(key).unwrap();
+            let mut parts = value.splitn(2, ' ');
+            let path = dir.join(parts.next().unwrap_or("").trim());
+            let src = parts.next().and_then(Rect::parse);
+            image_ids.insert(key["image.".len()..].to_string(), skin.add_image(path, src));
+        }
+
+        let image = |key: &str| -> Option<ImageId> {
+            config.get(key).and_then(|name| {
+                let id = image_ids.get(name).map(|id| *id);
+                if id.is_none() {
+                    println!("skin: {} uses an unknown image {}", key, name);
+                }
+                id
+            })
+        };
+
+        for color in &[LaneColor::WHITE, LaneColor::BLUE, LaneColor::RED] {
+            if let Some(id) = image(&format!("note.{}", color_name(*color))) {
+                skin.notes.insert(*color, id);
+            }
+            if let Some(id) = image(&format!("beam.{}", color_name(*color))) {
+                skin.beams.insert(*color, id);
+            }
+        }
+        for judge in &[Judge::PGREAT, Judge::GREAT, Judge::GOOD, Judge::BAD, Judge::POOR, Judge::MISSED] {
+            if let Some(id) = image(&format!("judge.{}", judge_name(*judge))) {
+                skin.judges.insert(*judge, id);
+            }
+        }
+        skin.digits = (0..10).filter_map(|digit| image(&format!("digit.{}", digit))).collect();
+        skin.background = image("background");
+        skin.lane_bg = image("lane_bg");
+        skin.bar = image("bar");
+
+        for key in config.keys_with_prefix("element.") {
+            let value = config.get(key).unwrap();
+            let rect = Rect::parse(value);
+            if rect.is_none() && value != "none" {
+                println!("skin: {} is not x,y,w,h", key);
+                continue;
+            }
+            skin.set_element(&key["element.".len()..], rect);
+        }
+        skin
+    }
+
+    // nothing drawn but the lanes, for filling in from a skin file
+    pub fn new(resolution: (f64, f64), font: PathBuf) -> Skin {
+        Skin {
+            resolution: resolution,
+            files: vec![],
+            file_sizes: vec![],
+            images: vec![],
+            font: font,
+            background: None,
+            lane_bg: None,
+            bar: None,
+            notes: HashMap::new(),
+            beams: HashMap::new(),
+            judges: HashMap::new(),
+            digits: vec![],
+            elements: HashMap::new(),
+        }
+    }
+
+    // images from the same file share its texture
+    pub fn add_image(&mut self, path: PathBuf, src: Option<Rect>) -> ImageId {
+        let file = match self.files.iter().position(|file| *file == path) {
+            Some(file) => file,
+            None => {
+                self.files.push(path);
+                self.file_sizes.push((0.0, 0.0));
+                self.files.len() - 1
+            }
+        };
+        self.images.push(SkinImage { file: file, src: src });
+        self.images.len() - 1
+    }
+
+    pub fn set_file_size(&mut self, file: usize, width: f64, height: f64) {
+        self.file_sizes[file] = (width, height);
+    }
+
+    // size of an image in its file
+    pub fn image_size(&self, id: ImageId) -> (f64, f64) {
+        let image = &self.images[id];
+        image.src.map_or(self.file_sizes[image.file], |src| (src.w, src.h))
+    }
+
+    // how wide an image is when drawn this high
+    pub fn image_width(&self, id: ImageId, height: f64) -> f64 {
+        let (w, h) = self.image_size(id);
+        if h > 0.0 { w * height / h } else { 0.0 }
+    }
+
+    // None leaves the element out
+    pub fn set_element(&mut self, name: &str, rect: Option<Rect>) {
+        self.elements.insert(name.to_string(), rect);
+    }
+
+    // where an element goes in skin coordinates, None if the skin leaves it out
+    pub fn element(&self, name: &str, side: PlaySide, double: bool) -> Option<Rect> {
+        if double {
+            if let Some(rect) = self.elements.get(&format!("{}.DP", name)) {
+                return *rect;
+            }
+        }
+        let rect = self.elements.get(name).and_then(|rect| *rect);
+        match side {
+            PlaySide::P1 => rect,
+            PlaySide::P2 => match self.elements.get(&format!("{}.2P", name)) {
+                Some(rect) => *rect,
+                None => rect.map(|rect| rect.mirrored(self.resolution.0)),
+            },
+        }
+    }
+
+    // skin coordinates to a window this big
+    pub fn scale(&self, width: f64, height: f64) -> (f64, f64) {
+        (width / self.resolution.0, height / self.resolution.1)
+    }
+}
+
+#[test]
+fn skin_test() {
+    let config = Config::from_contents("
+        resolution = 400,300
+        image.notes = notes.png 0,0,40,10
+        image.notes_blue = notes.png 0,10,40,10
+        image.zero = digits.png
+        note.WHITE = notes
+        note.BLUE = notes_blue
+        note.RED = missing
+        digit.0 = zero
+        element.lanes = 10,0,200,300
+        element.gauge = 220,280,100,10
+        element.gauge.2P = 20,280,100,10
+        element.lanes.DP = 0,0,400,300
+        element.gauge.DP = none
+    ");
+    let mut skin = Skin::from_config(&config, Path::new("skins/test"));
+
+    assert_eq!((400.0, 300.0), skin.resolution);
+    assert_eq!(vec![PathBuf::from("skins/test/notes.png"), PathBuf::from("skins/test/digits.png")], skin.files);
+    let blue = &skin.images[skin.notes[&LaneColor::BLUE]];
+    assert_eq!(SkinImage { file: 0, src: Some(Rect::new(0.0, 10.0, 40.0, 10.0)) }, *blue);
+    assert!(!skin.notes.contains_key(&LaneColor::RED));
+    assert_eq!(1, skin.digits.len());
+    skin.set_file_size(1, 24.0, 30.0);
+    assert_eq!(12.0, skin.image_width(skin.digits[0], 15.0));
+    assert_eq!(40.0, skin.image_width(skin.notes[&LaneColor::WHITE], 10.0));
+
+    assert_eq!(Some(Rect::new(10.0, 0.0, 200.0, 300.0)), skin.element("lanes", PlaySide::P1, false));
+    assert_eq!(Some(Rect::new(190.0, 0.0, 200.0, 300.0)), skin.element("lanes", PlaySide::P2, false));
+    assert_eq!(Some(Rect::new(0.0, 0.0, 400.0, 300.0)), skin.element("lanes", PlaySide::P2, true));
+    assert_eq!(Some(Rect::new(20.0, 280.0, 100.0, 10.0)), skin.element("gauge", PlaySide::P2, false));
+    assert_eq!(None, skin.element("gauge", PlaySide::P1, true));
+    assert_eq!(None, skin.element("bpm", PlaySide::P1, false));
+    assert_eq!((2.0, 2.0), skin.scale(800.0, 600.0));
+}
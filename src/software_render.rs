@@ -0,0 +1,163 @@
+use image::{self, ImageBuffer, RgbaImage};
+
+use skin::{Rect, Skin};
+use frame::{DrawCommand, Frame, TextureId};
+
+// RGBA pixels to draw frames into without a window or GPU
+pub struct Canvas {
+    pub width: u32,
+    pub height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    // opaque black, like the window is cleared to
+    pub fn new(width: u32, height: u32) -> Canvas {
+        let mut pixels = vec![0; (width * height * 4) as usize];
+        for pixel in pixels.chunks_mut(4) {
+            pixel[3] = 255;
+        }
+        Canvas { width: width, height: height, pixels: pixels }
+    }
+
+    pub fn from_image(image: RgbaImage) -> Canvas {
+        let (width, height) = (image.width(), image.height());
+        Canvas { width: width, height: height, pixels: image.into_raw() }
+    }
+
+    pub fn into_image(self) -> RgbaImage {
+        ImageBuffer::from_raw(self.width, self.height, self.pixels).unwrap()
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = ((y * self.width + x) * 4) as usize;
+        [self.pixels[i], self.pixels[i + 1], self.pixels[i + 2], self.pixels[i + 3]]
+    }
+
+    // source over, color in 0.0 - 1.0
+    fn blend(&mut self, x: u32, y: u32, color: [f32; 4]) {
+        let i = ((y * self.width + x) * 4) as usize;
+        let a = color[3].max(0.0).min(1.0);
+        for c in 0..3 {
+            let dst = self.pixels[i + c] as f32 / 255.0;
+            self.pixels[i + c] = ((color[c] * a + dst * (1.0 - a)) * 255.0).round() as u8;
+        }
+        let dst_a = self.pixels[i + 3] as f32 / 255.0;
+        self.pixels[i + 3] = ((a + dst_a * (1.0 - a)) * 255.0).round() as u8;
+    }
+
+    // the pixels whose centers are inside rect, as ranges clipped to the canvas
+    fn covered(&self, rect: &Rect) -> (u32, u32, u32, u32) {
+        let (left, right) = (rect.x.min(rect.x + rect.w), rect.x.max(rect.x + rect.w));
+        let (top, bottom) = (rect.y.min(rect.y + rect.h), rect.y.max(rect.y + rect.h));
+        let clip = |v: f64, max: u32| (v - 0.5).ceil().max(0.0).min(max as f64) as u32;
+        (clip(left, self.width), clip(right, self.width), clip(top, self.height), clip(bottom, self.height))
+    }
+
+    pub fn fill(&mut self, rect: Rect, color: [f32; 4]) {
+        let (x0, x1, y0, y1) = self.covered(&rect);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                self.blend(x, y, color);
+            }
+        }
+    }
+
+    // nearest neighbor, which is enough for sprites drawn near their own size
+    pub fn draw_image(&mut self, src: &Canvas, src_rect: Rect, dest: Rect, alpha: f32) {
+        if src.width == 0 || src.height == 0 || dest.w == 0.0 || dest.h == 0.0 {
+            return;
+        }
+        let (x0, x1, y0, y1) = self.covered(&dest);
+        for y in y0..y1 {
+            let v = (y as f64 + 0.5 - dest.y) / dest.h;
+            let sy = (src_rect.y + v * src_rect.h).floor().max(0.0).min(src.height as f64 - 1.0) as u32;
+            for x in x0..x1 {
+                let u = (x as f64 + 0.5 - dest.x) / dest.w;
+                let sx = (src_rect.x + u * src_rect.w).floor().max(0.0).min(src.width as f64 - 1.0) as u32;
+                let p = src.pixel(sx, sy);
+                let color = [p[0] as f32 / 255.0, p[1] as f32 / 255.0, p[2] as f32 / 255.0, p[3] as f32 / 255.0 * alpha];
+                self.blend(x, y, color);
+            }
+        }
+    }
+}
+
+// draws frames the way PresetTextures does, with the images decoded in memory
+pub struct SoftwareRenderer {
+    textures: Vec<Option<Canvas>>, // in the order of skin.files
+    bga: Vec<Canvas>,
+}
+
+impl SoftwareRenderer {
+    // skin files that fail to load are left out of the picture rather than stopping the render
+    pub fn new(skin: &mut Skin, bga: Vec<RgbaImage>) -> SoftwareRenderer {
+        let textures: Vec<Option<Canvas>> = skin.files.iter().map(|file| match image::open(file) {
+            Ok(image) => Some(Canvas::from_image(image.to_rgba())),
+            Err(e) => {
+                println!("failed to load skin image {}: {}", file.to_str().unwrap_or(""), e);
+                None
+            }
+        }).collect();
+        for (file, texture) in textures.iter().enumerate() {
+            if let Some(ref texture) = *texture {
+                skin.set_file_size(file, texture.width as f64, texture.height as f64);
+            }
+        }
+        SoftwareRenderer { textures: textures, bga: bga.into_iter().map(Canvas::from_image).collect() }
+    }
+
+    // text needs a font rasterizer, so it is not drawn
+    pub fn render(&self, frame: &Frame, skin: &Skin) -> Canvas {
+        let mut canvas = Canvas::new(frame.width.round() as u32, frame.height.round() as u32);
+        for command in &frame.commands {
+            match *command {
+                DrawCommand::Image { texture, rect, alpha } => {
+                    let (texture, src) = match texture {
+                        TextureId::Skin(id) => match self.textures[skin.images[id].file] {
+                            Some(ref texture) => (texture, skin.images[id].src),
+                            None => continue,
+                        },
+                        TextureId::Bga(id) => match self.bga.get(id) {
+                            Some(texture) => (texture, None),
+                            None => continue,
+                        },
+                    };
+                    let src = src.unwrap_or(Rect::new(0.0, 0.0, texture.width as f64, texture.height as f64));
+                    canvas.draw_image(texture, src, rect, alpha);
+                }
+                DrawCommand::Fill { color, rect } => canvas.fill(rect, color),
+                DrawCommand::Text { .. } => (),
+            }
+        }
+        canvas
+    }
+}
+
+#[test]
+fn canvas_test() {
+    let mut canvas = Canvas::new(4, 4);
+    canvas.fill(Rect::new(1.0, 1.0, 2.0, 2.0), [1.0, 0.0, 0.0, 1.0]);
+    assert_eq!([0, 0, 0, 255], canvas.pixel(0, 0));
+    assert_eq!([255, 0, 0, 255], canvas.pixel(1, 1));
+    assert_eq!([255, 0, 0, 255], canvas.pixel(2, 2));
+    assert_eq!([0, 0, 0, 255], canvas.pixel(3, 3));
+
+    // half transparent over red
+    canvas.fill(Rect::new(2.0, 2.0, 10.0, 10.0), [0.0, 0.0, 1.0, 0.5]);
+    assert_eq!([128, 0, 128, 255], canvas.pixel(2, 2));
+    assert_eq!([0, 0, 128, 255], canvas.pixel(3, 3));
+
+    // a 2x1 source of white and green, stretched over the bottom row and off the edge
+    let src = Canvas { width: 2, height: 1, pixels: vec![255, 255, 255, 255, 0, 255, 0, 255] };
+    canvas.draw_image(&src, Rect::new(0.0, 0.0, 2.0, 1.0), Rect::new(0.0, 3.0, 4.0, 4.0), 1.0);
+    assert_eq!([255, 255, 255, 255], canvas.pixel(1, 3));
+    assert_eq!([0, 255, 0, 255], canvas.pixel(2, 3));
+    assert_eq!([255, 0, 0, 255], canvas.pixel(1, 2));
+
+    // only the green half, faded
+    canvas.draw_image(&src, Rect::new(1.0, 0.0, 1.0, 1.0), Rect::new(0.0, 0.0, 1.0, 1.0), 0.0);
+    assert_eq!([0, 0, 0, 255], canvas.pixel(0, 0));
+    canvas.draw_image(&src, Rect::new(1.0, 0.0, 1.0, 1.0), Rect::new(0.0, 0.0, 1.0, 1.0), 1.0);
+    assert_eq!([0, 255, 0, 255], canvas.pixel(0, 0));
+}
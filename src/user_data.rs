@@ -0,0 +1,24 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+const APP_DIR: &'static str = "rust-bms";
+
+// per-user directory for scores, replays and settings
+pub fn data_dir() -> PathBuf {
+    let base = if let Some(dir) = env::var_os("XDG_DATA_HOME") {
+        PathBuf::from(dir)
+    } else if let Some(dir) = env::var_os("APPDATA") {
+        PathBuf::from(dir)
+    } else if let Some(home) = env::var_os("HOME") {
+        PathBuf::from(home).join(".local").join("share")
+    } else {
+        env::current_dir().unwrap()
+    };
+
+    let dir = base.join(APP_DIR);
+    if let Err(e) = fs::create_dir_all(&dir) {
+        println!("failed to create data directory {}: {}", dir.to_str().unwrap_or(""), e);
+    }
+    dir
+}